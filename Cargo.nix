@@ -728,6 +728,120 @@ rec {
         };
         resolvedDefaultFeatures = [ "arrow-format" "compute_aggregate" "compute_arithmetics" "compute_boolean" "compute_boolean_kleene" "compute_cast" "compute_comparison" "compute_concatenate" "compute_filter" "compute_if_then_else" "compute_like" "compute_substring" "compute_take" "compute_temporal" "futures" "io_ipc" "io_ipc_compression" "io_ipc_write_async" "lexical-core" "lz4" "multiversion" "regex" "regex-syntax" "strength_reduce" "zstd" ];
       };
+      "asn1-rs" = rec {
+        crateName = "asn1-rs";
+        version = "0.5.2";
+        edition = "2018";
+        sha256 = "1w7zq0392qs7kkv0nzw50bfqvq7q9zxv48fsp3sxyl83mzfxavvz";
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "asn1-rs-derive";
+            packageId = "asn1-rs-derive";
+          }
+          {
+            name = "asn1-rs-impl";
+            packageId = "asn1-rs-impl";
+          }
+          {
+            name = "displaydoc";
+            packageId = "displaydoc";
+          }
+          {
+            name = "nom";
+            packageId = "nom";
+            usesDefaultFeatures = false;
+            features = [ "std" ];
+          }
+          {
+            name = "num-traits";
+            packageId = "num-traits";
+          }
+          {
+            name = "rusticata-macros";
+            packageId = "rusticata-macros";
+          }
+          {
+            name = "thiserror";
+            packageId = "thiserror";
+          }
+          {
+            name = "time";
+            packageId = "time 0.3.22";
+            optional = true;
+            features = [ "macros" "parsing" "formatting" ];
+          }
+        ];
+        features = {
+          "bigint" = [ "num-bigint" ];
+          "bits" = [ "bitvec" ];
+          "bitvec" = [ "dep:bitvec" ];
+          "cookie-factory" = [ "dep:cookie-factory" ];
+          "datetime" = [ "time" ];
+          "default" = [ "std" ];
+          "num-bigint" = [ "dep:num-bigint" ];
+          "serialize" = [ "cookie-factory" ];
+          "time" = [ "dep:time" ];
+        };
+        resolvedDefaultFeatures = [ "datetime" "default" "std" "time" ];
+      };
+      "asn1-rs-derive" = rec {
+        crateName = "asn1-rs-derive";
+        version = "0.4.0";
+        edition = "2018";
+        sha256 = "0v7fgmnzk7jjxv51grhwzcx5bf167nlqwk3vcmq7xblf5s4karbj";
+        procMacro = true;
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "proc-macro2";
+            packageId = "proc-macro2";
+          }
+          {
+            name = "quote";
+            packageId = "quote";
+          }
+          {
+            name = "syn";
+            packageId = "syn 1.0.109";
+            features = [ "full" ];
+          }
+          {
+            name = "synstructure";
+            packageId = "synstructure";
+          }
+        ];
+
+      };
+      "asn1-rs-impl" = rec {
+        crateName = "asn1-rs-impl";
+        version = "0.1.0";
+        edition = "2018";
+        sha256 = "1va27bn7qxqp4wanzjlkagnynv6jnrhnwmcky2ahzb1r405p6xr7";
+        procMacro = true;
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "proc-macro2";
+            packageId = "proc-macro2";
+          }
+          {
+            name = "quote";
+            packageId = "quote";
+          }
+          {
+            name = "syn";
+            packageId = "syn 1.0.109";
+          }
+        ];
+
+      };
       "async-channel" = rec {
         crateName = "async-channel";
         version = "1.8.0";
@@ -1375,6 +1489,48 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" "std" ];
       };
+      "bcrypt" = rec {
+        crateName = "bcrypt";
+        version = "0.14.0";
+        edition = "2021";
+        sha256 = "0ah60a63r01wb6bk1sbnahka12hh3n7lxzn53s6gfci2qyz8iwlx";
+        authors = [
+          "Vincent Prouillet <hello@prouilletvincent.com>"
+        ];
+        dependencies = [
+          {
+            name = "base64";
+            packageId = "base64 0.21.2";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "blowfish";
+            packageId = "blowfish";
+            features = [ "bcrypt" ];
+          }
+          {
+            name = "getrandom";
+            packageId = "getrandom";
+          }
+          {
+            name = "subtle";
+            packageId = "subtle";
+          }
+          {
+            name = "zeroize";
+            packageId = "zeroize";
+            optional = true;
+          }
+        ];
+        features = {
+          "alloc" = [ "base64/alloc" ];
+          "default" = [ "std" "zeroize" ];
+          "js" = [ "getrandom/js" ];
+          "std" = [ "getrandom/std" "base64/std" ];
+          "zeroize" = [ "dep:zeroize" ];
+        };
+        resolvedDefaultFeatures = [ "default" "std" "zeroize" ];
+      };
       "bencher" = rec {
         crateName = "bencher";
         version = "0.1.0";
@@ -1580,6 +1736,30 @@ rec {
         ];
 
       };
+      "blowfish" = rec {
+        crateName = "blowfish";
+        version = "0.9.1";
+        edition = "2021";
+        sha256 = "1mw7bvj3bg5w8vh9xw9xawqh7ixk2xwsxkj34ph96b9b1z6y44p4";
+        authors = [
+          "RustCrypto Developers"
+        ];
+        dependencies = [
+          {
+            name = "byteorder";
+            packageId = "byteorder";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "cipher";
+            packageId = "cipher";
+          }
+        ];
+        features = {
+          "zeroize" = [ "cipher/zeroize" ];
+        };
+        resolvedDefaultFeatures = [ "bcrypt" ];
+      };
       "bollard" = rec {
         crateName = "bollard";
         version = "0.12.0";
@@ -1888,7 +2068,7 @@ rec {
           }
           {
             name = "time";
-            packageId = "time";
+            packageId = "time 0.1.45";
             optional = true;
           }
           {
@@ -1926,6 +2106,33 @@ rec {
         };
         resolvedDefaultFeatures = [ "clock" "default" "iana-time-zone" "js-sys" "oldtime" "serde" "std" "time" "wasm-bindgen" "wasmbind" "winapi" ];
       };
+      "cipher" = rec {
+        crateName = "cipher";
+        version = "0.4.4";
+        edition = "2021";
+        sha256 = "1b9x9agg67xq5nq879z66ni4l08m6m3hqcshk37d4is4ysd3ngvp";
+        authors = [
+          "RustCrypto Developers"
+        ];
+        dependencies = [
+          {
+            name = "crypto-common";
+            packageId = "crypto-common";
+          }
+          {
+            name = "inout";
+            packageId = "inout";
+          }
+        ];
+        features = {
+          "blobby" = [ "dep:blobby" ];
+          "block-padding" = [ "inout/block-padding" ];
+          "dev" = [ "blobby" ];
+          "rand_core" = [ "crypto-common/rand_core" ];
+          "std" = [ "alloc" "crypto-common/std" "inout/std" ];
+          "zeroize" = [ "dep:zeroize" ];
+        };
+      };
       "clap 3.2.25" = rec {
         crateName = "clap";
         version = "3.2.25";
@@ -2764,6 +2971,65 @@ rec {
         ];
 
       };
+      "data-encoding" = rec {
+        crateName = "data-encoding";
+        version = "2.4.0";
+        edition = "2018";
+        sha256 = "023k3dk8422jgbj7k72g63x51h1mhv91dhw1j4h205vzh6fnrrn2";
+        authors = [
+          "Julien Cretin <git@ia0.eu>"
+        ];
+        features = {
+          "default" = [ "std" ];
+          "std" = [ "alloc" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "default" "std" ];
+      };
+      "der-parser" = rec {
+        crateName = "der-parser";
+        version = "8.2.0";
+        edition = "2018";
+        sha256 = "07mnz9y395zyxwj7nam2dbzkqdngfraxp2i7y2714dxmpbxpdmnv";
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "asn1-rs";
+            packageId = "asn1-rs";
+          }
+          {
+            name = "displaydoc";
+            packageId = "displaydoc";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "nom";
+            packageId = "nom";
+          }
+          {
+            name = "num-bigint";
+            packageId = "num-bigint";
+            optional = true;
+          }
+          {
+            name = "num-traits";
+            packageId = "num-traits";
+          }
+          {
+            name = "rusticata-macros";
+            packageId = "rusticata-macros";
+          }
+        ];
+        features = {
+          "bigint" = [ "num-bigint" ];
+          "cookie-factory" = [ "dep:cookie-factory" ];
+          "default" = [ "std" ];
+          "num-bigint" = [ "dep:num-bigint" ];
+          "serialize" = [ "std" "cookie-factory" ];
+        };
+        resolvedDefaultFeatures = [ "bigint" "default" "num-bigint" "std" ];
+      };
       "diff" = rec {
         crateName = "diff";
         version = "0.1.13";
@@ -2860,6 +3126,10 @@ rec {
             name = "axum";
             packageId = "axum";
           }
+          {
+            name = "bcrypt";
+            packageId = "bcrypt";
+          }
           {
             name = "chrono";
             packageId = "chrono";
@@ -2889,6 +3159,10 @@ rec {
             name = "prometheus-client";
             packageId = "prometheus-client";
           }
+          {
+            name = "prost";
+            packageId = "prost";
+          }
           {
             name = "rand";
             packageId = "rand 0.8.5";
@@ -2902,6 +3176,10 @@ rec {
             name = "serde_json";
             packageId = "serde_json";
           }
+          {
+            name = "sha2";
+            packageId = "sha2";
+          }
           {
             name = "sled";
             packageId = "sled";
@@ -2923,12 +3201,12 @@ rec {
           {
             name = "tonic";
             packageId = "tonic";
-            features = [ "tls" ];
+            features = [ "tls" "gzip" ];
           }
           {
             name = "tower";
             packageId = "tower";
-            features = [ "load-shed" ];
+            features = [ "load-shed" "util" ];
           }
           {
             name = "tracing";
@@ -2943,6 +3221,10 @@ rec {
             name = "url";
             packageId = "url";
           }
+          {
+            name = "x509-parser";
+            packageId = "x509-parser";
+          }
         ];
         devDependencies = [
           {
@@ -3038,6 +3320,10 @@ rec {
             name = "chrono";
             packageId = "chrono";
           }
+          {
+            name = "crc32fast";
+            packageId = "crc32fast";
+          }
           {
             name = "futures";
             packageId = "futures";
@@ -3103,6 +3389,34 @@ rec {
         ];
 
       };
+      "displaydoc" = rec {
+        crateName = "displaydoc";
+        version = "0.2.4";
+        edition = "2018";
+        sha256 = "0p8pyg10csc782qlwx3znr6qx46ni96m1qh597kmyrf6s3s8axa8";
+        procMacro = true;
+        authors = [
+          "Jane Lusby <jlusby@yaah.dev>"
+        ];
+        dependencies = [
+          {
+            name = "proc-macro2";
+            packageId = "proc-macro2";
+          }
+          {
+            name = "quote";
+            packageId = "quote";
+          }
+          {
+            name = "syn";
+            packageId = "syn 2.0.18";
+          }
+        ];
+        features = {
+          "default" = [ "std" ];
+        };
+        resolvedDefaultFeatures = [ "default" "std" ];
+      };
       "dtoa" = rec {
         crateName = "dtoa";
         version = "1.0.6";
@@ -4971,6 +5285,25 @@ rec {
         };
         resolvedDefaultFeatures = [ "std" ];
       };
+      "inout" = rec {
+        crateName = "inout";
+        version = "0.1.4";
+        edition = "2021";
+        sha256 = "008xfl1jn9rxsq19phnhbimccf4p64880jmnpg59wqi07kk117w7";
+        authors = [
+          "RustCrypto Developers"
+        ];
+        dependencies = [
+          {
+            name = "generic-array";
+            packageId = "generic-array";
+          }
+        ];
+        features = {
+          "block-padding" = [ "dep:block-padding" ];
+          "std" = [ "block-padding/std" ];
+        };
+      };
       "insta" = rec {
         crateName = "insta";
         version = "1.29.0";
@@ -5804,6 +6137,10 @@ rec {
             name = "axum";
             packageId = "axum";
           }
+          {
+            name = "bcrypt";
+            packageId = "bcrypt";
+          }
           {
             name = "chrono";
             packageId = "chrono";
@@ -5833,6 +6170,10 @@ rec {
             name = "prometheus-client";
             packageId = "prometheus-client";
           }
+          {
+            name = "prost";
+            packageId = "prost";
+          }
           {
             name = "rand";
             packageId = "rand 0.8.5";
@@ -5846,6 +6187,10 @@ rec {
             name = "serde_json";
             packageId = "serde_json";
           }
+          {
+            name = "sha2";
+            packageId = "sha2";
+          }
           {
             name = "sled";
             packageId = "sled";
@@ -5867,12 +6212,12 @@ rec {
           {
             name = "tonic";
             packageId = "tonic";
-            features = [ "tls" ];
+            features = [ "tls" "gzip" ];
           }
           {
             name = "tower";
             packageId = "tower";
-            features = [ "load-shed" ];
+            features = [ "load-shed" "util" ];
           }
           {
             name = "tracing";
@@ -5887,6 +6232,10 @@ rec {
             name = "url";
             packageId = "url";
           }
+          {
+            name = "x509-parser";
+            packageId = "x509-parser";
+          }
         ];
         devDependencies = [
           {
@@ -5940,6 +6289,10 @@ rec {
             name = "chrono";
             packageId = "chrono";
           }
+          {
+            name = "crc32fast";
+            packageId = "crc32fast";
+          }
           {
             name = "etcd-proto";
             packageId = "etcd-proto";
@@ -6046,6 +6399,19 @@ rec {
         ];
 
       };
+      "minimal-lexical" = rec {
+        crateName = "minimal-lexical";
+        version = "0.2.1";
+        edition = "2018";
+        sha256 = "16ppc5g84aijpri4jzv14rvcnslvlpphbszc7zzp6vfkddf4qdb8";
+        authors = [
+          "Alex Huszagh <ahuszagh@gmail.com>"
+        ];
+        features = {
+          "default" = [ "std" ];
+        };
+        resolvedDefaultFeatures = [ "std" ];
+      };
       "miniz_oxide" = rec {
         crateName = "miniz_oxide";
         version = "0.7.1";
@@ -6307,6 +6673,32 @@ rec {
         };
         resolvedDefaultFeatures = [ "acct" "aio" "default" "dir" "env" "event" "feature" "fs" "hostname" "inotify" "ioctl" "kmod" "memoffset" "mman" "mount" "mqueue" "net" "personality" "poll" "process" "pthread" "ptrace" "quota" "reboot" "resource" "sched" "signal" "socket" "term" "time" "ucontext" "uio" "user" "zerocopy" ];
       };
+      "nom" = rec {
+        crateName = "nom";
+        version = "7.1.3";
+        edition = "2018";
+        sha256 = "0jha9901wxam390jcf5pfa0qqfrgh8li787jx2ip0yk5b8y9hwyj";
+        authors = [
+          "contact@geoffroycouprie.com"
+        ];
+        dependencies = [
+          {
+            name = "memchr";
+            packageId = "memchr";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "minimal-lexical";
+            packageId = "minimal-lexical";
+            usesDefaultFeatures = false;
+          }
+        ];
+        features = {
+          "default" = [ "std" ];
+          "std" = [ "alloc" "memchr/std" "minimal-lexical/std" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "default" "std" ];
+      };
       "now" = rec {
         crateName = "now";
         version = "0.1.3";
@@ -6374,6 +6766,72 @@ rec {
           "serde" = [ "dep:serde" ];
         };
       };
+      "num-bigint" = rec {
+        crateName = "num-bigint";
+        version = "0.4.3";
+        edition = "2018";
+        sha256 = "0py73wsa5j4izhd39nkqzqv260r0ma08vy30ky54ld3vkhlbcfpr";
+        authors = [
+          "The Rust Project Developers"
+        ];
+        dependencies = [
+          {
+            name = "num-integer";
+            packageId = "num-integer";
+            usesDefaultFeatures = false;
+            features = [ "i128" ];
+          }
+          {
+            name = "num-traits";
+            packageId = "num-traits";
+            usesDefaultFeatures = false;
+            features = [ "i128" ];
+          }
+        ];
+        buildDependencies = [
+          {
+            name = "autocfg";
+            packageId = "autocfg";
+          }
+        ];
+        features = {
+          "arbitrary" = [ "dep:arbitrary" ];
+          "default" = [ "std" ];
+          "quickcheck" = [ "dep:quickcheck" ];
+          "rand" = [ "dep:rand" ];
+          "serde" = [ "dep:serde" ];
+          "std" = [ "num-integer/std" "num-traits/std" ];
+        };
+        resolvedDefaultFeatures = [ "default" "std" ];
+      };
+      "num-integer" = rec {
+        crateName = "num-integer";
+        version = "0.1.45";
+        edition = "2015";
+        sha256 = "1ncwavvwdmsqzxnn65phv6c6nn72pnv9xhpmjd6a429mzf4k6p92";
+        authors = [
+          "The Rust Project Developers"
+        ];
+        dependencies = [
+          {
+            name = "num-traits";
+            packageId = "num-traits";
+            usesDefaultFeatures = false;
+          }
+        ];
+        buildDependencies = [
+          {
+            name = "autocfg";
+            packageId = "autocfg";
+          }
+        ];
+        features = {
+          "default" = [ "std" ];
+          "i128" = [ "num-traits/i128" ];
+          "std" = [ "num-traits/std" ];
+        };
+        resolvedDefaultFeatures = [ "default" "i128" "std" ];
+      };
       "num-traits" = rec {
         crateName = "num-traits";
         version = "0.2.15";
@@ -6399,7 +6857,7 @@ rec {
           "default" = [ "std" ];
           "libm" = [ "dep:libm" ];
         };
-        resolvedDefaultFeatures = [ "default" "libm" "std" ];
+        resolvedDefaultFeatures = [ "default" "i128" "libm" "std" ];
       };
       "num_cpus" = rec {
         crateName = "num_cpus";
@@ -6423,6 +6881,26 @@ rec {
         ];
 
       };
+      "oid-registry" = rec {
+        crateName = "oid-registry";
+        version = "0.6.1";
+        edition = "2018";
+        sha256 = "1zwvjp3ad6gzn8g8w2hcn9a2xdap0lkzckhlnwp6rabbzdpz7vcv";
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "asn1-rs";
+            packageId = "asn1-rs";
+          }
+        ];
+        features = {
+          "crypto" = [ "kdf" "pkcs1" "pkcs7" "pkcs9" "pkcs12" "nist_algs" "x962" ];
+          "default" = [ "registry" ];
+        };
+        resolvedDefaultFeatures = [ "crypto" "default" "kdf" "nist_algs" "pkcs1" "pkcs12" "pkcs7" "pkcs9" "registry" "x509" "x962" ];
+      };
       "once_cell" = rec {
         crateName = "once_cell";
         version = "1.18.0";
@@ -9426,6 +9904,24 @@ rec {
           }
         ];
 
+      };
+      "rusticata-macros" = rec {
+        crateName = "rusticata-macros";
+        version = "4.1.0";
+        edition = "2018";
+        sha256 = "0ch67lljmgl5pfrlb90bl5kkp2x6yby1qaxnpnd0p5g9xjkc9w7s";
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "nom";
+            packageId = "nom";
+            usesDefaultFeatures = false;
+            features = [ "std" ];
+          }
+        ];
+
       };
       "rustix" = rec {
         crateName = "rustix";
@@ -9825,7 +10321,7 @@ rec {
           "derive" = [ "serde_derive" ];
           "serde_derive" = [ "dep:serde_derive" ];
         };
-        resolvedDefaultFeatures = [ "default" "derive" "serde_derive" "std" ];
+        resolvedDefaultFeatures = [ "alloc" "default" "derive" "serde_derive" "std" ];
       };
       "serde_derive" = rec {
         crateName = "serde_derive";
@@ -10499,6 +10995,7 @@ rec {
         features = {
           "default" = [ "std" "i128" ];
         };
+        resolvedDefaultFeatures = [ "default" "i128" "std" ];
       };
       "syn 1.0.109" = rec {
         crateName = "syn";
@@ -10532,7 +11029,7 @@ rec {
           "quote" = [ "dep:quote" ];
           "test" = [ "syn-test-suite/all-features" ];
         };
-        resolvedDefaultFeatures = [ "clone-impls" "default" "derive" "extra-traits" "full" "parsing" "printing" "proc-macro" "quote" "visit-mut" ];
+        resolvedDefaultFeatures = [ "clone-impls" "default" "derive" "extra-traits" "full" "parsing" "printing" "proc-macro" "quote" "visit" "visit-mut" ];
       };
       "syn 2.0.18" = rec {
         crateName = "syn";
@@ -10581,6 +11078,42 @@ rec {
           "futures-core" = [ "dep:futures-core" ];
         };
       };
+      "synstructure" = rec {
+        crateName = "synstructure";
+        version = "0.12.6";
+        edition = "2018";
+        sha256 = "03r1lydbf3japnlpc4wka7y90pmz1i0danaj3f9a7b431akdlszk";
+        authors = [
+          "Nika Layzell <nika@thelayzells.com>"
+        ];
+        dependencies = [
+          {
+            name = "proc-macro2";
+            packageId = "proc-macro2";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "quote";
+            packageId = "quote";
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "syn";
+            packageId = "syn 1.0.109";
+            usesDefaultFeatures = false;
+            features = [ "derive" "parsing" "printing" "clone-impls" "visit" "extra-traits" ];
+          }
+          {
+            name = "unicode-xid";
+            packageId = "unicode-xid";
+          }
+        ];
+        features = {
+          "default" = [ "proc-macro" ];
+          "proc-macro" = [ "proc-macro2/proc-macro" "syn/proc-macro" "quote/proc-macro" ];
+        };
+        resolvedDefaultFeatures = [ "default" "proc-macro" ];
+      };
       "sysinfo 0.28.4" = rec {
         crateName = "sysinfo";
         version = "0.28.4";
@@ -10904,7 +11437,7 @@ rec {
         features = {
         };
       };
-      "time" = rec {
+      "time 0.1.45" = rec {
         crateName = "time";
         version = "0.1.45";
         edition = "2015";
@@ -10940,6 +11473,84 @@ rec {
           "rustc-serialize" = [ "dep:rustc-serialize" ];
         };
       };
+      "time 0.3.22" = rec {
+        crateName = "time";
+        version = "0.3.22";
+        edition = "2021";
+        sha256 = "1z8y87bxq0fr23r1ma7kb1a15ws2spa8ah77v42y0fi4y4y1p7pa";
+        authors = [
+          "Jacob Pratt <open-source@jhpratt.dev>"
+          "Time contributors"
+        ];
+        dependencies = [
+          {
+            name = "itoa";
+            packageId = "itoa";
+            optional = true;
+          }
+          {
+            name = "serde";
+            packageId = "serde";
+            optional = true;
+            usesDefaultFeatures = false;
+          }
+          {
+            name = "time-core";
+            packageId = "time-core";
+          }
+          {
+            name = "time-macros";
+            packageId = "time-macros";
+            optional = true;
+          }
+        ];
+        features = {
+          "alloc" = [ "serde?/alloc" ];
+          "default" = [ "std" ];
+          "formatting" = [ "dep:itoa" "std" "time-macros?/formatting" ];
+          "large-dates" = [ "time-macros?/large-dates" ];
+          "local-offset" = [ "std" "dep:libc" "dep:num_threads" ];
+          "macros" = [ "dep:time-macros" ];
+          "parsing" = [ "time-macros?/parsing" ];
+          "quickcheck" = [ "dep:quickcheck" "alloc" ];
+          "rand" = [ "dep:rand" ];
+          "serde" = [ "dep:serde" "time-macros?/serde" ];
+          "serde-human-readable" = [ "serde" "formatting" "parsing" ];
+          "serde-well-known" = [ "serde" "formatting" "parsing" ];
+          "std" = [ "alloc" ];
+          "wasm-bindgen" = [ "dep:js-sys" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "default" "formatting" "macros" "parsing" "std" ];
+      };
+      "time-core" = rec {
+        crateName = "time-core";
+        version = "0.1.1";
+        edition = "2021";
+        sha256 = "1yz6d246zbmx9v6wpfg1jyfjlsgagirz7km96pr1mp6snkpzn03k";
+        authors = [
+          "Jacob Pratt <open-source@jhpratt.dev>"
+          "Time contributors"
+        ];
+
+      };
+      "time-macros" = rec {
+        crateName = "time-macros";
+        version = "0.2.9";
+        edition = "2021";
+        sha256 = "16yh6g3k9rcksa94jyakvwkp6vcy7hldf4g2vcwby1sz1aa50a9p";
+        procMacro = true;
+        authors = [
+          "Jacob Pratt <open-source@jhpratt.dev>"
+          "Time contributors"
+        ];
+        dependencies = [
+          {
+            name = "time-core";
+            packageId = "time-core";
+          }
+        ];
+
+      };
       "tinyvec" = rec {
         crateName = "tinyvec";
         version = "1.6.0";
@@ -11336,6 +11947,11 @@ rec {
             name = "bytes";
             packageId = "bytes";
           }
+          {
+            name = "flate2";
+            packageId = "flate2";
+            optional = true;
+          }
           {
             name = "futures-core";
             packageId = "futures-core";
@@ -11449,7 +12065,7 @@ rec {
           "tls-webpki-roots" = [ "tls-roots-common" "dep:webpki-roots" ];
           "transport" = [ "dep:axum" "channel" ];
         };
-        resolvedDefaultFeatures = [ "channel" "codegen" "default" "prost" "tls" "transport" ];
+        resolvedDefaultFeatures = [ "channel" "codegen" "default" "gzip" "prost" "tls" "transport" ];
       };
       "tonic-build" = rec {
         crateName = "tonic-build";
@@ -11978,6 +12594,18 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" ];
       };
+      "unicode-xid" = rec {
+        crateName = "unicode-xid";
+        version = "0.2.4";
+        edition = "2015";
+        sha256 = "131dfzf7d8fsr1ivch34x42c2d1ik5ig3g78brxncnn0r1sdyqpr";
+        authors = [
+          "erick.tryzelaar <erick.tryzelaar@gmail.com>"
+          "kwantam <kwantam@gmail.com>"
+          "Manish Goregaokar <manishsmail@gmail.com>"
+        ];
+
+      };
       "untrusted" = rec {
         crateName = "untrusted";
         version = "0.7.1";
@@ -14865,6 +15493,62 @@ rec {
           "transactions" = [ "winapi/ktmw32" ];
         };
       };
+      "x509-parser" = rec {
+        crateName = "x509-parser";
+        version = "0.15.1";
+        edition = "2018";
+        sha256 = "1nk3ryam7yzsza735xdypkv1i4c35gqlygax5jyr74bbnsjznsbh";
+        authors = [
+          "Pierre Chifflier <chifflier@wzdftpd.net>"
+        ];
+        dependencies = [
+          {
+            name = "asn1-rs";
+            packageId = "asn1-rs";
+            features = [ "datetime" ];
+          }
+          {
+            name = "data-encoding";
+            packageId = "data-encoding";
+          }
+          {
+            name = "der-parser";
+            packageId = "der-parser";
+            features = [ "bigint" ];
+          }
+          {
+            name = "lazy_static";
+            packageId = "lazy_static";
+          }
+          {
+            name = "nom";
+            packageId = "nom";
+          }
+          {
+            name = "oid-registry";
+            packageId = "oid-registry";
+            features = [ "crypto" "x509" "x962" ];
+          }
+          {
+            name = "rusticata-macros";
+            packageId = "rusticata-macros";
+          }
+          {
+            name = "thiserror";
+            packageId = "thiserror";
+          }
+          {
+            name = "time";
+            packageId = "time 0.3.22";
+            features = [ "formatting" ];
+          }
+        ];
+        features = {
+          "ring" = [ "dep:ring" ];
+          "verify" = [ "ring" ];
+        };
+        resolvedDefaultFeatures = [ "default" ];
+      };
       "xxhash-rust" = rec {
         crateName = "xxhash-rust";
         version = "0.8.6";
@@ -14903,6 +15587,23 @@ rec {
         ];
 
       };
+      "zeroize" = rec {
+        crateName = "zeroize";
+        version = "1.8.2";
+        edition = "2021";
+        sha256 = "1l48zxgcv34d7kjskr610zqsm6j2b4fcr2vfh9jm9j1jgvk58wdr";
+        authors = [
+          "The RustCrypto Project Developers"
+        ];
+        features = {
+          "default" = [ "alloc" ];
+          "derive" = [ "zeroize_derive" ];
+          "serde" = [ "dep:serde" ];
+          "std" = [ "alloc" ];
+          "zeroize_derive" = [ "dep:zeroize_derive" ];
+        };
+        resolvedDefaultFeatures = [ "alloc" "default" ];
+      };
       "zstd" = rec {
         crateName = "zstd";
         version = "0.12.3+zstd.1.5.2";
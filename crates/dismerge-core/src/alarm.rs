@@ -0,0 +1,78 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmType {
+    /// The member's storage has grown beyond its quota.
+    NoSpace,
+    /// The member's storage has been found to be corrupt.
+    Corrupt,
+}
+
+impl AlarmType {
+    /// The name the alarm is stored under in the document.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::NoSpace => "NOSPACE",
+            Self::Corrupt => "CORRUPT",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "NOSPACE" => Some(Self::NoSpace),
+            "CORRUPT" => Some(Self::Corrupt),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<mergeable_proto::etcdserverpb::AlarmType> for AlarmType {
+    type Error = ();
+
+    /// Fails for `None`, which is only used to match any alarm.
+    fn try_from(value: mergeable_proto::etcdserverpb::AlarmType) -> Result<Self, Self::Error> {
+        match value {
+            mergeable_proto::etcdserverpb::AlarmType::None => Err(()),
+            mergeable_proto::etcdserverpb::AlarmType::Nospace => Ok(Self::NoSpace),
+            mergeable_proto::etcdserverpb::AlarmType::Corrupt => Ok(Self::Corrupt),
+        }
+    }
+}
+
+impl From<AlarmType> for mergeable_proto::etcdserverpb::AlarmType {
+    fn from(value: AlarmType) -> Self {
+        match value {
+            AlarmType::NoSpace => Self::Nospace,
+            AlarmType::Corrupt => Self::Corrupt,
+        }
+    }
+}
+
+/// An alarm raised for a member, these are shared across the cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Alarm {
+    pub member_id: u64,
+    pub alarm: AlarmType,
+}
+
+impl Alarm {
+    /// The key the alarm is stored under in the document.
+    pub(crate) fn key(&self) -> String {
+        format!("{}/{}", self.member_id, self.alarm.name())
+    }
+
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        let (member_id, name) = key.split_once('/')?;
+        Some(Self {
+            member_id: member_id.parse().ok()?,
+            alarm: AlarmType::from_name(name)?,
+        })
+    }
+}
+
+impl From<Alarm> for mergeable_proto::etcdserverpb::AlarmMember {
+    fn from(value: Alarm) -> Self {
+        Self {
+            member_id: value.member_id,
+            alarm: mergeable_proto::etcdserverpb::AlarmType::from(value.alarm) as i32,
+        }
+    }
+}
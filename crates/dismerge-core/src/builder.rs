@@ -22,6 +22,7 @@ pub struct DocumentBuilder<P, S, W, V> {
     auto_flush: bool,
     auto_sync: bool,
     max_outstanding: u64,
+    quota_backend_bytes: Option<u64>,
     _value_type: PhantomData<V>,
 }
 
@@ -40,6 +41,7 @@ impl<V> Default for DocumentBuilder<MemoryPersister, (), (), V> {
             auto_flush: true,
             auto_sync: true,
             max_outstanding: 100,
+            quota_backend_bytes: None,
             _value_type: PhantomData::default(),
         }
     }
@@ -61,6 +63,7 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
             _value_type: PhantomData::default(),
         }
     }
@@ -80,6 +83,7 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
             _value_type: PhantomData::default(),
         }
     }
@@ -99,6 +103,7 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
             _value_type: PhantomData::default(),
         }
     }
@@ -201,6 +206,18 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
         self.max_outstanding = max_outstanding;
         self
     }
+
+    /// Raise a NOSPACE alarm once the storage grows beyond this many bytes.
+    #[must_use]
+    pub fn with_quota_backend_bytes(mut self, quota_backend_bytes: u64) -> Self {
+        self.quota_backend_bytes = Some(quota_backend_bytes);
+        self
+    }
+
+    pub fn set_quota_backend_bytes(&mut self, quota_backend_bytes: u64) -> &mut Self {
+        self.quota_backend_bytes = Some(quota_backend_bytes);
+        self
+    }
 }
 
 impl<S, W, V> DocumentBuilder<MemoryPersister, S, W, V> {
@@ -238,6 +255,7 @@ where
             members_objid: automerge::ObjId::Root,
             leases_objid: automerge::ObjId::Root,
             cluster_objid: automerge::ObjId::Root,
+            auth_objid: automerge::ObjId::Root,
             rng: StdRng::seed_from_u64(self.seed),
             flush_notifier,
             flush_notifier_receiver,
@@ -245,6 +263,7 @@ where
             auto_sync: self.auto_sync,
             outstanding: 0,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
             _value_type: PhantomData::default(),
             peer_heads: HashMap::default(),
             local_changes: Vec::new(),
@@ -16,6 +16,8 @@ use tokio::sync::watch;
 use tracing::warn;
 use tracing::{debug, info};
 
+use crate::alarm::{Alarm, AlarmType};
+use crate::auth::{Permission, PermissionType, User, ROOT_ROLE, ROOT_USER};
 use crate::transaction::extract_key_value_at;
 use crate::value::Value;
 use crate::{
@@ -23,7 +25,7 @@ use crate::{
         DeleteRangeRequest, DeleteRangeResponse, Header, PutRequest, PutResponse, RangeRequest,
         RangeResponse,
     },
-    Syncer, TxnRequest, TxnResponse, VecWatcher, Watcher,
+    KvRequest, Syncer, TxnRequest, TxnResponse, VecWatcher, Watcher,
 };
 
 #[cfg(test)]
@@ -38,8 +40,16 @@ const DEFAULT_LEASE_TTL: i64 = 30;
 /// {
 ///   "kvs": { "key1": { "value": ..., "lease_id": 0 } },
 ///   "leases": { "1": (), "5": () },
-///   "cluster": { "cluster_id": 0x00 }
+///   "cluster": {
+///     "cluster_id": 0x00, "alarms": { "1/NOSPACE": true }, "removed_members": { "3": true },
+///     "learners": { "4": true }
+///   }
 ///   "members": { 0: {"name": "default", "peer_urls":[], "client_urls":[]} }
+///   "auth": {
+///     "enabled": false,
+///     "users": { "root": { "password": "$2b$...", "roles": { "root": () } } },
+///     "roles": { "reader": { "permissions": { "key1": { "key5": 0 } } } }
+///   }
 /// }
 #[derive(Debug)]
 pub struct Document<P, S, W, V> {
@@ -54,6 +64,7 @@ pub struct Document<P, S, W, V> {
     pub(crate) members_objid: ObjId,
     pub(crate) leases_objid: ObjId,
     pub(crate) cluster_objid: ObjId,
+    pub(crate) auth_objid: ObjId,
     pub rng: StdRng,
     pub(crate) flush_notifier: watch::Sender<()>,
     pub(crate) peer_heads: HashMap<u64, Vec<ChangeHash>>,
@@ -64,6 +75,8 @@ pub struct Document<P, S, W, V> {
     pub(crate) auto_sync: bool,
     pub(crate) outstanding: u64,
     pub(crate) max_outstanding: u64,
+    /// Raise a NOSPACE alarm when the storage grows beyond this.
+    pub(crate) quota_backend_bytes: Option<u64>,
     pub(crate) local_changes: Vec<automerge::Change>,
 
     pub(crate) _value_type: PhantomData<V>,
@@ -78,8 +91,17 @@ where
 {
     pub(crate) fn init(&mut self, cluster_id: Option<u64>) {
         if self.am.document().get_heads().is_empty() {
-            self.init_document();
+            debug!("Initialising document with 0 actor");
+            self.am.document_mut().set_actor(ActorId::from(vec![0]));
+        } else {
+            // anything missing from a document written by an older version is made by this member
+            // rather than the shared 0 actor, which already has changes in the document
+            debug!("Loading existing document");
+            self.am
+                .document_mut()
+                .set_actor(ActorId::from(self.member_id.to_be_bytes()));
         }
+        self.init_document();
 
         self.am
             .document_mut()
@@ -98,10 +120,9 @@ where
         self.add_member_local();
     }
 
-    /// set up the document's initial structure
+    /// set up the document's structure, finding the parts that a loaded document, such as a
+    /// snapshot, already has and making any that it is missing
     fn init_document(&mut self) {
-        debug!("Initialising document with 0 actor");
-        self.am.document_mut().set_actor(ActorId::from(vec![0]));
         self.am
             .transact::<_, _, AutomergeError>(|tx| {
                 self.kvs_objid = if let Some((_, id)) = tx.get(ROOT, "kvs").unwrap() {
@@ -114,6 +135,14 @@ where
                 } else {
                     tx.put_object(ROOT, "cluster", ObjType::Map).unwrap()
                 };
+                // made up front so that members don't make conflicting ones with their first
+                // removals or learners
+                for map in ["removed_members", "learners"] {
+                    if tx.get(&self.cluster_objid, map).unwrap().is_none() {
+                        tx.put_object(&self.cluster_objid, map, ObjType::Map)
+                            .unwrap();
+                    }
+                }
                 self.members_objid = if let Some((_, id)) = tx.get(ROOT, "members").unwrap() {
                     id
                 } else {
@@ -126,6 +155,17 @@ where
                     tx.put_object(ROOT, "leases", ObjType::Map).unwrap()
                 };
 
+                self.auth_objid = if let Some((_, id)) = tx.get(ROOT, "auth").unwrap() {
+                    id
+                } else {
+                    tx.put_object(ROOT, "auth", ObjType::Map).unwrap()
+                };
+                for map in ["users", "roles"] {
+                    if tx.get(&self.auth_objid, map).unwrap().is_none() {
+                        tx.put_object(&self.auth_objid, map, ObjType::Map).unwrap();
+                    }
+                }
+
                 Ok(())
             })
             .unwrap();
@@ -168,10 +208,45 @@ where
         changes + document + sync_states
     }
 
+    /// Save the document to storage and truncate the log of changes it was built from, returning
+    /// the stored sizes from before and after.
+    pub fn defragment(&mut self) -> (StoredSizes, StoredSizes) {
+        // make sure all changes are in storage before replacing them
+        self.flush();
+        let before = self.am.persister().sizes();
+        self.am.compact(&[]).unwrap();
+        self.flush();
+        let after = self.am.persister().sizes();
+        (before, after)
+    }
+
+    /// Save the whole document in its compacted form, for backing up or seeding new nodes from.
+    pub fn snapshot(&mut self) -> Vec<u8> {
+        self.am.document().save()
+    }
+
+    /// Hash the whole document, members that have the same changes have the same hash.
+    pub fn hash(&mut self) -> u32 {
+        crc32fast::hash(&self.snapshot())
+    }
+
+    /// Hash the live key-values at the heads, or the latest ones, so that members can check that
+    /// they have converged.
+    pub fn hash_kv(&self, heads: &[ChangeHash]) -> u32 {
+        crate::transaction::hash_kv::<_, V>(self.am.document(), heads)
+    }
+
     pub fn heads(&self) -> Vec<ChangeHash> {
         self.am.document().get_heads()
     }
 
+    /// Whether the document has the changes for all of the heads.
+    pub fn has_heads(&self, heads: &[ChangeHash]) -> bool {
+        heads
+            .iter()
+            .all(|head| self.am.document().get_change_by_hash(head).is_some())
+    }
+
     pub fn header(&self) -> crate::Result<Header> {
         let heads = self.heads();
         let Some(cluster_id) = self.cluster_id() else {
@@ -190,6 +265,7 @@ where
             debug!(?flushed_bytes, "Flushed db");
         }
         self.flush_notifier.send(()).unwrap();
+        self.check_quota();
         flushed_bytes
     }
 
@@ -225,6 +301,8 @@ where
         &mut self,
         request: PutRequest<V>,
     ) -> crate::Result<oneshot::Receiver<(Header, PutResponse<V>)>> {
+        self.check_not_learner()?;
+        self.check_space()?;
         let mut temp_watcher = VecWatcher::default();
         let txn_result = self
             .am
@@ -264,6 +342,7 @@ where
         &mut self,
         request: DeleteRangeRequest,
     ) -> crate::Result<oneshot::Receiver<(Header, DeleteRangeResponse<V>)>> {
+        self.check_not_learner()?;
         let mut temp_watcher = VecWatcher::default();
         let txn_result = self
             .am
@@ -325,6 +404,12 @@ where
         &mut self,
         request: TxnRequest<V>,
     ) -> crate::Result<oneshot::Receiver<(Header, TxnResponse<V>)>> {
+        if !request.is_read_only() {
+            self.check_not_learner()?;
+        }
+        if request.has_puts() {
+            self.check_space()?;
+        }
         let mut temp_watcher = VecWatcher::default();
         let txn_result = self
             .am
@@ -408,6 +493,30 @@ where
             .unwrap()
     }
 
+    /// Pick up syncing with a peer after the connection to it was lost. Messages in flight may
+    /// never have arrived, so sync again from the heads last shared with it, which is all that
+    /// the persisted sync state keeps.
+    pub fn resume_sync(&mut self, peer_id: u64) {
+        debug!(?peer_id, "resuming sync");
+        let peer = peer_id.to_be_bytes();
+        let saved = match self.am.persister().get_sync_state(&peer) {
+            Ok(saved) => saved,
+            Err(error) => {
+                warn!(?error, ?peer_id, "Failed to load sync state to resume from");
+                None
+            }
+        };
+        if let Err(error) = self.am.reset_sync_state(&peer) {
+            warn!(?error, ?peer_id, "Failed to reset sync state");
+        }
+        if let Some(saved) = saved {
+            if let Err(error) = self.am.persister_mut().set_sync_state(peer.to_vec(), saved) {
+                warn!(?error, ?peer_id, "Failed to restore sync state");
+            }
+        }
+        self.sync();
+    }
+
     pub fn generate_sync_changes(
         &mut self,
         peer_id: u64,
@@ -546,14 +655,14 @@ where
                         };
                         self.watcher.publish_event(self.header()?, event).await;
                     } else if obj == self.members_objid {
-                        let member = self
-                            .get_member(
-                                key.to_string()
-                                    .parse()
-                                    .map_err(|_| crate::Error::NotParseableAsId(key.to_string()))?,
-                            )
-                            .unwrap();
-                        self.syncer.member_change(&member).await;
+                        // the member may have been removed since
+                        if let Some(member) = self.get_member(
+                            key.to_string()
+                                .parse()
+                                .map_err(|_| crate::Error::NotParseableAsId(key.to_string()))?,
+                        ) {
+                            self.syncer.member_change(&member).await;
+                        }
                     } else if let Some(member_id) = self
                         .am
                         .document()
@@ -567,14 +676,13 @@ where
                             }
                         })
                     {
-                        let member = self
-                            .get_member(
-                                member_id
-                                    .parse()
-                                    .map_err(|_| crate::Error::NotParseableAsId(member_id))?,
-                            )
-                            .unwrap();
-                        self.syncer.member_change(&member).await;
+                        if let Some(member) = self.get_member(
+                            member_id
+                                .parse()
+                                .map_err(|_| crate::Error::NotParseableAsId(member_id))?,
+                        ) {
+                            self.syncer.member_change(&member).await;
+                        }
                     }
                 }
                 automerge::op_observer::PatchAction::PutSeq {
@@ -618,6 +726,11 @@ where
                             prev_kv,
                         };
                         self.watcher.publish_event(self.header()?, event).await;
+                    } else if obj == self.members_objid {
+                        let member_id = key
+                            .parse()
+                            .map_err(|_| crate::Error::NotParseableAsId(key.clone()))?;
+                        self.forget_member(member_id).await;
                     }
                 }
                 automerge::op_observer::PatchAction::DeleteSeq {
@@ -661,37 +774,49 @@ where
             }
         }
 
-        let persister = self.am.persister();
         let mut replication_states = BTreeMap::new();
         let members = self.list_members().unwrap();
-        let member_ids = members.iter().map(|member| member.id);
+        // learners don't count towards replication until they are promoted
+        let member_ids = members
+            .iter()
+            .filter(|member| !member.is_learner)
+            .map(|member| member.id);
         for member_id in member_ids {
             if member_id == self_member_id {
                 replication_states.insert(member_id, true);
                 continue;
             }
-            let peer_id = member_id.to_be_bytes().to_vec();
-            let sync_state = persister.get_sync_state(&peer_id).unwrap().unwrap();
-            let sync_state = automerge::sync::State::decode(&sync_state).unwrap();
-            let they_have_the_heads = match self
-                .am
-                .document()
-                .partial_cmp_heads(heads, &sync_state.shared_heads)
-            {
-                Some(ordering) => match ordering {
-                    std::cmp::Ordering::Less | std::cmp::Ordering::Equal => true,
-                    std::cmp::Ordering::Greater => false,
-                },
-                None => {
-                    // concurrent, so no
-                    false
-                }
-            };
-            replication_states.insert(member_id, they_have_the_heads);
+            replication_states.insert(member_id, self.member_has_heads(member_id, heads));
         }
         replication_states
     }
 
+    /// Whether the member has the heads, going by the heads we last shared with it.
+    fn member_has_heads(&self, member_id: u64, heads: &[ChangeHash]) -> bool {
+        let peer_id = member_id.to_be_bytes().to_vec();
+        let sync_state = match self.am.persister().get_sync_state(&peer_id) {
+            Ok(Some(sync_state)) => sync_state,
+            Ok(None) | Err(_) => return false,
+        };
+        let Ok(sync_state) = automerge::sync::State::decode(&sync_state) else {
+            return false;
+        };
+        match self
+            .am
+            .document()
+            .partial_cmp_heads(heads, &sync_state.shared_heads)
+        {
+            Some(ordering) => match ordering {
+                std::cmp::Ordering::Less | std::cmp::Ordering::Equal => true,
+                std::cmp::Ordering::Greater => false,
+            },
+            None => {
+                // concurrent, so no
+                false
+            }
+        }
+    }
+
     pub fn list_members(&self) -> crate::Result<Vec<Member>> {
         let mut members = Vec::new();
         let document = self.am.document();
@@ -766,12 +891,18 @@ where
             id,
             peer_ur_ls: peer_urls,
             client_ur_ls: client_urls,
-            is_learner: false, // unsupported
+            is_learner: self.is_member_learner(id),
         })
     }
 
-    /// Add a cluster member with the given peer urls to connect to.
-    pub async fn add_member(&mut self, peer_urls: Vec<String>, id: u64) -> Member {
+    /// Add a cluster member with the given peer urls to connect to, learners only receive changes
+    /// until they are promoted.
+    pub async fn add_member(
+        &mut self,
+        peer_urls: Vec<String>,
+        id: u64,
+        is_learner: bool,
+    ) -> Member {
         let result = self
             .am
             .transact::<_, _, AutomergeError>(|txn| {
@@ -781,13 +912,25 @@ where
                 let peer_list = txn.put_object(&map, "peer_urls", ObjType::List).unwrap();
                 txn.splice(&peer_list, 0, 0, peer_urls.iter().map(|s| s.into()))
                     .unwrap();
+                if is_learner {
+                    // kept out of the member's map as the member may put its own over it when it
+                    // starts
+                    let learners_objid = if let Some((_, learners_objid)) =
+                        txn.get(&self.cluster_objid, "learners")?
+                    {
+                        learners_objid
+                    } else {
+                        txn.put_object(&self.cluster_objid, "learners", ObjType::Map)?
+                    };
+                    txn.put(&learners_objid, id.to_string(), true)?;
+                }
 
                 Ok(Member {
                     id,
                     name: String::new(),
                     peer_ur_ls: peer_urls,
                     client_ur_ls: vec![],
-                    is_learner: false,
+                    is_learner,
                 })
             })
             .unwrap()
@@ -798,6 +941,143 @@ where
         result
     }
 
+    /// Replace the peer urls of a member, the others reconnect to it at the new urls once they
+    /// receive the change.
+    pub async fn update_member(
+        &mut self,
+        id: u64,
+        peer_urls: Vec<String>,
+    ) -> crate::Result<Member> {
+        // a member needs somewhere for the others to reach it
+        if peer_urls.is_empty() {
+            return Err(crate::Error::MemberBadUrls);
+        }
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        let in_use = self.list_members()?.into_iter().any(|member| {
+            member.id != id && member.peer_ur_ls.iter().any(|url| peer_urls.contains(url))
+        });
+        if in_use {
+            return Err(crate::Error::PeerUrlExists);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, map) = txn
+                    .get(&self.members_objid, id.to_string())?
+                    .expect("member should exist");
+                let peer_list = txn.put_object(&map, "peer_urls", ObjType::List)?;
+                txn.splice(&peer_list, 0, 0, peer_urls.iter().map(|s| s.into()))?;
+                Ok(())
+            })
+            .unwrap();
+        if id == self.member_id {
+            self.peer_urls = peer_urls;
+        }
+        let member = self.get_member(id).unwrap();
+        self.syncer.member_change(&member).await;
+        debug!("document changed in update_member");
+        self.document_changed();
+        Ok(member)
+    }
+
+    /// Promote a learner to a full member once it has caught up with our changes.
+    pub async fn promote_member(&mut self, id: u64) -> crate::Result<Member> {
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        if !self.is_member_learner(id) {
+            return Err(crate::Error::MemberNotLearner);
+        }
+        if !self.member_has_heads(id, &self.heads()) {
+            return Err(crate::Error::LearnerNotReady);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, learners_objid) = txn
+                    .get(&self.cluster_objid, "learners")?
+                    .expect("learners should exist");
+                txn.delete(&learners_objid, id.to_string())?;
+                Ok(())
+            })
+            .unwrap();
+        let member = self.get_member(id).unwrap();
+        self.syncer.member_change(&member).await;
+        debug!("document changed in promote_member");
+        self.document_changed();
+        Ok(member)
+    }
+
+    /// Remove a member from the cluster for good. The other members drop their connections to it
+    /// and refuse its syncs once they receive the removal.
+    pub async fn remove_member(&mut self, id: u64) -> crate::Result<()> {
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&self.members_objid, id.to_string())?;
+                let removed_objid = if let Some((_, removed_objid)) =
+                    txn.get(&self.cluster_objid, "removed_members")?
+                {
+                    removed_objid
+                } else {
+                    txn.put_object(&self.cluster_objid, "removed_members", ObjType::Map)?
+                };
+                txn.put(&removed_objid, id.to_string(), true)?;
+                if let Some((_, learners_objid)) = txn.get(&self.cluster_objid, "learners")? {
+                    if txn.get(&learners_objid, id.to_string())?.is_some() {
+                        txn.delete(&learners_objid, id.to_string())?;
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        self.forget_member(id).await;
+        debug!("document changed in remove_member");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Whether the member has been removed from the cluster, so should no longer be synced with.
+    pub fn is_member_removed(&self, id: u64) -> bool {
+        let document = self.am.document();
+        document
+            .get(&self.cluster_objid, "removed_members")
+            .unwrap()
+            .and_then(|(_, removed_objid)| document.get(&removed_objid, id.to_string()).unwrap())
+            .is_some()
+    }
+
+    /// Drop the state kept for syncing with a removed member.
+    async fn forget_member(&mut self, id: u64) {
+        info!(member_id=?id, "Forgetting removed member");
+        self.peer_heads.remove(&id);
+        if let Err(error) = self.am.reset_sync_state(&id.to_be_bytes()) {
+            warn!(?error, member_id=?id, "Failed to remove sync state for removed member");
+        }
+        self.syncer.member_remove(id).await;
+    }
+
+    /// Whether the member is a learner, so only receives changes.
+    pub fn is_member_learner(&self, id: u64) -> bool {
+        let document = self.am.document();
+        document
+            .get(&self.cluster_objid, "learners")
+            .unwrap()
+            .and_then(|(_, learners_objid)| document.get(&learners_objid, id.to_string()).unwrap())
+            .is_some()
+    }
+
+    /// Check that this member can take writes, which learners can't until they are promoted.
+    pub fn check_not_learner(&self) -> crate::Result<()> {
+        if self.is_member_learner(self.member_id) {
+            Err(crate::Error::NotSupportedForLearner)
+        } else {
+            Ok(())
+        }
+    }
+
     fn add_member_local(&mut self) -> Member {
         let id = self.member_id;
         let name = self.name.clone();
@@ -943,7 +1223,7 @@ where
         let ttl = self
             .am
             .transact::<_, _, AutomergeError>(|txn| {
-                let ttl = if let Some((_, lease_obj)) =
+                let ttl = if let Some((automerge::Value::Object(ObjType::Map), lease_obj)) =
                     txn.get(&self.leases_objid, make_lease_string(id)).unwrap()
                 {
                     // update the refresh time
@@ -967,34 +1247,45 @@ where
         ttl
     }
 
-    /// Try and return the last refresh of this lease.
-    pub fn last_lease_refresh(&self, id: i64) -> Option<i64> {
-        if let Some((_, lease_obj)) = self
+    fn lease_obj(&self, id: i64) -> Option<ObjId> {
+        match self
             .am
             .document()
             .get(&self.leases_objid, make_lease_string(id))
             .unwrap()
         {
-            let (refresh, _) = self
-                .am
-                .document()
-                .get(&lease_obj, "last_refresh_secs")
-                .unwrap()
-                .unwrap();
-            refresh.to_i64()
-        } else {
-            None
+            // revoked leases are left behind as null
+            Some((automerge::Value::Object(ObjType::Map), lease_obj)) => Some(lease_obj),
+            _ => None,
         }
     }
 
+    /// Try and return the last refresh of this lease.
+    pub fn last_lease_refresh(&self, id: i64) -> Option<i64> {
+        let lease_obj = self.lease_obj(id)?;
+        let document = self.am.document();
+        document
+            // take the latest in case of concurrent refreshes on different nodes
+            .get_all(&lease_obj, "last_refresh_secs")
+            .unwrap()
+            .into_iter()
+            .filter_map(|(refresh, _)| refresh.to_i64())
+            .max()
+    }
+
+    /// Try and return the seconds left before this lease expires, counting from its last refresh
+    /// on any node.
+    pub fn remaining_lease_ttl(&self, id: i64, now: i64) -> Option<i64> {
+        let last_refresh = self.last_lease_refresh(id)?;
+        let granted_ttl = self.granted_lease_ttl(id)?;
+        let time_since_refresh = now - last_refresh;
+        // a lease that has expired but not been revoked yet has no time left
+        Some((granted_ttl - time_since_refresh).max(0))
+    }
+
     /// Try and return the ttl allowed for this lease.
     pub fn granted_lease_ttl(&self, id: i64) -> Option<i64> {
-        if let Some((_, lease_obj)) = self
-            .am
-            .document()
-            .get(&self.leases_objid, make_lease_string(id))
-            .unwrap()
-        {
+        if let Some(lease_obj) = self.lease_obj(id) {
             let (ttl, _) = self
                 .am
                 .document()
@@ -1009,9 +1300,13 @@ where
 
     /// List all current leases in the document.
     pub fn all_lease_ids(&self) -> crate::Result<Vec<i64>> {
-        let leases = self.am.document().keys(&self.leases_objid);
+        let leases = self.am.document().map_range(&self.leases_objid, ..);
         let mut ids = Vec::new();
-        for id in leases {
+        for (id, value, _) in leases {
+            if !matches!(value, automerge::Value::Object(ObjType::Map)) {
+                // revoked
+                continue;
+            }
             ids.push(
                 id.parse::<i64>()
                     .map_err(|_| crate::Error::NotParseableAsId(id.to_owned()))?,
@@ -1021,12 +1316,7 @@ where
     }
 
     pub fn keys_for_lease(&self, id: i64) -> Vec<String> {
-        if let Some((_, lease_obj)) = self
-            .am
-            .document()
-            .get(&self.leases_objid, make_lease_string(id))
-            .unwrap()
-        {
+        if let Some(lease_obj) = self.lease_obj(id) {
             let keys_obj = self
                 .am
                 .document()
@@ -1039,6 +1329,528 @@ where
             vec![]
         }
     }
+
+    /// Whether clients need to authenticate to make requests.
+    pub fn auth_enabled(&self) -> bool {
+        self.am
+            .document()
+            .get(&self.auth_objid, "enabled")
+            .unwrap()
+            .and_then(|(enabled, _)| enabled.to_bool())
+            .unwrap_or(false)
+    }
+
+    /// The alarms that are raised across the cluster.
+    pub fn alarms(&self) -> Vec<Alarm> {
+        let document = self.am.document();
+        let mut alarms = Vec::new();
+        if let Some((_, alarms_objid)) = document.get(&self.cluster_objid, "alarms").unwrap() {
+            for (key, _, _) in document.map_range(&alarms_objid, ..) {
+                if let Some(alarm) = Alarm::from_key(key) {
+                    alarms.push(alarm);
+                }
+            }
+        }
+        alarms
+    }
+
+    /// Raise the alarm, returns whether it was newly raised.
+    pub fn activate_alarm(&mut self, alarm: Alarm) -> bool {
+        if self.alarms().contains(&alarm) {
+            return false;
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let alarms_objid =
+                    if let Some((_, id)) = txn.get(&self.cluster_objid, "alarms").unwrap() {
+                        id
+                    } else {
+                        txn.put_object(&self.cluster_objid, "alarms", ObjType::Map)?
+                    };
+                txn.put(&alarms_objid, alarm.key(), true)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?alarm, "document changed in activate_alarm");
+        self.document_changed();
+        true
+    }
+
+    /// Disarm the alarm, returns whether it was raised.
+    pub fn deactivate_alarm(&mut self, alarm: Alarm) -> bool {
+        if !self.alarms().contains(&alarm) {
+            return false;
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, alarms_objid) = txn.get(&self.cluster_objid, "alarms").unwrap().unwrap();
+                txn.delete(&alarms_objid, alarm.key())?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?alarm, "document changed in deactivate_alarm");
+        self.document_changed();
+        true
+    }
+
+    /// Raise a NOSPACE alarm for this member if the storage has grown beyond the quota.
+    fn check_quota(&mut self) {
+        if let Some(quota_backend_bytes) = self.quota_backend_bytes {
+            let db_size = self.db_size();
+            if db_size > quota_backend_bytes {
+                let alarm = Alarm {
+                    member_id: self.member_id,
+                    alarm: AlarmType::NoSpace,
+                };
+                if self.activate_alarm(alarm) {
+                    warn!(?db_size, ?quota_backend_bytes, "Database space exceeded");
+                }
+            }
+        }
+    }
+
+    /// Check that writes can take up more space, which they can't while any member has a NOSPACE
+    /// alarm.
+    pub fn check_space(&self) -> crate::Result<()> {
+        if self
+            .alarms()
+            .iter()
+            .any(|alarm| alarm.alarm == AlarmType::NoSpace)
+        {
+            Err(crate::Error::NoSpace)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Enable auth, the root user must exist and have the root role.
+    pub fn enable_auth(&mut self) -> crate::Result<()> {
+        let root = self
+            .get_user(ROOT_USER)
+            .map_err(|_| crate::Error::RootUserNotExist)?;
+        if !root.roles.iter().any(|role| role == ROOT_ROLE) {
+            return Err(crate::Error::RootRoleNotExist);
+        }
+        self.set_auth_enabled(true);
+        Ok(())
+    }
+
+    /// Disable auth, letting any client make requests.
+    pub fn disable_auth(&mut self) {
+        self.set_auth_enabled(false);
+    }
+
+    fn set_auth_enabled(&mut self, enabled: bool) {
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.put(&self.auth_objid, "enabled", enabled)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?enabled, "document changed in set_auth_enabled");
+        self.document_changed();
+    }
+
+    /// Get the users or roles map in the auth object.
+    fn auth_map(&self, map: &str) -> ObjId {
+        self.am
+            .document()
+            .get(&self.auth_objid, map)
+            .unwrap()
+            .unwrap()
+            .1
+    }
+
+    /// Get the object for the named user or role.
+    fn auth_entry(&self, map: &str, name: &str) -> Option<ObjId> {
+        match self.am.document().get(self.auth_map(map), name).unwrap() {
+            Some((automerge::Value::Object(ObjType::Map), obj)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    /// Add a user, with the bcrypt hash of their password if they have one.
+    pub fn add_user(&mut self, name: &str, password: Option<String>) -> crate::Result<()> {
+        if name.is_empty() {
+            return Err(crate::Error::UserEmpty);
+        }
+        if self.auth_entry("users", name).is_some() {
+            return Err(crate::Error::UserAlreadyExists);
+        }
+
+        let users = self.auth_map("users");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let user = txn.put_object(&users, name, ObjType::Map)?;
+                match password {
+                    Some(password) => txn.put(&user, "password", password)?,
+                    None => txn.put(&user, "password", ())?,
+                }
+                txn.put_object(&user, "roles", ObjType::Map)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in add_user");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> crate::Result<User> {
+        let user = self
+            .auth_entry("users", name)
+            .ok_or(crate::Error::UserNotFound)?;
+        let document = self.am.document();
+        let password = document
+            .get(&user, "password")
+            .unwrap()
+            .and_then(|(password, _)| password.into_string().ok());
+        let (_, roles) = document.get(&user, "roles").unwrap().unwrap();
+        Ok(User {
+            name: name.to_owned(),
+            password,
+            roles: document.keys(&roles).collect(),
+        })
+    }
+
+    /// List the names of all users, in order.
+    pub fn list_users(&self) -> Vec<String> {
+        self.am.document().keys(self.auth_map("users")).collect()
+    }
+
+    pub fn delete_user(&mut self, name: &str) -> crate::Result<()> {
+        // the root user is needed while auth is enabled
+        if self.auth_enabled() && name == ROOT_USER {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        if self.auth_entry("users", name).is_none() {
+            return Err(crate::Error::UserNotFound);
+        }
+
+        let users = self.auth_map("users");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.delete(&users, name))
+            .unwrap();
+        debug!("document changed in delete_user");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Change the bcrypt hash of the user's password.
+    pub fn change_password(&mut self, name: &str, password: Option<String>) -> crate::Result<()> {
+        let user = self
+            .auth_entry("users", name)
+            .ok_or(crate::Error::UserNotFound)?;
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                match password {
+                    Some(password) => txn.put(&user, "password", password)?,
+                    None => txn.put(&user, "password", ())?,
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in change_password");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn grant_role(&mut self, user: &str, role: &str) -> crate::Result<()> {
+        let user = self
+            .auth_entry("users", user)
+            .ok_or(crate::Error::UserNotFound)?;
+        // the root role doesn't need to be added before it is granted
+        if role != ROOT_ROLE && self.auth_entry("roles", role).is_none() {
+            return Err(crate::Error::RoleNotFound);
+        }
+        let (_, roles) = self.am.document().get(&user, "roles").unwrap().unwrap();
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.put(&roles, role, ()))
+            .unwrap();
+        debug!("document changed in grant_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn revoke_role(&mut self, user: &str, role: &str) -> crate::Result<()> {
+        // the root user needs to keep the root role while auth is enabled
+        if self.auth_enabled() && user == ROOT_USER && role == ROOT_ROLE {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        let user = self
+            .auth_entry("users", user)
+            .ok_or(crate::Error::UserNotFound)?;
+        let (_, roles) = self.am.document().get(&user, "roles").unwrap().unwrap();
+        if self.am.document().get(&roles, role).unwrap().is_none() {
+            return Err(crate::Error::RoleNotGranted);
+        }
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.delete(&roles, role))
+            .unwrap();
+        debug!("document changed in revoke_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn add_role(&mut self, name: &str) -> crate::Result<()> {
+        if name.is_empty() {
+            return Err(crate::Error::RoleEmpty);
+        }
+        if self.auth_entry("roles", name).is_some() {
+            return Err(crate::Error::RoleAlreadyExists);
+        }
+
+        let roles = self.auth_map("roles");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let role = txn.put_object(&roles, name, ObjType::Map)?;
+                txn.put_object(&role, "permissions", ObjType::Map)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in add_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Get the permissions granted to the role.
+    pub fn get_role(&self, name: &str) -> crate::Result<Vec<Permission>> {
+        let role = self
+            .auth_entry("roles", name)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let document = self.am.document();
+        let (_, permissions) = document.get(&role, "permissions").unwrap().unwrap();
+        let mut result = Vec::new();
+        for (key, _, key_obj) in document.map_range(&permissions, ..) {
+            for (range_end, perm_type, _) in document.map_range(&key_obj, ..) {
+                let perm_type = perm_type.to_i64().unwrap() as i32;
+                // only known types are granted, skip anything else rather than failing
+                let Some(perm_type) =
+                    mergeable_proto::authpb::permission::Type::from_i32(perm_type)
+                else {
+                    continue;
+                };
+                result.push(Permission {
+                    perm_type: perm_type.into(),
+                    key: key.to_owned(),
+                    range_end: if range_end.is_empty() {
+                        None
+                    } else {
+                        Some(range_end.to_owned())
+                    },
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    /// List the names of all roles, in order.
+    pub fn list_roles(&self) -> Vec<String> {
+        self.am.document().keys(self.auth_map("roles")).collect()
+    }
+
+    /// Delete the role, revoking it from any users that have it.
+    pub fn delete_role(&mut self, name: &str) -> crate::Result<()> {
+        // the root role is needed while auth is enabled
+        if self.auth_enabled() && name == ROOT_ROLE {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        if self.auth_entry("roles", name).is_none() {
+            return Err(crate::Error::RoleNotFound);
+        }
+
+        let roles = self.auth_map("roles");
+        let user_roles = self
+            .list_users()
+            .into_iter()
+            .filter_map(|user| {
+                let user = self.auth_entry("users", &user)?;
+                let (_, roles) = self.am.document().get(&user, "roles").unwrap()?;
+                self.am.document().get(&roles, name).unwrap()?;
+                Some(roles)
+            })
+            .collect::<Vec<_>>();
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&roles, name)?;
+                for user_roles in &user_roles {
+                    txn.delete(user_roles, name)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in delete_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Grant the permission to the role, replacing any existing one for the same range.
+    pub fn grant_permission(&mut self, role: &str, permission: Permission) -> crate::Result<()> {
+        let role = self
+            .auth_entry("roles", role)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let (_, permissions) = self
+            .am
+            .document()
+            .get(&role, "permissions")
+            .unwrap()
+            .unwrap();
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let key_obj = if let Some((_, key_obj)) = txn.get(&permissions, &permission.key)? {
+                    key_obj
+                } else {
+                    txn.put_object(&permissions, &permission.key, ObjType::Map)?
+                };
+                let perm_type =
+                    mergeable_proto::authpb::permission::Type::from(permission.perm_type);
+                txn.put(
+                    &key_obj,
+                    permission.range_end.as_deref().unwrap_or_default(),
+                    perm_type as i64,
+                )?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in grant_permission");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Revoke the permission for the range `[key, range_end)` from the role.
+    pub fn revoke_permission(
+        &mut self,
+        role: &str,
+        key: &str,
+        range_end: Option<&str>,
+    ) -> crate::Result<()> {
+        let role = self
+            .auth_entry("roles", role)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let document = self.am.document();
+        let (_, permissions) = document.get(&role, "permissions").unwrap().unwrap();
+        let range_end = range_end.unwrap_or_default();
+        let (_, key_obj) = document
+            .get(&permissions, key)
+            .unwrap()
+            .ok_or(crate::Error::PermissionNotGranted)?;
+        if document.get(&key_obj, range_end).unwrap().is_none() {
+            return Err(crate::Error::PermissionNotGranted);
+        }
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&key_obj, range_end)?;
+                if txn.length(&key_obj) == 0 {
+                    txn.delete(&permissions, key)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in revoke_permission");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Check that the user has the root role, needed to manage auth and the cluster.
+    pub fn check_admin(&self, user: &str) -> crate::Result<()> {
+        let user = self
+            .get_user(user)
+            .map_err(|_| crate::Error::PermissionDenied)?;
+        if user.roles.iter().any(|role| role == ROOT_ROLE) {
+            Ok(())
+        } else {
+            Err(crate::Error::PermissionDenied)
+        }
+    }
+
+    /// Check that the user's roles give them the access to every key in the range
+    /// `[key, range_end)`.
+    pub fn check_permission(
+        &self,
+        user: &str,
+        key: &str,
+        range_end: Option<&str>,
+        access: PermissionType,
+    ) -> crate::Result<()> {
+        let user = self
+            .get_user(user)
+            .map_err(|_| crate::Error::PermissionDenied)?;
+        if user.roles.iter().any(|role| role == ROOT_ROLE) {
+            return Ok(());
+        }
+
+        let permissions = user
+            .roles
+            .iter()
+            .filter_map(|role| self.get_role(role).ok())
+            .flatten()
+            .filter(|permission| permission.perm_type.allows(access))
+            .collect::<Vec<_>>();
+        if crate::auth::covers(permissions.iter(), key, range_end) {
+            Ok(())
+        } else {
+            Err(crate::Error::PermissionDenied)
+        }
+    }
+
+    pub fn check_range_permission(&self, user: &str, request: &RangeRequest) -> crate::Result<()> {
+        self.check_permission(
+            user,
+            &request.start,
+            request.end.as_deref(),
+            PermissionType::Read,
+        )
+    }
+
+    pub fn check_put_permission(&self, user: &str, request: &PutRequest<V>) -> crate::Result<()> {
+        self.check_permission(user, &request.key, None, PermissionType::Write)?;
+        // returning the previous value needs read access too
+        if request.prev_kv {
+            self.check_permission(user, &request.key, None, PermissionType::Read)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_delete_range_permission(
+        &self,
+        user: &str,
+        request: &DeleteRangeRequest,
+    ) -> crate::Result<()> {
+        let end = request.end.as_deref();
+        self.check_permission(user, &request.start, end, PermissionType::Write)?;
+        if request.prev_kv {
+            self.check_permission(user, &request.start, end, PermissionType::Read)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the user has the access needed for all of the comparisons and operations in a
+    /// txn request.
+    pub fn check_txn_permission(&self, user: &str, request: &TxnRequest<V>) -> crate::Result<()> {
+        for compare in &request.compare {
+            self.check_permission(
+                user,
+                &compare.key,
+                compare.range_end.as_deref(),
+                PermissionType::Read,
+            )?;
+        }
+        for op in request.success.iter().chain(request.failure.iter()) {
+            match op {
+                KvRequest::Range(range) => self.check_range_permission(user, range)?,
+                KvRequest::Put(put) => self.check_put_permission(user, put)?,
+                KvRequest::DeleteRange(delete_range) => {
+                    self.check_delete_range_permission(user, delete_range)?
+                }
+                KvRequest::Txn(txn) => self.check_txn_permission(user, txn)?,
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Make a lease id into a string by padding it with zeros
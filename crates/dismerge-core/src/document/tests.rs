@@ -4,6 +4,11 @@ use test_log::test;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
+use crate::alarm::Alarm;
+use crate::alarm::AlarmType;
+use crate::auth::Permission;
+use crate::auth::PermissionType;
+use crate::auth::User;
 use crate::req_resp::Compare;
 use crate::syncer::LocalSyncer;
 use crate::value::Bytes;
@@ -12,6 +17,9 @@ use crate::CompareResult;
 use crate::CompareTarget;
 use crate::DocumentBuilder;
 use crate::KvRequest;
+use crate::SortOrder;
+use crate::SortTarget;
+use crate::WatchFilter;
 use crate::WatchServer;
 
 use insta::assert_debug_snapshot;
@@ -69,6 +77,7 @@ async fn write_value() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -166,6 +175,7 @@ async fn write_value() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -215,6 +225,7 @@ async fn write_value() {
             heads: first_put.0.heads,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -307,6 +318,7 @@ async fn delete_value() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -414,6 +426,7 @@ async fn delete_value() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -443,6 +456,7 @@ async fn delete_value() {
             heads: old_heads,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -616,6 +630,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -666,6 +681,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -716,6 +732,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -766,6 +783,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -817,6 +835,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -846,6 +865,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -936,6 +956,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -986,6 +1007,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1056,6 +1078,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1085,6 +1108,7 @@ async fn range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1237,6 +1261,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1287,6 +1312,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1337,6 +1363,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1387,6 +1414,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1438,6 +1466,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1467,6 +1496,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1557,6 +1587,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1607,6 +1638,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1677,6 +1709,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1706,6 +1739,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1806,6 +1840,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1835,6 +1870,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1885,6 +1921,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1935,6 +1972,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1985,6 +2023,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2014,6 +2053,7 @@ async fn remove_range() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2220,6 +2260,7 @@ async fn transaction() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Put(PutRequest {
                     key: key.clone(),
@@ -2233,6 +2274,7 @@ async fn transaction() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::DeleteRange(DeleteRangeRequest {
                     start: key.clone(),
@@ -2349,6 +2391,7 @@ async fn transaction() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Put(PutRequest {
                     key: key.clone(),
@@ -2362,6 +2405,7 @@ async fn transaction() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::DeleteRange(DeleteRangeRequest {
                     start: key.clone(),
@@ -2507,6 +2551,7 @@ async fn transaction_single_heads() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Range(RangeRequest {
                     start: key2.clone(),
@@ -2514,6 +2559,7 @@ async fn transaction_single_heads() {
                     heads: vec![],
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
             ],
             failure: vec![]
@@ -2646,6 +2692,7 @@ async fn transaction_no_modification() {
                 heads: vec![],
                 limit: None,
                 count_only: false,
+                ..Default::default()
             }),],
             failure: vec![]
         })
@@ -2715,6 +2762,7 @@ async fn transaction_no_modification() {
                 heads: vec![],
                 limit: None,
                 count_only: false,
+                ..Default::default()
             }),],
             failure: vec![]
         })
@@ -2827,6 +2875,7 @@ async fn sync_two_documents() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2843,6 +2892,7 @@ async fn sync_two_documents() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2945,6 +2995,7 @@ async fn sync_two_documents_conflicting_puts_same_heads() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2958,6 +3009,7 @@ async fn sync_two_documents_conflicting_puts_same_heads() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3090,6 +3142,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3121,6 +3174,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             heads: vec![],
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3206,6 +3260,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             // revision: Some(2),
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3536,7 +3591,9 @@ async fn watch_server_value_creation() {
             Some(key3.clone()),
             false,
             vec![],
-            sender,
+            vec![],
+            None,
+            sender.clone(),
         )
         .await
         .unwrap();
@@ -3840,7 +3897,8 @@ async fn watch_server_value_creation() {
     "###
     );
 
-    watch_server.remove_watch(watch_id);
+    watch_server.remove_watch(watch_id, &sender);
+    drop(sender);
 
     for (header, event) in std::mem::take(&mut *events.lock().await) {
         watch_server.receive_event(header, event).await
@@ -3905,6 +3963,8 @@ async fn sync_two_documents_trigger_watches() {
             Some(key2.clone()),
             true,
             vec![],
+            vec![],
+            None,
             sender1,
         )
         .await
@@ -3917,6 +3977,8 @@ async fn sync_two_documents_trigger_watches() {
             Some(key2.clone()),
             true,
             vec![],
+            vec![],
+            None,
             sender2,
         )
         .await
@@ -4450,7 +4512,7 @@ async fn add_other_member() {
     "###
     );
 
-    let _member = doc.add_member(vec![], 2).await;
+    let _member = doc.add_member(vec![], 2, false).await;
     assert_debug_snapshot!(
         doc.list_members().unwrap(),
         @r###"
@@ -4474,6 +4536,37 @@ async fn add_other_member() {
     );
 }
 
+#[tokio::test]
+async fn update_member() {
+    let mut doc = single_node_doc().build();
+    doc.add_member(vec!["http://a:2380".to_owned()], 2, false)
+        .await;
+    doc.add_member(vec!["http://b:2380".to_owned()], 3, false)
+        .await;
+
+    let member = doc
+        .update_member(2, vec!["http://c:2380".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(member.peer_ur_ls, vec!["http://c:2380".to_owned()]);
+    assert_eq!(doc.get_member(2), Some(member));
+
+    // urls can't be shared with other members
+    let result = doc.update_member(2, vec!["http://b:2380".to_owned()]).await;
+    assert!(matches!(result, Err(crate::Error::PeerUrlExists)));
+
+    // and there has to be at least one
+    let result = doc.update_member(2, vec![]).await;
+    assert!(matches!(result, Err(crate::Error::MemberBadUrls)));
+    assert_eq!(
+        doc.get_member(2).unwrap().peer_ur_ls,
+        vec!["http://c:2380".to_owned()]
+    );
+
+    let result = doc.update_member(4, vec!["http://d:2380".to_owned()]).await;
+    assert!(matches!(result, Err(crate::Error::MemberNotFound)));
+}
+
 #[tokio::test]
 async fn cluster_startup_2() {
     let id1 = 1;
@@ -5083,6 +5176,62 @@ async fn cluster_startup_3() {
     "###);
 }
 
+#[tokio::test]
+async fn remove_member() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+    syncer1.sync_all().await;
+    let heads = doc2.lock().await.heads();
+    doc1.lock().await.peer_heads.insert(id2, heads);
+
+    doc1.lock().await.remove_member(id2).await.unwrap();
+    {
+        let doc1 = doc1.lock().await;
+        let member_ids = doc1
+            .list_members()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+        assert_eq!(member_ids, vec![id1]);
+        assert!(doc1.is_member_removed(id2));
+        assert!(!doc1.is_member_removed(id1));
+        assert!(!doc1.peer_heads.contains_key(&id2));
+    }
+
+    // it can't be removed twice
+    let result = doc1.lock().await.remove_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::MemberNotFound)));
+
+    // the other members learn of the removal through syncing
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.is_member_removed(id2));
+    assert_eq!(doc2.lock().await.get_member(id2), None);
+}
+
 #[tokio::test]
 async fn range_limited() {
     let mut doc = single_node_doc().build();
@@ -5211,6 +5360,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5261,6 +5411,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5311,6 +5462,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5361,6 +5513,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5412,6 +5565,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5441,6 +5595,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5491,6 +5646,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5541,6 +5697,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5591,6 +5748,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5620,6 +5778,7 @@ async fn range_limited() {
             heads: vec![],
             limit: Some(1),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5771,7 +5930,8 @@ async fn range_count_only() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5800,7 +5960,8 @@ async fn range_count_only() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5829,7 +5990,8 @@ async fn range_count_only() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5858,7 +6020,8 @@ async fn range_count_only() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5888,7 +6051,8 @@ async fn range_count_only() {
             end: Some(key4.clone()),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5917,7 +6081,8 @@ async fn range_count_only() {
             end: Some(key4),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5946,7 +6111,8 @@ async fn range_count_only() {
             end: Some(key2.clone()),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -5975,7 +6141,8 @@ async fn range_count_only() {
             end: Some(key3.clone()),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -6004,7 +6171,8 @@ async fn range_count_only() {
             end: Some(key1),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -6033,7 +6201,8 @@ async fn range_count_only() {
             end: Some(key2),
             heads: vec![],
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -6155,8 +6324,10 @@ async fn watch_server_value_creation_start_heads() {
             key1.clone(),
             Some(key3.clone()),
             true, // prev_kv
+            vec![],
             start_heads,
-            sender,
+            None,
+            sender.clone(),
         )
         .await
         .unwrap();
@@ -6301,7 +6472,8 @@ async fn watch_server_value_creation_start_heads() {
         ))"
     );
 
-    watch_server.remove_watch(watch_id);
+    watch_server.remove_watch(watch_id, &sender);
+    drop(sender);
 
     for (header, event) in std::mem::take(&mut *events.lock().await) {
         watch_server.receive_event(header, event).await
@@ -6342,6 +6514,7 @@ async fn txn_compare() {
                 heads: vec![],
                 limit: None,
                 count_only: false,
+                ..Default::default()
             })],
         })
         .await
@@ -6387,6 +6560,7 @@ async fn txn_compare() {
                 heads: vec![],
                 limit: None,
                 count_only: false,
+                ..Default::default()
             })],
         })
         .await
@@ -6463,8 +6637,17 @@ async fn remove_lease() {
     let mut doc = single_node_doc().build();
 
     let (id, _ttl) = doc.add_lease(None, None, 2023).unwrap();
+    assert_eq!(doc.all_lease_ids().unwrap(), vec![id]);
 
     doc.remove_lease(id).await;
+
+    // the revoked lease is left behind as null but is gone as far as clients can tell
+    assert_eq!(doc.last_lease_refresh(id), None);
+    assert_eq!(doc.granted_lease_ttl(id), None);
+    assert_eq!(doc.remaining_lease_ttl(id, 2023), None);
+    assert_eq!(doc.keys_for_lease(id), Vec::<String>::new());
+    assert_eq!(doc.all_lease_ids().unwrap(), Vec::<i64>::new());
+    assert_eq!(doc.refresh_lease(id, 2024), 0);
 }
 
 #[test]
@@ -6483,6 +6666,24 @@ fn refresh_lease() {
     assert!(second_refresh > first_refresh);
 }
 
+#[test]
+fn remaining_lease_ttl() {
+    let mut doc = single_node_doc().build();
+
+    let (id, ttl) = doc.add_lease(None, Some(20), 2023).unwrap();
+    assert_eq!(doc.remaining_lease_ttl(id, 2023), Some(ttl));
+    assert_eq!(doc.remaining_lease_ttl(id, 2030), Some(13));
+
+    // refreshing starts the ttl again
+    doc.refresh_lease(id, 2030);
+    assert_eq!(doc.remaining_lease_ttl(id, 2030), Some(20));
+
+    // an expired lease that is yet to be revoked has no time left
+    assert_eq!(doc.remaining_lease_ttl(id, 2100), Some(0));
+
+    assert_eq!(doc.remaining_lease_ttl(id + 1, 2030), None);
+}
+
 #[tokio::test]
 async fn kv_leases() {
     let mut doc = single_node_doc().build();
@@ -6509,7 +6710,8 @@ async fn kv_leases() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: false
+            count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -6554,7 +6756,8 @@ async fn kv_leases() {
             end: None,
             heads: vec![],
             limit: None,
-            count_only: false
+            count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -6735,3 +6938,628 @@ async fn replication_status_double_node() {
     }
     "###);
 }
+
+#[tokio::test]
+async fn learner_member() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let member = doc1.lock().await.add_member(vec![], id2, true).await;
+    assert!(member.is_learner);
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+
+    // it can't be promoted until it has caught up
+    let result = doc1.lock().await.promote_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::LearnerNotReady)));
+
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.get_member(id2).unwrap().is_learner);
+
+    // learners don't take writes
+    let result = doc2
+        .lock()
+        .await
+        .put(PutRequest {
+            key: "key1".to_owned(),
+            value: Bytes::from(b"value1".to_vec()),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await;
+    assert!(matches!(result, Err(crate::Error::NotSupportedForLearner)));
+
+    // or count towards replication
+    let heads = doc1.lock().await.heads();
+    let replication_status = doc1.lock().await.replication_status(&heads);
+    assert_debug_snapshot!(replication_status, @r###"
+    {
+        1: true,
+    }
+    "###);
+
+    let member = doc1.lock().await.promote_member(id2).await.unwrap();
+    assert!(!member.is_learner);
+    let result = doc1.lock().await.promote_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::MemberNotLearner)));
+
+    // once promoted it takes writes like any other member
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.check_not_learner().is_ok());
+}
+
+#[tokio::test]
+async fn range_sorted() {
+    let mut doc = single_node_doc().build();
+
+    for (key, value) in [("key1", b"c"), ("key2", b"a"), ("key3", b"b")] {
+        doc.put(PutRequest {
+            key: key.to_owned(),
+            value: Bytes::from(value.to_vec()),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    let mut sorted_keys = |sort_order, sort_target, limit| {
+        let receiver = doc
+            .range(RangeRequest {
+                start: "key1".to_owned(),
+                end: Some("key4".to_owned()),
+                limit,
+                sort_order,
+                sort_target,
+                ..Default::default()
+            })
+            .unwrap();
+        async move {
+            receiver
+                .await
+                .unwrap()
+                .1
+                .values
+                .into_iter()
+                .map(|kv| kv.key)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Key, None).await,
+        vec!["key3", "key2", "key1"]
+    );
+    // versions are all equal so stay in key order
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Version, None).await,
+        vec!["key1", "key2", "key3"]
+    );
+    // limit applies after sorting
+    assert_eq!(
+        sorted_keys(SortOrder::None, SortTarget::Value, Some(2)).await,
+        vec!["key2", "key3"]
+    );
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Value, Some(1)).await,
+        vec!["key1"]
+    );
+}
+
+#[tokio::test]
+async fn auth_permissions() {
+    let mut doc = single_node_doc().build();
+    assert!(!doc.auth_enabled());
+
+    assert!(matches!(
+        doc.enable_auth(),
+        Err(crate::Error::RootUserNotExist)
+    ));
+    doc.add_user("root", Some("hash".to_owned())).unwrap();
+    assert!(matches!(
+        doc.enable_auth(),
+        Err(crate::Error::RootRoleNotExist)
+    ));
+    doc.grant_role("root", "root").unwrap();
+    doc.enable_auth().unwrap();
+    assert!(doc.auth_enabled());
+    assert!(matches!(
+        doc.revoke_role("root", "root"),
+        Err(crate::Error::InvalidAuthMgmt)
+    ));
+
+    doc.add_user("user", None).unwrap();
+    assert!(matches!(
+        doc.add_user("user", None),
+        Err(crate::Error::UserAlreadyExists)
+    ));
+    assert!(matches!(
+        doc.grant_role("user", "reader"),
+        Err(crate::Error::RoleNotFound)
+    ));
+    doc.add_role("reader").unwrap();
+    doc.grant_role("user", "reader").unwrap();
+    assert_eq!(
+        doc.get_user("user").unwrap(),
+        User {
+            name: "user".to_owned(),
+            password: None,
+            roles: vec!["reader".to_owned()],
+        }
+    );
+    assert_eq!(doc.list_users(), vec!["root".to_owned(), "user".to_owned()]);
+
+    let read = |key: &str, range_end: Option<&str>| Permission {
+        perm_type: PermissionType::Read,
+        key: key.to_owned(),
+        range_end: range_end.map(|range_end| range_end.to_owned()),
+    };
+    doc.grant_permission("reader", read("a", Some("c")))
+        .unwrap();
+    doc.grant_permission("reader", read("c", Some("e")))
+        .unwrap();
+    doc.grant_permission("reader", read("x", None)).unwrap();
+    assert_eq!(
+        doc.get_role("reader").unwrap(),
+        vec![read("a", Some("c")), read("c", Some("e")), read("x", None)]
+    );
+
+    let check = |doc: &Document<_, _, _, _>, user, key: &str, range_end: Option<&str>, access| {
+        doc.check_permission(user, key, range_end, access).is_ok()
+    };
+    // root can do anything
+    assert!(check(&doc, "root", "z", None, PermissionType::Write));
+    assert!(check(&doc, "user", "b", None, PermissionType::Read));
+    assert!(!check(&doc, "user", "b", None, PermissionType::Write));
+    // adjacent permissions cover the range between them
+    assert!(check(&doc, "user", "a", Some("e"), PermissionType::Read));
+    assert!(!check(&doc, "user", "a", Some("f"), PermissionType::Read));
+    assert!(check(&doc, "user", "x", None, PermissionType::Read));
+    assert!(!check(&doc, "user", "x", Some("y"), PermissionType::Read));
+    assert!(!check(&doc, "user", "a", Some("\0"), PermissionType::Read));
+    assert!(!check(&doc, "missing", "a", None, PermissionType::Read));
+
+    doc.revoke_permission("reader", "c", Some("e")).unwrap();
+    assert!(matches!(
+        doc.revoke_permission("reader", "c", Some("e")),
+        Err(crate::Error::PermissionNotGranted)
+    ));
+    assert!(!check(&doc, "user", "a", Some("e"), PermissionType::Read));
+
+    doc.delete_role("reader").unwrap();
+    assert_eq!(doc.get_user("user").unwrap().roles, Vec::<String>::new());
+    assert!(!check(&doc, "user", "b", None, PermissionType::Read));
+    assert!(matches!(
+        doc.delete_user("root"),
+        Err(crate::Error::InvalidAuthMgmt)
+    ));
+    doc.delete_user("user").unwrap();
+    assert_eq!(doc.list_users(), vec!["root".to_owned()]);
+}
+
+#[tokio::test]
+async fn snapshot_restore() {
+    let mut doc = single_node_doc().build();
+    let key = "key1".to_owned();
+    let value = Bytes::from(b"value1".to_vec());
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    let snapshot = doc.snapshot();
+
+    // seed a new node's storage with the snapshot
+    let mut persister = MemoryPersister::default();
+    persister.set_document(snapshot).unwrap();
+    let mut restored = TestDocumentBuilder::default()
+        .with_persister(persister)
+        .with_member_id(2)
+        .build();
+
+    assert_eq!(restored.cluster_id(), Some(1));
+    let (_, response) = restored
+        .range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(response.values.len(), 1);
+    assert_eq!(response.values[0].key, key);
+    assert_eq!(
+        Vec::<u8>::from(response.values[0].value.clone()),
+        Vec::<u8>::from(value)
+    );
+}
+
+#[tokio::test]
+async fn load_document_without_auth() {
+    // a document written before there was any auth
+    let mut old = automerge::AutoCommit::new();
+    for map in ["kvs", "cluster", "members", "leases"] {
+        old.put_object(ROOT, map, ObjType::Map).unwrap();
+    }
+
+    let mut persister = MemoryPersister::default();
+    persister.set_document(old.save()).unwrap();
+    let mut doc = TestDocumentBuilder::default()
+        .with_persister(persister)
+        .build();
+
+    assert!(!doc.auth_enabled());
+    assert_eq!(doc.list_users(), Vec::<String>::new());
+    doc.add_user("user", None).unwrap();
+    assert_eq!(doc.list_users(), vec!["user".to_owned()]);
+}
+
+#[tokio::test]
+async fn hash_kv() {
+    let mut doc = single_node_doc().build();
+    let key = "key1".to_owned();
+    let value = Bytes::from(b"value1".to_vec());
+
+    let empty = doc.hash_kv(&[]);
+    let heads = doc.heads();
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    assert_ne!(doc.hash_kv(&[]), empty);
+    assert_eq!(doc.hash_kv(&heads), empty);
+}
+
+#[tokio::test]
+async fn alarm_no_space() {
+    let mut doc = single_node_doc().build();
+    let key = "key1".to_owned();
+    let value = Bytes::from(b"value1".to_vec());
+    let put = || PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    };
+    let alarm = Alarm {
+        member_id: 1,
+        alarm: AlarmType::NoSpace,
+    };
+
+    assert!(doc.activate_alarm(alarm));
+    assert!(!doc.activate_alarm(alarm));
+    assert_eq!(doc.alarms(), vec![alarm]);
+
+    assert!(matches!(doc.put(put()).await, Err(crate::Error::NoSpace)));
+    assert!(matches!(
+        doc.txn(TxnRequest {
+            compare: vec![],
+            success: vec![KvRequest::Put(put())],
+            failure: vec![],
+        })
+        .await,
+        Err(crate::Error::NoSpace)
+    ));
+    // reads and deletes don't take up more space
+    doc.txn(TxnRequest {
+        compare: vec![],
+        success: vec![KvRequest::Range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })],
+        failure: vec![],
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    assert!(doc.deactivate_alarm(alarm));
+    assert!(!doc.deactivate_alarm(alarm));
+    assert_eq!(doc.alarms(), vec![]);
+    doc.put(put()).await.unwrap().await.unwrap();
+}
+
+#[tokio::test]
+async fn defragment() {
+    let mut doc = single_node_doc().build();
+    let key = "key1".to_owned();
+    let value = Bytes::from(b"value1".to_vec());
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    let (_, after) = doc.defragment();
+    assert_eq!(after.changes, 0);
+
+    let (_, response) = doc
+        .range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(response.values.len(), 1);
+}
+
+#[tokio::test]
+async fn watch_filters() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let watcher = TestWatcher {
+        events: Arc::clone(&events),
+    };
+
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc()
+        .with_in_memory()
+        .with_watcher(watcher)
+        .build();
+    let key1 = "key1".to_owned();
+    let value = Bytes::from(b"value".to_vec());
+
+    let (sender, mut receiver) = mpsc::channel(100);
+    watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![WatchFilter::NoPut],
+            vec![],
+            None,
+            sender,
+        )
+        .await
+        .unwrap();
+
+    doc.put(PutRequest {
+        key: key1.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+    doc.delete_range(DeleteRangeRequest {
+        start: key1.clone(),
+        end: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    for (header, event) in std::mem::take(&mut *events.lock().await) {
+        watch_server.receive_event(header, event).await
+    }
+
+    let (_, _, event) = receiver.try_recv().unwrap();
+    assert!(matches!(
+        event.typ,
+        crate::watcher::WatchEventType::Delete(key, _) if key == key1
+    ));
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn watch_ids_per_stream() {
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().build();
+    let key1 = "key1".to_owned();
+
+    let (sender1, _receiver1) = mpsc::channel(100);
+    let (sender2, _receiver2) = mpsc::channel(100);
+
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            Some(1),
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+
+    // the same stream can't reuse an id
+    let result = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            Some(1),
+            sender1.clone(),
+        )
+        .await;
+    assert!(matches!(result, Err(crate::Error::DuplicateWatchId)));
+
+    // but other streams can
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            Some(1),
+            sender2.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+
+    // picked ids skip the ones the stream has chosen
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            None,
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 2);
+
+    // removing the watch frees its id on that stream
+    watch_server.remove_watch(1, &sender1);
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            Some(1),
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+}
+
+#[tokio::test]
+async fn watch_unknown_heads() {
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().build();
+    let key1 = "key1".to_owned();
+
+    let (sender, _receiver) = mpsc::channel(100);
+    // the history can't be replayed from heads the document doesn't have
+    let result = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![ChangeHash([1; 32])],
+            None,
+            sender,
+        )
+        .await;
+    assert!(matches!(result, Err(crate::Error::Compacted)));
+}
+
+#[tokio::test]
+async fn watch_slow_cancelled() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let watcher = TestWatcher {
+        events: Arc::clone(&events),
+    };
+
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc()
+        .with_in_memory()
+        .with_watcher(watcher)
+        .build();
+    let key1 = "key1".to_owned();
+    let value = Bytes::from(b"value".to_vec());
+
+    // a stream with no room for more than a single event
+    let (sender, _receiver) = mpsc::channel(1);
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            vec![],
+            None,
+            sender.clone(),
+        )
+        .await
+        .unwrap();
+
+    for _ in 0..crate::watch_server::MAX_BUFFERED_EVENTS + 2 {
+        doc.put(PutRequest {
+            key: key1.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    // handing the events over doesn't wait on the stream
+    for (header, event) in std::mem::take(&mut *events.lock().await) {
+        watch_server.receive_event(header, event).await
+    }
+
+    // there is no history to catch up from so the watch is cancelled
+    let cancelled = watch_server.take_cancelled(&sender);
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].0, watch_id);
+    assert!(matches!(cancelled[0].1, crate::Error::WatchTooSlow));
+    assert!(watch_server.take_cancelled(&sender).is_empty());
+}
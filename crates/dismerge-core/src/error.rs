@@ -6,13 +6,107 @@ pub enum Error {
     NotReady,
     #[error("failed to parse key as member id: {0}")]
     NotParseableAsId(String),
+    #[error("mvcc: required revision has been compacted")]
+    Compacted,
+    #[error("root user does not exist")]
+    RootUserNotExist,
+    #[error("root user does not have root role")]
+    RootRoleNotExist,
+    #[error("user name is empty")]
+    UserEmpty,
+    #[error("user name already exists")]
+    UserAlreadyExists,
+    #[error("user name not found")]
+    UserNotFound,
+    #[error("role name is empty")]
+    RoleEmpty,
+    #[error("role name already exists")]
+    RoleAlreadyExists,
+    #[error("role name not found")]
+    RoleNotFound,
+    #[error("role is not granted to the user")]
+    RoleNotGranted,
+    #[error("permission is not granted to the role")]
+    PermissionNotGranted,
+    #[error("invalid auth management")]
+    InvalidAuthMgmt,
+    #[error("authentication is not enabled")]
+    AuthNotEnabled,
+    #[error("authentication failed, invalid user ID or password")]
+    AuthFailed,
+    #[error("invalid auth token")]
+    InvalidAuthToken,
+    #[error("permission denied")]
+    PermissionDenied,
+    #[error("mvcc: database space exceeded")]
+    NoSpace,
+    #[error("mvcc: duplicate watch ID provided on the WatchStream")]
+    DuplicateWatchId,
+    #[error("watch fell too far behind the events")]
+    WatchTooSlow,
+    #[error("member not found")]
+    MemberNotFound,
+    #[error("the member has been permanently removed from the cluster")]
+    MemberRemoved,
+    #[error("Peer URLs already exists")]
+    PeerUrlExists,
+    #[error("given member URLs are invalid")]
+    MemberBadUrls,
+    #[error("can only promote a learner member")]
+    MemberNotLearner,
+    #[error("can only promote a learner member which is in sync with leader")]
+    LearnerNotReady,
+    #[error("rpc not supported for learner")]
+    NotSupportedForLearner,
 }
 
 impl From<Error> for tonic::Status {
     fn from(error: Error) -> Self {
         match error {
             Error::NotReady => tonic::Status::unavailable("node not ready"),
+            Error::NotSupportedForLearner => {
+                tonic::Status::unavailable(format!("etcdserver: {}", error))
+            }
             Error::NotParseableAsId(_) => tonic::Status::internal(error.to_string()),
+            Error::Compacted => tonic::Status::out_of_range(format!("etcdserver: {}", error)),
+            Error::UserEmpty | Error::RoleEmpty | Error::AuthFailed => {
+                tonic::Status::invalid_argument(format!("etcdserver: {}", error))
+            }
+            Error::RootUserNotExist
+            | Error::RootRoleNotExist
+            | Error::UserAlreadyExists
+            | Error::UserNotFound
+            | Error::RoleAlreadyExists
+            | Error::RoleNotFound
+            | Error::RoleNotGranted
+            | Error::PermissionNotGranted
+            | Error::InvalidAuthMgmt
+            | Error::AuthNotEnabled => {
+                tonic::Status::failed_precondition(format!("etcdserver: {}", error))
+            }
+            Error::InvalidAuthToken => {
+                tonic::Status::unauthenticated(format!("etcdserver: {}", error))
+            }
+            Error::PermissionDenied => {
+                tonic::Status::permission_denied(format!("etcdserver: {}", error))
+            }
+            Error::NoSpace => tonic::Status::resource_exhausted(format!("etcdserver: {}", error)),
+            Error::DuplicateWatchId => {
+                tonic::Status::invalid_argument(format!("etcdserver: {}", error))
+            }
+            Error::WatchTooSlow => {
+                tonic::Status::resource_exhausted(format!("etcdserver: {}", error))
+            }
+            Error::MemberNotFound => tonic::Status::not_found(format!("etcdserver: {}", error)),
+            Error::MemberBadUrls => {
+                tonic::Status::invalid_argument(format!("etcdserver: {}", error))
+            }
+            Error::PeerUrlExists
+            | Error::MemberRemoved
+            | Error::MemberNotLearner
+            | Error::LearnerNotReady => {
+                tonic::Status::failed_precondition(format!("etcdserver: {}", error))
+            }
         }
     }
 }
@@ -1,3 +1,5 @@
+pub mod alarm;
+pub mod auth;
 mod builder;
 mod document;
 mod error;
@@ -26,9 +28,12 @@ pub use req_resp::PutRequest;
 pub use req_resp::PutResponse;
 pub use req_resp::RangeRequest;
 pub use req_resp::RangeResponse;
+pub use req_resp::SortOrder;
+pub use req_resp::SortTarget;
 pub use req_resp::TxnRequest;
 pub use req_resp::TxnResponse;
 pub use syncer::Syncer;
+pub use watch_server::WatchFilter;
 pub use watch_server::WatchServer;
 pub use watcher::VecWatcher;
 pub use watcher::WatchEvent;
@@ -23,17 +23,66 @@ impl<V: Value> From<KeyValue<V>> for mergeable_proto::mvccpb::KeyValue {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct RangeRequest {
     pub start: String,
     pub end: Option<String>,
     pub heads: Vec<ChangeHash>,
     pub limit: Option<u64>,
     pub count_only: bool,
+    pub sort_order: SortOrder,
+    pub sort_target: SortTarget,
 }
 
-impl From<mergeable_proto::etcdserverpb::RangeRequest> for RangeRequest {
-    fn from(
+/// The order to return the values of a range in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Leave the values in key order.
+    #[default]
+    None,
+    Ascend,
+    Descend,
+}
+
+impl From<mergeable_proto::etcdserverpb::range_request::SortOrder> for SortOrder {
+    fn from(sort_order: mergeable_proto::etcdserverpb::range_request::SortOrder) -> Self {
+        match sort_order {
+            mergeable_proto::etcdserverpb::range_request::SortOrder::None => SortOrder::None,
+            mergeable_proto::etcdserverpb::range_request::SortOrder::Ascend => SortOrder::Ascend,
+            mergeable_proto::etcdserverpb::range_request::SortOrder::Descend => SortOrder::Descend,
+        }
+    }
+}
+
+/// The field of the values in a range to sort by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortTarget {
+    #[default]
+    Key,
+    /// Values do not have versions so this leaves them in key order.
+    Version,
+    Create,
+    Mod,
+    Value,
+}
+
+impl From<mergeable_proto::etcdserverpb::range_request::SortTarget> for SortTarget {
+    fn from(sort_target: mergeable_proto::etcdserverpb::range_request::SortTarget) -> Self {
+        match sort_target {
+            mergeable_proto::etcdserverpb::range_request::SortTarget::Key => SortTarget::Key,
+            mergeable_proto::etcdserverpb::range_request::SortTarget::Version => {
+                SortTarget::Version
+            }
+            mergeable_proto::etcdserverpb::range_request::SortTarget::Create => SortTarget::Create,
+            mergeable_proto::etcdserverpb::range_request::SortTarget::Mod => SortTarget::Mod,
+            mergeable_proto::etcdserverpb::range_request::SortTarget::Value => SortTarget::Value,
+        }
+    }
+}
+
+impl TryFrom<mergeable_proto::etcdserverpb::RangeRequest> for RangeRequest {
+    type Error = tonic::Status;
+    fn try_from(
         mergeable_proto::etcdserverpb::RangeRequest {
             key,
             range_end,
@@ -50,9 +99,7 @@ impl From<mergeable_proto::etcdserverpb::RangeRequest> for RangeRequest {
             min_create_heads,
             max_create_heads,
         }: mergeable_proto::etcdserverpb::RangeRequest,
-    ) -> Self {
-        assert_eq!(sort_order, 0);
-        assert_eq!(sort_target, 0);
+    ) -> Result<Self, Self::Error> {
         assert!(!serializable);
         assert!(!keys_only);
         assert_eq!(min_mod_heads, 0);
@@ -60,7 +107,7 @@ impl From<mergeable_proto::etcdserverpb::RangeRequest> for RangeRequest {
         assert_eq!(min_create_heads, 0);
         assert_eq!(max_create_heads, 0);
 
-        RangeRequest {
+        Ok(RangeRequest {
             start: String::from_utf8(key).unwrap(),
             end: if range_end.is_empty() {
                 None
@@ -73,7 +120,17 @@ impl From<mergeable_proto::etcdserverpb::RangeRequest> for RangeRequest {
                 .collect(),
             limit: if limit > 0 { Some(limit as u64) } else { None },
             count_only,
-        }
+            sort_order: mergeable_proto::etcdserverpb::range_request::SortOrder::from_i32(
+                sort_order,
+            )
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown sort order"))?
+            .into(),
+            sort_target: mergeable_proto::etcdserverpb::range_request::SortTarget::from_i32(
+                sort_target,
+            )
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown sort target"))?
+            .into(),
+        })
     }
 }
 
@@ -294,11 +351,37 @@ pub struct TxnRequest<V> {
     pub failure: Vec<KvRequest<V>>,
 }
 
+impl<V> TxnRequest<V> {
+    /// Whether the transaction may put any values, whichever branch it takes.
+    pub fn has_puts(&self) -> bool {
+        self.success
+            .iter()
+            .chain(self.failure.iter())
+            .any(|op| match op {
+                KvRequest::Put(_) => true,
+                KvRequest::Range(_) | KvRequest::DeleteRange(_) => false,
+                KvRequest::Txn(txn) => txn.has_puts(),
+            })
+    }
+
+    /// Whether the transaction only reads, whichever branch it takes.
+    pub fn is_read_only(&self) -> bool {
+        self.success
+            .iter()
+            .chain(self.failure.iter())
+            .all(|op| match op {
+                KvRequest::Range(_) => true,
+                KvRequest::Put(_) | KvRequest::DeleteRange(_) => false,
+                KvRequest::Txn(txn) => txn.is_read_only(),
+            })
+    }
+}
+
 impl<V: Value> TryFrom<mergeable_proto::etcdserverpb::TxnRequest> for TxnRequest<V>
 where
     <V as TryFrom<Vec<u8>>>::Error: std::fmt::Debug,
 {
-    type Error = <V as TryFrom<Vec<u8>>>::Error;
+    type Error = tonic::Status;
     fn try_from(
         mergeable_proto::etcdserverpb::TxnRequest {
             compare,
@@ -350,16 +433,18 @@ impl<V: Value> TryFrom<mergeable_proto::etcdserverpb::RequestOp> for KvRequest<V
 where
     <V as TryFrom<Vec<u8>>>::Error: std::fmt::Debug,
 {
-    type Error = <V as TryFrom<Vec<u8>>>::Error;
+    type Error = tonic::Status;
     fn try_from(
         mergeable_proto::etcdserverpb::RequestOp { request }: mergeable_proto::etcdserverpb::RequestOp,
     ) -> Result<Self, Self::Error> {
         let val = match request.unwrap() {
             mergeable_proto::etcdserverpb::request_op::Request::RequestRange(req) => {
-                KvRequest::Range(req.into())
+                KvRequest::Range(req.try_into()?)
             }
             mergeable_proto::etcdserverpb::request_op::Request::RequestPut(req) => {
-                KvRequest::Put(req.try_into()?)
+                KvRequest::Put(req.try_into().map_err(|err| {
+                    tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
+                })?)
             }
             mergeable_proto::etcdserverpb::request_op::Request::RequestDeleteRange(req) => {
                 KvRequest::DeleteRange(req.into())
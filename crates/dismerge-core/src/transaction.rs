@@ -18,6 +18,8 @@ use crate::PutRequest;
 use crate::PutResponse;
 use crate::RangeRequest;
 use crate::RangeResponse;
+use crate::SortOrder;
+use crate::SortTarget;
 use crate::TxnRequest;
 use crate::TxnResponse;
 use crate::VecWatcher;
@@ -324,14 +326,28 @@ pub fn range<R: ReadDoc, V: Value>(txn: &R, request: RangeRequest) -> RangeRespo
         heads,
         limit,
         count_only,
+        sort_order,
+        sort_target,
     } = request;
+    // targets other than the key default to ascending order, as in etcd
+    let sort_order = match (sort_order, sort_target) {
+        (SortOrder::None | SortOrder::Ascend, SortTarget::Key) => SortOrder::None,
+        (SortOrder::None, _) => SortOrder::Ascend,
+        (sort_order, _) => sort_order,
+    };
+    // the limit can only be applied during iteration when the values stay in key order
+    let iter_limit = if sort_order == SortOrder::None {
+        limit
+    } else {
+        None
+    };
     let mut values = Vec::new();
     if let Some((_, kvs)) = automerge::ReadDoc::get(txn, ROOT, "kvs").unwrap() {
         if let Some(end) = &end {
             if heads.is_empty() {
                 let keys = automerge::ReadDoc::map_range(txn, &kvs, start.clone()..end.clone());
                 for (i, (key, _value, key_obj)) in keys.enumerate() {
-                    if let Some(limit) = limit {
+                    if let Some(limit) = iter_limit {
                         if i as u64 == limit {
                             // reached the limit
                             break;
@@ -343,7 +359,7 @@ pub fn range<R: ReadDoc, V: Value>(txn: &R, request: RangeRequest) -> RangeRespo
             } else {
                 let keys = txn.map_range_at(&kvs, start.clone()..end.clone(), &heads);
                 for (i, (key, _value, key_obj)) in keys.enumerate() {
-                    if let Some(limit) = limit {
+                    if let Some(limit) = iter_limit {
                         if i as u64 == limit {
                             // reached the limit
                             break;
@@ -363,6 +379,12 @@ pub fn range<R: ReadDoc, V: Value>(txn: &R, request: RangeRequest) -> RangeRespo
             values.push(value);
         }
     }
+    if sort_order != SortOrder::None {
+        sort_key_values(&mut values, sort_order, sort_target);
+        if let Some(limit) = limit {
+            values.truncate(limit as usize);
+        }
+    }
     let count = values.len();
     if count_only {
         values.clear();
@@ -373,6 +395,8 @@ pub fn range<R: ReadDoc, V: Value>(txn: &R, request: RangeRequest) -> RangeRespo
         ?heads,
         ?limit,
         ?count_only,
+        ?sort_order,
+        ?sort_target,
         ?values,
         ?count,
         "Processed range request"
@@ -380,6 +404,63 @@ pub fn range<R: ReadDoc, V: Value>(txn: &R, request: RangeRequest) -> RangeRespo
     RangeResponse { values, count }
 }
 
+/// Hash all of the live key-values, at the heads if given.
+pub fn hash_kv<R: ReadDoc, V: Value>(txn: &R, heads: &[ChangeHash]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    if let Some((_, kvs)) = automerge::ReadDoc::get(txn, ROOT, "kvs").unwrap() {
+        let values: Vec<KeyValue<V>> = if heads.is_empty() {
+            automerge::ReadDoc::map_range(txn, &kvs, ..)
+                .map(|(key, _value, key_obj)| extract_key_value(txn, key.to_owned(), &key_obj))
+                .collect()
+        } else {
+            txn.map_range_at(&kvs, .., heads)
+                .map(|(key, _value, key_obj)| {
+                    extract_key_value_at(txn, key.to_owned(), &key_obj, heads)
+                })
+                .collect()
+        };
+        for kv in values {
+            let value: Vec<u8> = kv.value.into();
+            // prefix the variable length fields with their lengths so they can't run together
+            hasher.update(&(kv.key.len() as u64).to_be_bytes());
+            hasher.update(kv.key.as_bytes());
+            hasher.update(&(value.len() as u64).to_be_bytes());
+            hasher.update(&value);
+            hasher.update(&kv.create_head.0);
+            hasher.update(&kv.mod_head.0);
+            hasher.update(&kv.lease.unwrap_or_default().to_be_bytes());
+        }
+    }
+    hasher.finalize()
+}
+
+/// Sort the values of a range, they start in ascending key order.
+fn sort_key_values<V: Value>(
+    values: &mut [KeyValue<V>],
+    sort_order: SortOrder,
+    sort_target: SortTarget,
+) {
+    values.sort_by(|a, b| {
+        let ordering = match sort_target {
+            SortTarget::Key => a.key.cmp(&b.key),
+            // there are no versions so they are all equal and stay in key order
+            SortTarget::Version => std::cmp::Ordering::Equal,
+            // heads are only partially ordered so use the hashes to at least be deterministic
+            SortTarget::Create => a.create_head.cmp(&b.create_head),
+            SortTarget::Mod => a.mod_head.cmp(&b.mod_head),
+            SortTarget::Value => {
+                let a: Vec<u8> = a.value.clone().into();
+                let b: Vec<u8> = b.value.clone().into();
+                a.cmp(&b)
+            }
+        };
+        match sort_order {
+            SortOrder::Descend => ordering.reverse(),
+            SortOrder::None | SortOrder::Ascend => ordering,
+        }
+    });
+}
+
 pub fn put<V: Value>(
     txn: &mut Transaction,
     watcher: &mut VecWatcher<V>,
@@ -546,6 +627,7 @@ fn txn_compare<V: Value>(txn: &mut Transaction, compare: Compare) -> bool {
             heads: Vec::new(),
             limit: None,
             count_only: false,
+            ..Default::default()
         },
     );
 
@@ -1,13 +1,46 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use automerge::ChangeHash;
 use automerge_persistent::Persister;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tracing::warn;
 
-use crate::{value::Value, Document, Header, Syncer, WatchEvent, Watcher};
+use crate::{value::Value, watcher::WatchEventType, Document, Header, Syncer, WatchEvent, Watcher};
 
 type WatchId = i64;
 
+/// How many events a watch can hold while its stream is full before it is too far behind and gets
+/// cancelled.
+pub(crate) const MAX_BUFFERED_EVENTS: usize = 1024;
+
+/// Types of events that a watch can ask not to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFilter {
+    NoPut,
+    NoDelete,
+}
+
+impl WatchFilter {
+    fn filters<V>(self, typ: &WatchEventType<V>) -> bool {
+        matches!(
+            (self, typ),
+            (WatchFilter::NoPut, WatchEventType::Put(_))
+                | (WatchFilter::NoDelete, WatchEventType::Delete(..))
+        )
+    }
+}
+
+impl From<mergeable_proto::etcdserverpb::watch_create_request::FilterType> for WatchFilter {
+    fn from(value: mergeable_proto::etcdserverpb::watch_create_request::FilterType) -> Self {
+        match value {
+            mergeable_proto::etcdserverpb::watch_create_request::FilterType::Noput => Self::NoPut,
+            mergeable_proto::etcdserverpb::watch_create_request::FilterType::Nodelete => {
+                Self::NoDelete
+            }
+        }
+    }
+}
+
 struct Watch<V> {
     watch_id: WatchId,
     start: String,
@@ -15,11 +48,65 @@ struct Watch<V> {
     sender: Sender<(WatchId, Header, WatchEvent<V>)>,
     /// Whether to include previous kvs in the events.
     prev_kv: bool,
+    filters: Vec<WatchFilter>,
+    /// Events waiting for room in the stream.
+    buffer: VecDeque<(Header, WatchEvent<V>)>,
+    /// Whether events are being held back, such as while a progress notification is sent.
+    paused: bool,
+}
+
+impl<V> Watch<V> {
+    /// Whether the event is one that this watch asked not to be sent.
+    fn filters(&self, event: &WatchEvent<V>) -> bool {
+        self.filters.iter().any(|filter| filter.filters(&event.typ))
+    }
+
+    fn watches_key(&self, key: &str) -> bool {
+        if let Some(end) = &self.end {
+            self.start.as_str() <= key && key <= end.as_str()
+        } else {
+            self.start == key
+        }
+    }
+
+    /// Queue the event for the stream, returning false if the watch has fallen too far behind.
+    fn push(&mut self, header: Header, mut event: WatchEvent<V>) -> bool {
+        if !self.prev_kv {
+            event.prev_kv = None;
+        }
+        self.buffer.push_back((header, event));
+        self.flush();
+        self.buffer.len() <= MAX_BUFFERED_EVENTS
+    }
+
+    /// Send as many of the buffered events as the stream has room for.
+    fn flush(&mut self) {
+        if self.paused {
+            return;
+        }
+        while let Some((header, event)) = self.buffer.pop_front() {
+            match self.sender.try_send((self.watch_id, header, event)) {
+                Ok(()) => {}
+                Err(TrySendError::Full((_, header, event)))
+                | Err(TrySendError::Closed((_, header, event))) => {
+                    self.buffer.push_front((header, event));
+                    break;
+                }
+            }
+        }
+    }
 }
 
 pub struct WatchServer<V> {
-    watches: HashMap<WatchId, Watch<V>>,
+    /// Watch ids are only unique within a stream, so there can be one watch per stream for each.
+    watches: HashMap<WatchId, Vec<Watch<V>>>,
     max_id: WatchId,
+    /// Watches that were cancelled by the server, for their streams to tell the clients about.
+    cancelled: Vec<(
+        WatchId,
+        Sender<(WatchId, Header, WatchEvent<V>)>,
+        crate::Error,
+    )>,
 }
 
 impl<V> Default for WatchServer<V> {
@@ -27,6 +114,7 @@ impl<V> Default for WatchServer<V> {
         Self {
             watches: Default::default(),
             max_id: Default::default(),
+            cancelled: Default::default(),
         }
     }
 }
@@ -37,13 +125,18 @@ where
 {
     /// Create a new watcher watching the range `[start, end)` and streaming values from the
     /// start_revision to the sender.
+    ///
+    /// The watch gets the given id if there is one, otherwise the next free one for the stream.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_watch<P, S, W>(
         &mut self,
-        _document: &mut Document<P, S, W, V>,
+        document: &mut Document<P, S, W, V>,
         start: String,
         end: Option<String>,
         prev_kv: bool,
+        filters: Vec<WatchFilter>,
         start_heads: Vec<ChangeHash>,
+        watch_id: Option<WatchId>,
         sender: Sender<(WatchId, Header, WatchEvent<V>)>,
     ) -> crate::Result<WatchId>
     where
@@ -51,18 +144,35 @@ where
         S: Syncer,
         W: Watcher<V>,
     {
-        self.max_id += 1;
-        let watch_id = self.max_id;
-        self.watches.insert(
-            watch_id,
-            Watch {
-                watch_id,
-                start,
-                end,
-                sender,
-                prev_kv,
+        // history we don't have can't be replayed, as if it had been compacted
+        if !document.has_heads(&start_heads) {
+            return Err(crate::Error::Compacted);
+        }
+
+        let watch_id = match watch_id {
+            Some(watch_id) => {
+                if self.exists_on_stream(watch_id, &sender) {
+                    return Err(crate::Error::DuplicateWatchId);
+                }
+                watch_id
+            }
+            None => loop {
+                self.max_id += 1;
+                if !self.exists_on_stream(self.max_id, &sender) {
+                    break self.max_id;
+                }
             },
-        );
+        };
+        self.watches.entry(watch_id).or_default().push(Watch {
+            watch_id,
+            start,
+            end,
+            sender,
+            prev_kv,
+            filters,
+            buffer: VecDeque::new(),
+            paused: false,
+        });
         if !start_heads.is_empty() {
             // start the watch from a point in time
             // let header = document.header()?;
@@ -91,36 +201,117 @@ where
         Ok(watch_id)
     }
 
-    pub fn remove_watch(&mut self, id: WatchId) {
-        self.watches.remove(&id);
+    /// Whether the stream with this sender already has a watch with the id.
+    fn exists_on_stream(
+        &self,
+        watch_id: WatchId,
+        sender: &Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> bool {
+        self.watches
+            .get(&watch_id)
+            .into_iter()
+            .flatten()
+            .any(|watch| watch.sender.same_channel(sender))
+    }
+
+    /// Remove the watch with the id from the stream with this sender.
+    pub fn remove_watch(&mut self, id: WatchId, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        if let Some(watches) = self.watches.get_mut(&id) {
+            watches.retain(|watch| !watch.sender.same_channel(sender));
+            if watches.is_empty() {
+                self.watches.remove(&id);
+            }
+        }
+    }
+
+    /// The watches on the stream with this sender.
+    fn stream_watches<'a>(
+        &'a mut self,
+        sender: &'a Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> impl Iterator<Item = &'a mut Watch<V>> {
+        self.watches
+            .values_mut()
+            .flatten()
+            .filter(|watch| watch.sender.same_channel(sender))
+    }
+
+    /// Take the watches on the stream with this sender that were cancelled by the server, along
+    /// with why.
+    pub fn take_cancelled(
+        &mut self,
+        sender: &Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> Vec<(WatchId, crate::Error)> {
+        let (taken, kept) = std::mem::take(&mut self.cancelled)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, cancelled_sender, _)| {
+                cancelled_sender.same_channel(sender)
+            });
+        self.cancelled = kept;
+        taken
+            .into_iter()
+            .map(|(watch_id, _, error)| (watch_id, error))
+            .collect()
+    }
+
+    /// Whether every watch on the stream with this sender has handed all of its events to the
+    /// stream.
+    pub fn is_synced(&self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) -> bool {
+        self.watches
+            .values()
+            .flatten()
+            .filter(|watch| watch.sender.same_channel(sender))
+            .all(|watch| watch.buffer.is_empty())
+    }
+
+    /// Hold back events for the watches on the stream with this sender until it is resumed.
+    pub fn pause(&mut self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        for watch in self.stream_watches(sender) {
+            watch.paused = true;
+        }
+    }
+
+    pub fn resume(&mut self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        for watch in self.stream_watches(sender) {
+            watch.paused = false;
+            watch.flush();
+        }
+    }
+
+    /// Retry sending the events that streams didn't have room for and drop the watches of streams
+    /// that have gone away.
+    pub fn flush(&mut self) {
+        for watches in self.watches.values_mut() {
+            watches.retain(|watch| !watch.sender.is_closed());
+            for watch in watches.iter_mut() {
+                watch.flush();
+            }
+        }
+        self.watches.retain(|_, watches| !watches.is_empty());
     }
 
     pub async fn receive_event(&mut self, header: Header, event: WatchEvent<V>) {
-        for watcher in self.watches.values() {
-            let key = event.typ.key();
-            if let Some(end) = &watcher.end {
-                if watcher.start.as_str() <= key && key <= end.as_str() {
-                    let mut event = event.clone();
-                    if !watcher.prev_kv {
-                        event.prev_kv = None;
-                    }
-                    watcher
-                        .sender
-                        .send((watcher.watch_id, header.clone(), event))
-                        .await
-                        .unwrap();
+        for watches in self.watches.values_mut() {
+            let cancelled = &mut self.cancelled;
+            watches.retain_mut(|watch| {
+                if watch.filters(&event) || !watch.watches_key(event.typ.key()) {
+                    return true;
                 }
-            } else if watcher.start == key {
-                let mut event = event.clone();
-                if !watcher.prev_kv {
-                    event.prev_kv = None;
+                if watch.push(header.clone(), event.clone()) {
+                    return true;
                 }
-                watcher
-                    .sender
-                    .send((watcher.watch_id, header.clone(), event))
-                    .await
-                    .unwrap();
-            }
+                // without the history to catch up from, the watch can't be kept
+                warn!(
+                    watch_id = watch.watch_id,
+                    "Watch fell too far behind, cancelling it"
+                );
+                cancelled.push((
+                    watch.watch_id,
+                    watch.sender.clone(),
+                    crate::Error::WatchTooSlow,
+                ));
+                false
+            });
         }
+        self.watches.retain(|_, watches| !watches.is_empty());
     }
 }
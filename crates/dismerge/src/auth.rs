@@ -1,142 +1,555 @@
-pub(crate) struct AuthServer {}
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use dismerge_core::value::Value;
+use dismerge_core::Error;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tokio::sync::Mutex;
+use tracing::debug;
+use x509_parser::prelude::FromDer;
+use x509_parser::prelude::X509Certificate;
+
+use crate::{Doc, DocInner, DocPersister};
+
+/// Same cost as etcd uses for hashing passwords.
+const BCRYPT_COST: u32 = 10;
+
+/// How long a token stays valid for since it was last used.
+const TOKEN_TTL: Duration = Duration::from_secs(300);
+
+/// What a client presented to identify itself with a request.
+#[derive(Debug, Clone)]
+pub(crate) struct Credentials {
+    /// The auth token that the client sent in the request metadata.
+    token: Option<String>,
+    /// The common name from the client's TLS certificate, only present when the server verifies
+    /// client certificates.
+    common_name: Option<String>,
+}
+
+/// Get the credentials that the client sent with the request.
+pub(crate) fn request_credentials<T>(request: &tonic::Request<T>) -> Credentials {
+    let token = request
+        .metadata()
+        .get("token")
+        .and_then(|token| token.to_str().ok())
+        .map(|token| token.to_owned());
+    let common_name = request
+        .peer_certs()
+        .and_then(|certs| certs.first().and_then(certificate_common_name));
+    Credentials { token, common_name }
+}
+
+/// Extract the subject's common name from a DER encoded certificate.
+fn certificate_common_name(cert: &tonic::transport::Certificate) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert.get_ref()).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(|name| name.to_owned())
+}
+
+/// Simple tokens given out to authenticated users, these are local to this node.
+#[derive(Clone, Default)]
+pub(crate) struct Tokens {
+    tokens: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl Tokens {
+    async fn assign(&self, user: String) -> String {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        self.tokens
+            .lock()
+            .await
+            .insert(token.clone(), (user, Instant::now()));
+        token
+    }
+
+    /// Find the user that the token was given to, refreshing it.
+    async fn user(&self, token: &str) -> Option<String> {
+        let mut tokens = self.tokens.lock().await;
+        let expired =
+            matches!(tokens.get(token), Some((_, last_used)) if last_used.elapsed() > TOKEN_TTL);
+        if expired {
+            tokens.remove(token);
+        }
+        let (user, last_used) = tokens.get_mut(token)?;
+        *last_used = Instant::now();
+        Some(user.clone())
+    }
+
+    /// Remove all of the tokens for the user, so they have to authenticate again.
+    async fn revoke_user(&self, user: &str) {
+        self.tokens
+            .lock()
+            .await
+            .retain(|_, (token_user, _)| token_user != user);
+    }
+
+    /// Find the user making a request, `None` when auth is disabled and anyone can make requests.
+    pub(crate) async fn authenticate<P: DocPersister, V: Value>(
+        &self,
+        document: &DocInner<P, V>,
+        credentials: &Credentials,
+    ) -> Result<Option<String>, tonic::Status> {
+        if !document.auth_enabled() {
+            return Ok(None);
+        }
+        // a token takes precedence over the certificate, as in etcd
+        if let Some(token) = &credentials.token {
+            let user = self.user(token).await.ok_or(Error::InvalidAuthToken)?;
+            return Ok(Some(user));
+        }
+        let user = credentials.common_name.clone().ok_or(Error::UserEmpty)?;
+        Ok(Some(user))
+    }
+}
+
+async fn hash_password(password: String) -> Result<String, tonic::Status> {
+    tokio::task::spawn_blocking(move || bcrypt::hash(password, BCRYPT_COST))
+        .await
+        .unwrap()
+        .map_err(|error| tonic::Status::internal(error.to_string()))
+}
+
+pub(crate) struct AuthServer<P, V> {
+    pub(crate) document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
+}
+
+impl<P: DocPersister, V: Value> AuthServer<P, V> {
+    /// Check that the request is allowed to manage auth, anyone can while auth is disabled.
+    async fn check_admin(
+        &self,
+        document: &DocInner<P, V>,
+        credentials: &Credentials,
+    ) -> Result<(), tonic::Status> {
+        if let Some(user) = self.tokens.authenticate(document, credentials).await? {
+            document.check_admin(&user)?;
+        }
+        Ok(())
+    }
+
+    /// Like `check_admin` but also lets the named user make requests about themselves.
+    async fn check_admin_or_user(
+        &self,
+        document: &DocInner<P, V>,
+        credentials: &Credentials,
+        name: &str,
+    ) -> Result<(), tonic::Status> {
+        if let Some(user) = self.tokens.authenticate(document, credentials).await? {
+            if user != name {
+                document.check_admin(&user)?;
+            }
+        }
+        Ok(())
+    }
+}
 
 #[tonic::async_trait]
-impl mergeable_proto::etcdserverpb::auth_server::Auth for AuthServer {
+impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::auth_server::Auth
+    for AuthServer<P, V>
+{
     async fn auth_enable(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthEnableRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthEnableRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthEnableResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        debug!("Got auth_enable request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.enable_auth()?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthEnableResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn auth_disable(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthDisableRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthDisableRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthDisableResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        debug!("Got auth_disable request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.disable_auth();
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthDisableResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn authenticate(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthenticateRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthenticateRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthenticateResponse>, tonic::Status>
     {
-        todo!()
+        let mergeable_proto::etcdserverpb::AuthenticateRequest { name, password } =
+            request.into_inner();
+        debug!(?name, "Got authenticate request");
+
+        let user = {
+            let document = self.document.lock().await;
+            if !document.auth_enabled() {
+                return Err(Error::AuthNotEnabled.into());
+            }
+            document.get_user(&name).map_err(|_| Error::AuthFailed)?
+        };
+        let hash = user.password.ok_or(Error::AuthFailed)?;
+        let verified = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .unwrap()
+            .unwrap_or(false);
+        if !verified {
+            return Err(Error::AuthFailed.into());
+        }
+
+        let token = self.tokens.assign(name).await;
+        let header = self.document.lock().await.header()?;
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthenticateResponse {
+                header: Some(header.into()),
+                token,
+            },
+        ))
     }
 
     async fn user_add(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserAddRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserAddRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthUserAddResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserAddRequest {
+            name,
+            password,
+            options,
+        } = request.into_inner();
+        debug!(?name, "Got user_add request");
+
+        // check before doing the expensive hashing
+        {
+            let document = self.document.lock().await;
+            self.check_admin(&document, &credentials).await?;
+        }
+        let password = if matches!(options, Some(options) if options.no_password) {
+            None
+        } else {
+            Some(hash_password(password).await?)
+        };
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.add_user(&name, password)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserAddResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn user_get(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserGetRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserGetRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthUserGetResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserGetRequest { name } = request.into_inner();
+        debug!(?name, "Got user_get request");
+
+        let document = self.document.lock().await;
+        self.check_admin_or_user(&document, &credentials, &name)
+            .await?;
+        let user = document.get_user(&name)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserGetResponse {
+                header: Some(document.header()?.into()),
+                roles: user.roles,
+            },
+        ))
     }
 
     async fn user_list(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserListRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserListRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthUserListResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        debug!("Got user_list request");
+
+        let document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserListResponse {
+                header: Some(document.header()?.into()),
+                users: document.list_users(),
+            },
+        ))
     }
 
     async fn user_delete(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserDeleteRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserDeleteRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthUserDeleteResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserDeleteRequest { name } = request.into_inner();
+        debug!(?name, "Got user_delete request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.delete_user(&name)?;
+        self.tokens.revoke_user(&name).await;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserDeleteResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn user_change_password(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserChangePasswordRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserChangePasswordRequest>,
     ) -> Result<
         tonic::Response<mergeable_proto::etcdserverpb::AuthUserChangePasswordResponse>,
         tonic::Status,
     > {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserChangePasswordRequest { name, password } =
+            request.into_inner();
+        debug!(?name, "Got user_change_password request");
+
+        // check before doing the expensive hashing
+        {
+            let document = self.document.lock().await;
+            self.check_admin_or_user(&document, &credentials, &name)
+                .await?;
+        }
+        let password = hash_password(password).await?;
+
+        let mut document = self.document.lock().await;
+        self.check_admin_or_user(&document, &credentials, &name)
+            .await?;
+        document.change_password(&name, Some(password))?;
+        self.tokens.revoke_user(&name).await;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserChangePasswordResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn user_grant_role(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserGrantRoleRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserGrantRoleRequest>,
     ) -> Result<
         tonic::Response<mergeable_proto::etcdserverpb::AuthUserGrantRoleResponse>,
         tonic::Status,
     > {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserGrantRoleRequest { user, role } =
+            request.into_inner();
+        debug!(?user, ?role, "Got user_grant_role request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.grant_role(&user, &role)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserGrantRoleResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn user_revoke_role(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserRevokeRoleRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthUserRevokeRoleRequest>,
     ) -> Result<
         tonic::Response<mergeable_proto::etcdserverpb::AuthUserRevokeRoleResponse>,
         tonic::Status,
     > {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthUserRevokeRoleRequest { name, role } =
+            request.into_inner();
+        debug!(?name, ?role, "Got user_revoke_role request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.revoke_role(&name, &role)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthUserRevokeRoleResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn role_add(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleAddRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleAddRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthRoleAddResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthRoleAddRequest { name } = request.into_inner();
+        debug!(?name, "Got role_add request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.add_role(&name)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleAddResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn role_get(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleGetRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleGetRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthRoleGetResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthRoleGetRequest { role } = request.into_inner();
+        debug!(?role, "Got role_get request");
+
+        let document = self.document.lock().await;
+        // users can always get the roles they have
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            if !matches!(document.get_user(&user), Ok(user) if user.roles.contains(&role)) {
+                document.check_admin(&user)?;
+            }
+        }
+        let permissions = document.get_role(&role)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleGetResponse {
+                header: Some(document.header()?.into()),
+                perm: permissions.into_iter().map(|p| p.into()).collect(),
+            },
+        ))
     }
 
     async fn role_list(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleListRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleListRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthRoleListResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        debug!("Got role_list request");
+
+        let document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleListResponse {
+                header: Some(document.header()?.into()),
+                roles: document.list_roles(),
+            },
+        ))
     }
 
     async fn role_delete(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleDeleteRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleDeleteRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AuthRoleDeleteResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthRoleDeleteRequest { role } = request.into_inner();
+        debug!(?role, "Got role_delete request");
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.delete_role(&role)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleDeleteResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn role_grant_permission(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleGrantPermissionRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleGrantPermissionRequest>,
     ) -> Result<
         tonic::Response<mergeable_proto::etcdserverpb::AuthRoleGrantPermissionResponse>,
         tonic::Status,
     > {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthRoleGrantPermissionRequest { name, perm } =
+            request.into_inner();
+        debug!(?name, ?perm, "Got role_grant_permission request");
+        let perm = perm.ok_or_else(|| tonic::Status::invalid_argument("missing permission"))?;
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.grant_permission(&name, perm.try_into()?)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleGrantPermissionResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn role_revoke_permission(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleRevokePermissionRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AuthRoleRevokePermissionRequest>,
     ) -> Result<
         tonic::Response<mergeable_proto::etcdserverpb::AuthRoleRevokePermissionResponse>,
         tonic::Status,
     > {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AuthRoleRevokePermissionRequest {
+            role,
+            key,
+            range_end,
+        } = request.into_inner();
+        debug!(
+            ?role,
+            ?key,
+            ?range_end,
+            "Got role_revoke_permission request"
+        );
+        let (key, range_end) = match (String::from_utf8(key), String::from_utf8(range_end)) {
+            (Ok(key), Ok(range_end)) => (key, range_end),
+            _ => return Err(tonic::Status::invalid_argument("keys must be valid utf-8")),
+        };
+        let range_end = if range_end.is_empty() {
+            None
+        } else {
+            Some(range_end.as_str())
+        };
+
+        let mut document = self.document.lock().await;
+        self.check_admin(&document, &credentials).await?;
+        document.revoke_permission(&role, &key, range_end)?;
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AuthRoleRevokePermissionResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 }
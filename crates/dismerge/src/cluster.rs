@@ -1,13 +1,14 @@
 use dismerge_core::value::Value;
 use rand::Rng;
-use tracing::error;
 use tracing::info;
 
+use crate::auth::{request_credentials, Tokens};
 use crate::Doc;
 use crate::DocPersister;
 
 pub struct ClusterServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 #[tonic::async_trait]
@@ -19,12 +20,16 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::cluster_server::C
         request: tonic::Request<mergeable_proto::etcdserverpb::MemberAddRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::MemberAddResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
         let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
         let header = document.header()?;
         let member_id = document.rng.gen();
         let member = document
-            .add_member(request.peer_ur_ls.clone(), member_id)
+            .add_member(request.peer_ur_ls.clone(), member_id, request.is_learner)
             .await;
         info!(peer_urls=?request.peer_ur_ls, id=?member.id, "Added member");
         let members = document.list_members()?;
@@ -42,10 +47,14 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::cluster_server::C
         request: tonic::Request<mergeable_proto::etcdserverpb::MemberRemoveRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::MemberRemoveResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member remove request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document.remove_member(request.id).await?;
+        info!(id=?request.id, "Removed member");
         let header = document.header()?;
         let list = document.list_members()?;
 
@@ -62,10 +71,16 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::cluster_server::C
         request: tonic::Request<mergeable_proto::etcdserverpb::MemberUpdateRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::MemberUpdateResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member update request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document
+            .update_member(request.id, request.peer_ur_ls.clone())
+            .await?;
+        info!(id=?request.id, peer_urls=?request.peer_ur_ls, "Updated member");
         let header = document.header()?;
         let list = document.list_members()?;
 
@@ -100,10 +115,14 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::cluster_server::C
         request: tonic::Request<mergeable_proto::etcdserverpb::MemberPromoteRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::MemberPromoteResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member_promote request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document.promote_member(request.id).await?;
+        info!(id=?request.id, "Promoted member");
         let header = document.header()?;
         let list = document.list_members()?;
 
@@ -1,6 +1,8 @@
 use dismerge_core::value::Value;
 use tonic::Response;
 
+use crate::auth::request_credentials;
+use crate::auth::Tokens;
 use crate::{Doc, DocPersister};
 use mergeable_proto::etcdserverpb::{kv_server::Kv, RangeResponse};
 use mergeable_proto::etcdserverpb::{DeleteRangeResponse, PutResponse, TxnResponse};
@@ -8,12 +10,14 @@ use tracing::debug;
 
 pub struct KvServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 impl<P: DocPersister, V: Value> Clone for KvServer<P, V> {
     fn clone(&self) -> Self {
         Self {
             document: self.document.clone(),
+            tokens: self.tokens.clone(),
         }
     }
 }
@@ -27,12 +31,16 @@ where
         &self,
         request: tonic::Request<mergeable_proto::etcdserverpb::RangeRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::RangeResponse>, tonic::Status> {
-        let request: dismerge_core::RangeRequest = request.into_inner().into();
+        let credentials = request_credentials(&request);
+        let request: dismerge_core::RangeRequest = request.into_inner().try_into()?;
         debug!(start=?request.start, end=?request.end, "RANGE");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_range_permission(&user, &request)?;
+            }
             document.range(request)
         };
 
@@ -60,6 +68,7 @@ where
         &self,
         request: tonic::Request<mergeable_proto::etcdserverpb::PutRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::PutResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let request: dismerge_core::PutRequest<V> =
             request.into_inner().try_into().map_err(|err| {
                 tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
@@ -69,6 +78,9 @@ where
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_put_permission(&user, &request)?;
+            }
             document.put(request).await
         };
 
@@ -87,12 +99,16 @@ where
         request: tonic::Request<mergeable_proto::etcdserverpb::DeleteRangeRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::DeleteRangeResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request: dismerge_core::DeleteRangeRequest = request.into_inner().into();
         debug!(start=?request.start, end=?request.end, "DELETE_RANGE");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_delete_range_permission(&user, &request)?;
+            }
             document.delete_range(request).await
         };
 
@@ -114,14 +130,16 @@ where
         &self,
         request: tonic::Request<mergeable_proto::etcdserverpb::TxnRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::TxnResponse>, tonic::Status> {
-        let request = request.into_inner().try_into().map_err(|err| {
-            tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
-        })?;
+        let credentials = request_credentials(&request);
+        let request: dismerge_core::TxnRequest<V> = request.into_inner().try_into()?;
         debug!("TXN");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_txn_permission(&user, &request)?;
+            }
             document.txn(request).await
         };
 
@@ -144,12 +162,16 @@ where
         request: tonic::Request<mergeable_proto::etcdserverpb::CompactionRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::CompactionResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let mergeable_proto::etcdserverpb::CompactionRequest {
             heads: _,
             physical: _,
         } = request.into_inner();
 
         let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
         document.compact();
         let header = document.header()?;
 
@@ -1,3 +1,4 @@
+use dismerge_core::auth::PermissionType;
 use dismerge_core::value::Value;
 use futures::Stream;
 use futures::StreamExt;
@@ -6,11 +7,14 @@ use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::debug;
 
+use crate::auth::request_credentials;
+use crate::auth::Tokens;
 use crate::Doc;
 use crate::DocPersister;
 
 pub(crate) struct LeaseServer<P, V> {
     pub(crate) document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 #[tonic::async_trait]
@@ -22,6 +26,7 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
         request: tonic::Request<mergeable_proto::etcdserverpb::LeaseGrantRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::LeaseGrantResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let mergeable_proto::etcdserverpb::LeaseGrantRequest { ttl, id } = request.into_inner();
 
         debug!(?ttl, ?id, "Got lease_grant request");
@@ -32,12 +37,16 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let ttl = if ttl > 0 { Some(ttl) } else { None };
         let id = if id > 0 { Some(id) } else { None };
 
         let mut document = self.document.lock().await;
+        document.check_not_learner()?;
+        document.check_space()?;
         if let Some((id, ttl)) = document.add_lease(id, ttl, chrono::Utc::now().timestamp()) {
             let document_clone = self.document.clone();
             tokio::spawn(async move {
@@ -84,11 +93,19 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
         request: tonic::Request<mergeable_proto::etcdserverpb::LeaseRevokeRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::LeaseRevokeResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let mergeable_proto::etcdserverpb::LeaseRevokeRequest { id } = request.into_inner();
 
         debug!(?id, "Got lease_revoke request");
 
         let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            // revoking deletes the attached keys so needs write access to all of them
+            for key in document.keys_for_lease(id) {
+                document.check_permission(&user, &key, None, PermissionType::Write)?;
+            }
+        }
+        document.check_not_learner()?;
         document.remove_lease(id).await;
         // the revoke poller will exit once it can't find the lease
 
@@ -118,6 +135,7 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
             tonic::Streaming<mergeable_proto::etcdserverpb::LeaseKeepAliveRequest>,
         >,
     ) -> Result<tonic::Response<Self::LeaseKeepAliveStream>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let mut request_stream = request.into_inner();
 
         // check our node is ready
@@ -126,6 +144,8 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let (response_sender, response_receiver) = mpsc::channel(10);
@@ -167,27 +187,37 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
         tonic::Response<mergeable_proto::etcdserverpb::LeaseTimeToLiveResponse>,
         tonic::Status,
     > {
+        let credentials = request_credentials(&request);
         let mergeable_proto::etcdserverpb::LeaseTimeToLiveRequest { id, keys } =
             request.into_inner();
 
-        debug!("Got request for lease ttl");
+        debug!(?id, ?keys, "Got request for lease ttl");
 
-        // check our node is ready
-        {
-            let document = self.document.lock().await;
-            if !document.is_ready() {
-                return Err(tonic::Status::unavailable("node not ready"));
-            }
+        let document = self.document.lock().await;
+        if !document.is_ready() {
+            return Err(tonic::Status::unavailable("node not ready"));
         }
+        // any authenticated user can use leases
+        let user = self.tokens.authenticate(&document, &credentials).await?;
 
-        let document = self.document.lock().await;
         let header = document.header()?;
-        let last_refresh = document.last_lease_refresh(id).unwrap();
-        let granted_ttl = document.granted_lease_ttl(id).unwrap();
-        let time_since_refresh = chrono::Utc::now().timestamp() - last_refresh;
-        let ttl = granted_ttl - time_since_refresh;
+        // like etcd, a lease that doesn't exist has a ttl of -1
+        let (ttl, granted_ttl) = match (
+            document.remaining_lease_ttl(id, chrono::Utc::now().timestamp()),
+            document.granted_lease_ttl(id),
+        ) {
+            (Some(ttl), Some(granted_ttl)) => (ttl, granted_ttl),
+            _ => (-1, 0),
+        };
         let keys_for_lease = if keys {
-            document.keys_for_lease(id)
+            let keys_for_lease = document.keys_for_lease(id);
+            if let Some(user) = user {
+                // listing the keys reveals them so needs read access to all of them
+                for key in &keys_for_lease {
+                    document.check_permission(&user, key, None, PermissionType::Read)?;
+                }
+            }
+            keys_for_lease
         } else {
             vec![]
         };
@@ -208,6 +238,7 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
         request: tonic::Request<mergeable_proto::etcdserverpb::LeaseLeasesRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::LeaseLeasesResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let mergeable_proto::etcdserverpb::LeaseLeasesRequest {} = request.into_inner();
 
         debug!("Got request for all leases");
@@ -218,6 +249,8 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::lease_server::Lea
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let document = self.document.lock().await;
@@ -1,4 +1,5 @@
 use crate::auth::AuthServer;
+use crate::auth::Tokens;
 use crate::kv::KvServer;
 use crate::lease::LeaseServer;
 use crate::options::InitialClusterState;
@@ -14,6 +15,7 @@ use futures::join;
 use maintenance::MaintenanceServer;
 use peer::DocumentChangedSyncer;
 use peer_proto::peer_server::PeerServer;
+use prometheus_client::registry::Registry;
 use replication::ReplicationServer;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -22,6 +24,8 @@ use std::time::Instant;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::Certificate;
 use tonic::transport::Identity;
 use tonic::transport::ServerTlsConfig;
 use tower::ServiceBuilder;
@@ -39,6 +43,7 @@ mod options;
 mod peer;
 mod persister;
 mod replication;
+mod snapshot;
 mod watch;
 
 pub use options::Options;
@@ -68,52 +73,65 @@ where
         initial_cluster,
         initial_cluster_state,
         cert_file,
-        client_cert_auth: _,
+        client_cert_auth,
         key_file,
-        trusted_ca_file: _,
+        trusted_ca_file,
         peer_cert_file,
         peer_key_file,
         peer_trusted_ca_file,
         peer_client_cert_auth: _,
         snapshot_count: _,
+        quota_backend_bytes,
         listen_client_urls,
         listen_peer_urls,
         listen_metrics_urls,
         flush_interval_ms,
         sync_interval_ms,
+        peer_compression,
         log_filter: _,
         no_colour: _,
         persister,
         concurrency_limit,
         timeout,
+        restore_snapshot,
     } = options;
 
     let (watch_sender, watch_receiver) = mpsc::channel(10);
     let (local_change_sender, _local_change_receiver) = broadcast::channel(10);
     let (member_changed_sender, _member_changed_receiver) = broadcast::channel(10);
+    let (member_removed_sender, _member_removed_receiver) = broadcast::channel(10);
 
     let notify = Arc::new(tokio::sync::Notify::new());
 
     let data_dir = data_dir.unwrap_or_else(|| format!("{}.metcd", name).into());
     info!(?data_dir, "Making db");
-    let persister = PersisterDispatcher::new(persister, &data_dir);
+    let mut persister = PersisterDispatcher::new(persister, &data_dir);
+    if let Some(restore_snapshot) = restore_snapshot {
+        snapshot::restore(&restore_snapshot, &mut persister).expect("Failed to restore snapshot");
+    }
 
     info!("Building document");
     let mut document = DocumentBuilder::<_, _, _, V>::default()
         .with_watcher(watch::MyWatcher {
-            sender: watch_sender,
+            sender: watch_sender.clone(),
         })
         .with_syncer(DocumentChangedSyncer {
             notify: Arc::clone(&notify),
             local_change_sender: local_change_sender.clone(),
             member_changed: member_changed_sender.clone(),
+            member_removed: member_removed_sender.clone(),
         })
         .with_persister(persister)
         .with_auto_flush(false)
         .with_auto_sync(false)
         .with_name(name.clone())
         .with_peer_urls(initial_advertise_peer_urls.clone())
-        .with_client_urls(advertise_client_urls.clone());
+        .with_client_urls(advertise_client_urls.clone())
+        .with_quota_backend_bytes(if quota_backend_bytes == 0 {
+            options::DEFAULT_QUOTA_BACKEND_BYTES
+        } else {
+            quota_backend_bytes
+        });
 
     if matches!(initial_cluster_state, InitialClusterState::New) {
         document.set_cluster_id(rand::random());
@@ -129,8 +147,10 @@ where
     let document = Arc::new(Mutex::new(document));
     start_flush_loop(document.clone(), Duration::from_millis(flush_interval_ms));
     start_sync_loop(document.clone(), Duration::from_millis(sync_interval_ms));
+    let tokens = Tokens::default();
     let server = KvServer {
         document: Arc::clone(&document),
+        tokens: tokens.clone(),
     };
 
     let watch_server = Arc::new(Mutex::new(dismerge_core::WatchServer::default()));
@@ -140,14 +160,25 @@ where
     });
     let watcher = watch::WatchService {
         watch_server,
+        watch_sender,
         document: Arc::clone(&document),
+        tokens: tokens.clone(),
     };
 
     let initial_cluster = peer::split_initial_cluster(&initial_cluster);
 
+    let mut registry = Registry::default();
+    let peer_metrics = metrics::PeerMetrics::default();
+    peer_metrics.register(&mut registry);
+    let registry = Arc::new(registry);
+
     let mut metrics_servers = Vec::new();
     for address in listen_metrics_urls {
-        metrics_servers.push(start_metrics_server(address, document.clone()));
+        metrics_servers.push(start_metrics_server(
+            address,
+            document.clone(),
+            Arc::clone(&registry),
+        ));
     }
 
     let mut peer_servers = Vec::new();
@@ -164,6 +195,9 @@ where
                 notify.clone(),
                 local_change_sender.subscribe(),
                 member_changed_sender.subscribe(),
+                member_removed_sender.subscribe(),
+                peer_compression,
+                peer_metrics.clone(),
             )
             .await,
         );
@@ -176,9 +210,12 @@ where
                 address,
                 &cert_file,
                 &key_file,
+                client_cert_auth.unwrap_or_default(),
+                &trusted_ca_file,
                 server.clone(),
                 watcher.clone(),
                 document.clone(),
+                tokens.clone(),
                 concurrency_limit,
                 timeout,
             )
@@ -193,13 +230,17 @@ where
     ];
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn start_client_server<P: DocPersister, V: Value>(
     address: String,
     cert_file: &str,
     key_file: &str,
+    client_cert_auth: bool,
+    trusted_ca_file: &str,
     server: KvServer<P, V>,
     watch_server: watch::WatchService<P, V>,
     document: Doc<P, V>,
+    tokens: Tokens,
     concurrency_limit: usize,
     timeout: u64,
 ) -> tokio::task::JoinHandle<()>
@@ -227,7 +268,16 @@ where
             .expect("Failed to read cert file");
 
         let server_identity = Identity::from_pem(cert, key);
-        let tls = ServerTlsConfig::new().identity(server_identity);
+        let mut tls = ServerTlsConfig::new().identity(server_identity);
+
+        if client_cert_auth {
+            // require clients to present a certificate signed by the trusted ca, the common name
+            // of which is used as their user for auth
+            let ca_cert = tokio::fs::read(trusted_ca_file)
+                .await
+                .expect("Failed to read trusted ca file");
+            tls = tls.client_ca_root(Certificate::from_pem(ca_cert));
+        }
 
         Some(tls)
     } else {
@@ -261,12 +311,14 @@ where
                 mergeable_proto::etcdserverpb::maintenance_server::MaintenanceServer::new(
                     MaintenanceServer {
                         document: document.clone(),
+                        tokens: tokens.clone(),
                     },
                 ),
             )
             .add_service(
                 mergeable_proto::etcdserverpb::cluster_server::ClusterServer::new(ClusterServer {
                     document: document.clone(),
+                    tokens: tokens.clone(),
                 }),
             )
             .add_service(
@@ -277,11 +329,15 @@ where
                 ),
             )
             .add_service(mergeable_proto::etcdserverpb::auth_server::AuthServer::new(
-                AuthServer {},
+                AuthServer {
+                    document: document.clone(),
+                    tokens: tokens.clone(),
+                },
             ))
             .add_service(
                 mergeable_proto::etcdserverpb::lease_server::LeaseServer::new(LeaseServer {
                     document: document.clone(),
+                    tokens,
                 }),
             );
 
@@ -304,6 +360,9 @@ async fn start_peer_server<P: DocPersister, V: Value>(
     notify: Arc<tokio::sync::Notify>,
     local_change_receiver: broadcast::Receiver<Vec<Vec<u8>>>,
     member_changed_receiver: broadcast::Receiver<mergeable_proto::etcdserverpb::Member>,
+    member_removed_receiver: broadcast::Receiver<u64>,
+    compression: bool,
+    peer_metrics: metrics::PeerMetrics,
 ) -> tokio::task::JoinHandle<()> {
     let peer_url = url::Url::parse(&address).unwrap();
     let proto = peer_url.scheme();
@@ -350,7 +409,10 @@ async fn start_peer_server<P: DocPersister, V: Value>(
         notify,
         local_change_receiver,
         member_changed_receiver,
+        member_removed_receiver,
         ca_cert,
+        compression,
+        peer_metrics,
     )
     .await;
     info!(?address, "Starting peer server");
@@ -360,7 +422,12 @@ async fn start_peer_server<P: DocPersister, V: Value>(
             builder = builder.tls_config(tls).unwrap();
         }
 
-        let router = builder.add_service(PeerServer::new(peer_server));
+        // always take compressed payloads, whether to send them is up to each peer
+        let mut service = PeerServer::new(peer_server).accept_compressed(CompressionEncoding::Gzip);
+        if compression {
+            service = service.send_compressed(CompressionEncoding::Gzip);
+        }
+        let router = builder.add_service(service);
 
         let res = router.serve(peer_address).await;
         if let Err(error) = res {
@@ -372,6 +439,7 @@ async fn start_peer_server<P: DocPersister, V: Value>(
 fn start_metrics_server<P: DocPersister, V: Value>(
     address: String,
     document: Doc<P, V>,
+    registry: Arc<Registry>,
 ) -> tokio::task::JoinHandle<()> {
     let metrics_url = url::Url::parse(&address).unwrap();
     let metrics_address = format!(
@@ -381,7 +449,7 @@ fn start_metrics_server<P: DocPersister, V: Value>(
     )
     .parse()
     .unwrap();
-    let metrics_server = metrics::MetricsServer { document };
+    let metrics_server = metrics::MetricsServer { document, registry };
     info!(?address, "Starting metrics server");
     tokio::spawn(async move {
         metrics_server.serve(metrics_address).await;
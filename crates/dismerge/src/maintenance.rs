@@ -1,11 +1,16 @@
+use crate::auth::{request_credentials, Tokens};
+use crate::snapshot::snapshot_responses;
 use crate::{Doc, DocPersister};
+use automerge::ChangeHash;
+use dismerge_core::alarm::{Alarm, AlarmType};
 use dismerge_core::value::Value;
 use futures::Stream;
 use std::pin::Pin;
-use tracing::info;
+use tracing::{debug, info};
 
 pub struct MaintenanceServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 const VERSION: &str = "3.3.27";
@@ -16,9 +21,66 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::maintenance_serve
 {
     async fn alarm(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::AlarmRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::AlarmRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::AlarmResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::AlarmRequest {
+            action,
+            member_id,
+            alarm,
+        } = request.into_inner();
+        let action = mergeable_proto::etcdserverpb::alarm_request::AlarmAction::from_i32(action)
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown alarm action"))?;
+        let alarm = mergeable_proto::etcdserverpb::AlarmType::from_i32(alarm)
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown alarm type"))?;
+        debug!(?action, ?member_id, ?alarm, "Got alarm request");
+        // none matches any alarm when getting them but isn't an alarm itself
+        let alarm = AlarmType::try_from(alarm).ok();
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let alarms = match action {
+            mergeable_proto::etcdserverpb::alarm_request::AlarmAction::Get => document
+                .alarms()
+                .into_iter()
+                .filter(|a| alarm.is_none() || alarm == Some(a.alarm))
+                .collect(),
+            mergeable_proto::etcdserverpb::alarm_request::AlarmAction::Activate => {
+                let alarm = Alarm {
+                    member_id,
+                    alarm: alarm.ok_or_else(|| {
+                        tonic::Status::invalid_argument("alarm type must be given")
+                    })?,
+                };
+                if document.activate_alarm(alarm) {
+                    vec![alarm]
+                } else {
+                    Vec::new()
+                }
+            }
+            mergeable_proto::etcdserverpb::alarm_request::AlarmAction::Deactivate => {
+                let alarm = Alarm {
+                    member_id,
+                    alarm: alarm.ok_or_else(|| {
+                        tonic::Status::invalid_argument("alarm type must be given")
+                    })?,
+                };
+                if document.deactivate_alarm(alarm) {
+                    vec![alarm]
+                } else {
+                    Vec::new()
+                }
+            }
+        };
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::AlarmResponse {
+                header: Some(document.header()?.into()),
+                alarms: alarms.into_iter().map(|alarm| alarm.into()).collect(),
+            },
+        ))
     }
 
     async fn status(
@@ -44,7 +106,7 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::maintenance_serve
             raft_applied_index: 1,
             errors: Vec::new(),
             db_size_in_use: db_size as i64,
-            is_learner: false,
+            is_learner: document.member().is_learner,
         };
 
         Ok(tonic::Response::new(response))
@@ -52,24 +114,85 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::maintenance_serve
 
     async fn defragment(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::DefragmentRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::DefragmentRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::DefragmentResponse>, tonic::Status>
     {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::DefragmentRequest {} = request.into_inner();
+        debug!("Got defragment request");
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let (before, after) = document.defragment();
+        info!(
+            before_changes = before.changes,
+            before_document = before.document,
+            after_changes = after.changes,
+            after_document = after.document,
+            "Defragmented storage"
+        );
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::DefragmentResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn hash(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::HashRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::HashRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::HashResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::HashRequest {} = request.into_inner();
+        debug!("Got hash request");
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let hash = document.hash();
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::HashResponse {
+                header: Some(document.header()?.into()),
+                hash,
+            },
+        ))
     }
 
     async fn hash_kv(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::HashKvRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::HashKvRequest>,
     ) -> Result<tonic::Response<mergeable_proto::etcdserverpb::HashKvResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::HashKvRequest { heads } = request.into_inner();
+        let heads = heads
+            .into_iter()
+            .map(|head| {
+                head.try_into()
+                    .map(ChangeHash)
+                    .map_err(|_| tonic::Status::invalid_argument("invalid heads"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        debug!(?heads, "Got hash_kv request");
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let hash = document.hash_kv(&heads);
+
+        Ok(tonic::Response::new(
+            mergeable_proto::etcdserverpb::HashKvResponse {
+                header: Some(document.header()?.into()),
+                hash,
+                // history isn't compacted so everything is always available
+                compact_revision: 0,
+            },
+        ))
     }
 
     type SnapshotStream = Pin<
@@ -84,9 +207,25 @@ impl<P: DocPersister, V: Value> mergeable_proto::etcdserverpb::maintenance_serve
 
     async fn snapshot(
         &self,
-        _request: tonic::Request<mergeable_proto::etcdserverpb::SnapshotRequest>,
+        request: tonic::Request<mergeable_proto::etcdserverpb::SnapshotRequest>,
     ) -> Result<tonic::Response<Self::SnapshotStream>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let mergeable_proto::etcdserverpb::SnapshotRequest {} = request.into_inner();
+        debug!("Got snapshot request");
+
+        let (header, snapshot) = {
+            let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_admin(&user)?;
+            }
+            (document.header()?, document.snapshot())
+        };
+        info!(size = snapshot.len(), "Streaming snapshot");
+
+        let responses = snapshot_responses(header.into(), snapshot);
+        Ok(tonic::Response::new(Box::pin(futures::stream::iter(
+            responses.into_iter().map(Ok::<_, tonic::Status>),
+        ))))
     }
 
     async fn move_leader(
@@ -1,21 +1,30 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use axum::http::StatusCode;
 use axum::routing::get;
 use axum::Router;
 use dismerge_core::value::Value;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::registry::Registry;
 use tracing::{debug, error, warn};
 
 use crate::{Doc, DocPersister};
 
 pub struct MetricsServer<P, V> {
     pub(crate) document: Doc<P, V>,
+    pub(crate) registry: Arc<Registry>,
 }
 
 impl<P: DocPersister, V: Value> MetricsServer<P, V> {
     pub async fn serve(&self, address: SocketAddr) {
         let document = self.document.clone();
-        let router = Router::new().route("/health", get(move || health(document.clone())));
+        let registry = Arc::clone(&self.registry);
+        let router = Router::new()
+            .route("/health", get(move || health(document.clone())))
+            .route("/metrics", get(move || metrics(registry.clone())));
         if let Err(error) = axum::Server::bind(&address)
             .serve(router.into_make_service())
             .await
@@ -35,3 +44,60 @@ async fn health<P: DocPersister, V: Value>(document: Doc<P, V>) -> Result<String
         Err(StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+async fn metrics(registry: Arc<Registry>) -> Result<String, StatusCode> {
+    let mut buffer = String::new();
+    if let Err(error) = prometheus_client::encoding::text::encode(&mut buffer, &registry) {
+        error!(%error, "Failed to encode metrics");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Ok(buffer)
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct PeerLabels {
+    pub peer: String,
+}
+
+/// Bytes of sync payloads sent to each peer.
+#[derive(Clone, Debug, Default)]
+pub struct PeerMetrics {
+    /// Encoded size of the payloads, before any compression.
+    sent_uncompressed_bytes: Family<PeerLabels, Counter>,
+    /// Size of the payloads as sent on the wire, after any compression. This is counted from the
+    /// HTTP body so it also includes the 5 byte gRPC frame header on each message, making it a
+    /// little larger than the uncompressed count when compression is off.
+    sent_compressed_bytes: Family<PeerLabels, Counter>,
+}
+
+impl PeerMetrics {
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "peer_sent_uncompressed_bytes",
+            "Bytes of sync payloads sent to a peer, before compression",
+            self.sent_uncompressed_bytes.clone(),
+        );
+        registry.register(
+            "peer_sent_compressed_bytes",
+            "Bytes of sync payloads sent to a peer, after compression",
+            self.sent_compressed_bytes.clone(),
+        );
+    }
+
+    /// The counters for a single peer, sharing their values with the registered ones.
+    pub fn for_peer(&self, id: u64) -> PeerByteCounters {
+        let labels = PeerLabels {
+            peer: id.to_string(),
+        };
+        PeerByteCounters {
+            uncompressed: self.sent_uncompressed_bytes.get_or_create(&labels).clone(),
+            compressed: self.sent_compressed_bytes.get_or_create(&labels).clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PeerByteCounters {
+    pub uncompressed: Counter,
+    pub compressed: Counter,
+}
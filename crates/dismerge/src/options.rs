@@ -4,6 +4,9 @@ use clap::Parser;
 
 use crate::{persister::PersisterDispatcher, DocPersister};
 
+/// The quota used when none is given, the same as etcd.
+pub(crate) const DEFAULT_QUOTA_BACKEND_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
 #[derive(Debug, Parser)]
 pub struct Options {
     #[clap(long, default_value = "default")]
@@ -49,6 +52,12 @@ pub struct Options {
     #[clap(long, default_value = "100000")]
     pub snapshot_count: u32,
 
+    /// Raise a NOSPACE alarm, stopping writes, when the db size exceeds this many bytes.
+    ///
+    /// 0 uses the default of 2GiB.
+    #[clap(long, default_value = "0")]
+    pub quota_backend_bytes: u64,
+
     /// How frequently to trigger a db flush.
     ///
     /// A flush will unblock all waiting requests.
@@ -59,6 +68,12 @@ pub struct Options {
     #[clap(long, default_value = "100")]
     pub sync_interval_ms: u64,
 
+    /// Compress sync payloads sent to peers with gzip.
+    ///
+    /// Peers always accept compressed payloads so this can be set on some members and not others.
+    #[clap(long)]
+    pub peer_compression: bool,
+
     /// Filter logs using this string, rather than the `RUST_LOG` environment variable.
     #[clap(long)]
     pub log_filter: Option<String>,
@@ -77,6 +92,13 @@ pub struct Options {
     /// Duration of request before it times out, in milliseconds.
     #[clap(long, default_value = "10000")]
     pub timeout: u64,
+
+    /// Seed the data dir from a snapshot file, as saved by `etcdctl snapshot save`, before
+    /// starting.
+    ///
+    /// The data dir must not already contain a document.
+    #[clap(long)]
+    pub restore_snapshot: Option<PathBuf>,
 }
 
 impl Default for Options {
@@ -100,13 +122,16 @@ impl Default for Options {
             peer_trusted_ca_file: Default::default(),
             peer_client_cert_auth: Default::default(),
             snapshot_count: Default::default(),
+            quota_backend_bytes: Default::default(),
             flush_interval_ms: 1,
             sync_interval_ms: 10,
+            peer_compression: false,
             log_filter: None,
             no_colour: false,
             persister: Default::default(),
             concurrency_limit: 1000,
             timeout: 1000,
+            restore_snapshot: None,
         }
     }
 }
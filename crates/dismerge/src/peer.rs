@@ -1,26 +1,81 @@
 use automerge::sync;
+use futures::Stream;
 use mergeable_proto::etcdserverpb::Member;
-use peer_proto::{HelloRequest, HelloResponse, SyncChanges, SyncMessage};
+use peer_proto::{
+    peer_message, HelloRequest, HelloResponse, PeerMessage, SyncAck, SyncChanges, SyncMessage,
+};
+use prometheus_client::metrics::counter::Counter;
+use prost::Message as _;
 use std::{
     collections::HashMap,
     fmt::Debug,
-    sync::Arc,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::body::BoxBody;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::{http, Body as _};
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tower::util::BoxCloneService;
+use tower::ServiceBuilder;
 use tracing::{debug, info, warn};
 
 use dismerge_core::{value::Value, Syncer};
 
+use crate::metrics::PeerMetrics;
 use crate::{Doc, DocPersister};
 
 const SYNC_SLEEP_DURATION: Duration = Duration::from_millis(10);
 
+/// How many messages can be sent to a peer before it has to acknowledge applying them.
+const MAX_IN_FLIGHT_MESSAGES: usize = 16;
+
+/// Longest to wait before reopening a broken sync stream.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(5);
+
+type PeerClient = peer_proto::peer_client::PeerClient<
+    BoxCloneService<
+        http::Request<BoxBody>,
+        http::Response<tonic::transport::Body>,
+        tonic::transport::Error,
+    >,
+>;
+
+/// Make a client for syncing with a peer, counting the bytes it sends after any compression.
+fn peer_client(channel: Channel, compression: bool, sent_bytes: Counter) -> PeerClient {
+    let service = ServiceBuilder::new()
+        .map_request(move |request: http::Request<BoxBody>| {
+            let sent_bytes = sent_bytes.clone();
+            request.map(|body| {
+                body.map_data(move |data| {
+                    sent_bytes.inc_by(data.len() as u64);
+                    data
+                })
+                .boxed_unsync()
+            })
+        })
+        .service(channel);
+    let client =
+        PeerClient::new(BoxCloneService::new(service)).accept_compressed(CompressionEncoding::Gzip);
+    if compression {
+        client.send_compressed(CompressionEncoding::Gzip)
+    } else {
+        client
+    }
+}
+
 pub struct DocumentChangedSyncer {
     pub notify: Arc<tokio::sync::Notify>,
     pub local_change_sender: broadcast::Sender<Vec<Vec<u8>>>,
     pub member_changed: broadcast::Sender<Member>,
+    pub member_removed: broadcast::Sender<u64>,
 }
 
 #[tonic::async_trait]
@@ -42,11 +97,26 @@ impl Syncer for DocumentChangedSyncer {
     async fn member_change(&mut self, member: &Member) {
         self.member_changed.send(member.clone()).unwrap();
     }
+
+    async fn member_remove(&mut self, id: u64) {
+        self.member_removed.send(id).unwrap();
+    }
 }
 
 pub struct PeerSyncer {
     address: String,
-    sender: mpsc::Sender<Message>,
+    sender: mpsc::Sender<PeerMessage>,
+    /// Messages sent on the sync stream that the peer hasn't acknowledged yet.
+    in_flight: Arc<AtomicUsize>,
+    /// The task keeping the sync stream to the peer open, which would otherwise keep retrying the
+    /// address after this syncer is gone.
+    stream_task: JoinHandle<()>,
+}
+
+impl Drop for PeerSyncer {
+    fn drop(&mut self) {
+        self.stream_task.abort();
+    }
 }
 
 impl Debug for PeerSyncer {
@@ -58,11 +128,14 @@ impl Debug for PeerSyncer {
 }
 
 impl PeerSyncer {
-    async fn new(
+    async fn new<P: DocPersister, V: Value>(
+        document: Doc<P, V>,
         address: String,
         ca_certificate: &Option<Vec<u8>>,
         member: Member,
         their_id: Option<u64>,
+        compression: bool,
+        metrics: &PeerMetrics,
     ) -> (u64, Self) {
         debug!(address, "Setting up peer syncer");
         let (msg_sender, mut msg_receiver) = mpsc::channel(1);
@@ -74,15 +147,15 @@ impl PeerSyncer {
         if let Some(tls_config) = tls_config {
             channel = channel.tls_config(tls_config).unwrap();
         }
-        let (id, mut client) = loop {
+        let (id, connected) = loop {
             debug!(address = address_clone, "Trying to connect to peer");
             match channel.connect().await {
-                Ok(channel) => {
-                    let mut client = peer_proto::peer_client::PeerClient::new(channel);
+                Ok(connected) => {
+                    let mut client = peer_proto::peer_client::PeerClient::new(connected.clone());
                     info!(address=?address_clone, "Connected client");
                     // if we already know who they are then don't worry about finding out
                     if let Some(their_id) = their_id {
-                        break (their_id, client);
+                        break (their_id, connected);
                     }
                     // otherwise introduce ourselves
                     let request = HelloRequest {
@@ -98,7 +171,7 @@ impl PeerSyncer {
                         Ok(res) => {
                             let res = res.into_inner();
                             debug!(?res, "Got peer member id");
-                            break (res.themselves.unwrap().id, client);
+                            break (res.themselves.unwrap().id, connected);
                         }
                         Err(err) => {
                             debug!(%err, "Error trying to get peer member id");
@@ -113,63 +186,59 @@ impl PeerSyncer {
         };
         debug!(address = address_clone, "Connected client");
 
-        tokio::spawn(async move {
-            debug!(address = address_clone, "Waiting on messages to send");
+        let sent_bytes = metrics.for_peer(id);
+        let mut client = peer_client(connected, compression, sent_bytes.compressed.clone());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let in_flight_clone = Arc::clone(&in_flight);
+        let stream_task = tokio::spawn(async move {
+            let in_flight = in_flight_clone;
+            let mut retry_wait = Duration::from_millis(1);
             loop {
-                match msg_receiver.recv().await {
-                    Some(message) => {
-                        debug!(address=?address_clone, "Sending message on client");
-                        let message: Message = message;
-
-                        // Try and send the message, retrying if it fails
-                        let mut retry_wait = Duration::from_millis(1);
-                        let retry_max = Duration::from_secs(5);
-
-                        loop {
-                            let res = match message.clone() {
-                                Message::SyncMessage(m) => client.sync_one(m).await.map(|_| ()),
-                                Message::SyncChanges(m) => client.send_changes(m).await.map(|_| ()),
-                            };
-                            debug!(address=?address_clone, "Sent sync message to client");
-                            match res {
-                                Ok(()) => {
-                                    break;
-                                }
-                                Err(error) => {
-                                    // don't race into the next request
-                                    tokio::time::sleep(retry_wait).await;
-
-                                    // exponential backoff
-                                    retry_wait *= 2;
-                                    // but don't let it get too high!
-                                    retry_wait = std::cmp::min(retry_wait, retry_max);
-
-                                    warn!(%error, ?retry_wait, address=?address_clone, "Got error sending sync message to peer");
-                                    // had an error, reconnect the client
-                                    client = loop {
-                                        match channel.connect().await {
-                                            Ok(channel) => {
-                                                let client =
-                                                    peer_proto::peer_client::PeerClient::new(
-                                                        channel,
-                                                    );
-                                                info!(address=?address_clone, "Reconnected client");
-                                                break client;
-                                            }
-                                            Err(err) => {
-                                                warn!(address=?address_clone, %err, "Failed to reconnect client");
-                                                tokio::time::sleep(Duration::from_millis(1000))
-                                                    .await;
-                                            }
-                                        }
-                                    };
-                                }
+                let (stream_sender, stream_receiver) = mpsc::channel(MAX_IN_FLIGHT_MESSAGES);
+                let error = match client.sync(ReceiverStream::new(stream_receiver)).await {
+                    Ok(acks) => {
+                        info!(address=?address_clone, "Opened sync stream to peer");
+                        retry_wait = Duration::from_millis(1);
+                        in_flight.store(0, Ordering::SeqCst);
+                        // anything in flight on an earlier stream may have been lost
+                        document.lock().await.resume_sync(id);
+                        let acks = acks.into_inner();
+                        match forward_messages(
+                            &mut msg_receiver,
+                            stream_sender,
+                            acks,
+                            &in_flight,
+                            &sent_bytes.uncompressed,
+                        )
+                        .await
+                        {
+                            Some(error) => error,
+                            None => {
+                                warn!(address=?address_clone, "No more messages to send, closing");
+                                break;
                             }
                         }
                     }
-                    None => {
-                        warn!(address=?address_clone, "No more messages to send, closing");
-                        break;
+                    Err(error) => error,
+                };
+                if error.code() == tonic::Code::FailedPrecondition {
+                    // the peer won't take a new stream either, such as when it has removed us
+                    warn!(%error, address=?address_clone, "Peer refused sync stream");
+                    break;
+                }
+
+                warn!(%error, ?retry_wait, address=?address_clone, "Sync stream to peer broke");
+                // don't race into reconnecting
+                tokio::time::sleep(retry_wait).await;
+                // exponential backoff, but don't let it get too high!
+                retry_wait = std::cmp::min(retry_wait * 2, MAX_RETRY_WAIT);
+                match channel.connect().await {
+                    Ok(channel) => {
+                        client = peer_client(channel, compression, sent_bytes.compressed.clone());
+                        info!(address=?address_clone, "Reconnected client");
+                    }
+                    Err(err) => {
+                        warn!(address=?address_clone, %err, "Failed to reconnect client");
                     }
                 }
             }
@@ -179,6 +248,8 @@ impl PeerSyncer {
             Self {
                 address,
                 sender: msg_sender,
+                in_flight,
+                stream_task,
             },
         )
     }
@@ -188,17 +259,22 @@ impl PeerSyncer {
         debug!(?from, ?to, ?name, "Sending message to peer");
 
         let _: Result<_, _> = sender
-            .send(Message::SyncMessage(SyncMessage {
-                from,
-                to,
-                name,
-                data: msg,
-            }))
+            .send(PeerMessage {
+                message: Some(peer_message::Message::SyncMessage(SyncMessage {
+                    from,
+                    to,
+                    name,
+                    data: msg,
+                    // dismerge keeps no hybrid logical clock
+                    hlc: 0,
+                })),
+            })
             .await;
     }
 
+    /// Whether the peer can take another message, it has to acknowledge the ones in flight first.
     pub fn can_send(&self) -> bool {
-        self.sender.capacity() > 0
+        self.sender.capacity() > 0 && self.in_flight.load(Ordering::SeqCst) < MAX_IN_FLIGHT_MESSAGES
     }
 
     pub async fn send_local_changes(
@@ -212,30 +288,74 @@ impl PeerSyncer {
         debug!(?from, ?to, ?name, "Sending message to peer");
 
         let _: Result<_, _> = sender
-            .send(Message::SyncChanges(SyncChanges {
-                from,
-                to,
-                name,
-                changes,
-            }))
+            .send(PeerMessage {
+                message: Some(peer_message::Message::SyncChanges(SyncChanges {
+                    from,
+                    to,
+                    name,
+                    changes,
+                    hlc: 0,
+                })),
+            })
             .await;
     }
 }
 
+/// Send messages on to the peer's sync stream until the syncer is dropped, returning `None`, or
+/// the stream breaks.
+async fn forward_messages(
+    messages: &mut mpsc::Receiver<PeerMessage>,
+    stream: mpsc::Sender<PeerMessage>,
+    mut acks: tonic::Streaming<SyncAck>,
+    in_flight: &AtomicUsize,
+    sent_bytes: &Counter,
+) -> Option<tonic::Status> {
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let message = message?;
+                sent_bytes.inc_by(message.encoded_len() as u64);
+                // counted before sending so that the ack can't arrive first
+                in_flight.fetch_add(1, Ordering::SeqCst);
+                if stream.send(message).await.is_err() {
+                    return Some(tonic::Status::unavailable("sync stream closed"));
+                }
+            }
+            ack = acks.message() => match ack {
+                Ok(Some(SyncAck {})) => {
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+                Ok(None) => return Some(tonic::Status::unavailable("sync stream closed by peer")),
+                Err(error) => return Some(error),
+            },
+        }
+    }
+}
+
 pub struct PeerServerInner<P, V> {
     pub document: Doc<P, V>,
     // map from peer id to the syncer running for them
     connections: HashMap<u64, PeerSyncer>,
     ca_certificate: Option<Vec<u8>>,
+    // whether to compress what we send to peers
+    compression: bool,
+    metrics: PeerMetrics,
 }
 
 impl<P: DocPersister, V: Value> PeerServerInner<P, V> {
-    async fn new(document: Doc<P, V>, ca_certificate: Option<Vec<u8>>) -> Self {
+    async fn new(
+        document: Doc<P, V>,
+        ca_certificate: Option<Vec<u8>>,
+        compression: bool,
+        metrics: PeerMetrics,
+    ) -> Self {
         let connections = HashMap::new();
         let s = Self {
             document,
             connections,
             ca_certificate,
+            compression,
+            metrics,
         };
         s
     }
@@ -304,6 +424,7 @@ impl<P, V> Clone for PeerServer<P, V> {
 }
 
 impl<P: DocPersister, V: Value> PeerServer<P, V> {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         document: Doc<P, V>,
         name: &str,
@@ -311,10 +432,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         notify: Arc<tokio::sync::Notify>,
         mut local_changes: broadcast::Receiver<Vec<Vec<u8>>>,
         mut member_changed: broadcast::Receiver<Member>,
+        mut member_removed: broadcast::Receiver<u64>,
         ca_certificate: Option<Vec<u8>>,
+        compression: bool,
+        metrics: PeerMetrics,
     ) -> Self {
         let inner = Arc::new(Mutex::new(
-            PeerServerInner::new(document, ca_certificate.clone()).await,
+            PeerServerInner::new(document, ca_certificate, compression, metrics).await,
         ));
         let s = Self { inner };
 
@@ -343,6 +467,14 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
             }
         });
 
+        let s_clone = s.clone();
+        tokio::spawn(async move {
+            // stop syncing with members removed from the document
+            while let Ok(id) = member_removed.recv().await {
+                s_clone.member_removed(id).await;
+            }
+        });
+
         let s_clone = s.clone();
         tokio::spawn(async move {
             // handle local changes
@@ -392,32 +524,38 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
     }
 
     pub async fn member_changed(&self, member: Member) {
-        let inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await;
+        let us = inner.member().await;
+        if member.id == us.id {
+            debug!(?member, "Skipping updated member as it is us!");
+            return;
+        }
         // see if we have a connection to that member
         if let Some(syncer) = inner.connections.get(&member.id) {
-            // they have the same address
-            let our_peer_address = member.peer_ur_ls.first().unwrap();
-            let us = inner.member().await;
-            let name = us.name;
             if member.peer_ur_ls.contains(&syncer.address) {
                 // no change
-            } else if member.peer_ur_ls.contains(&our_peer_address) {
-                debug!(?name, ?member, "Skipping updated member as it is us!");
-            } else {
-                warn!(?name, ?member, ?our_peer_address, ?syncer.address, "hit update member");
-                // FIXME: should update address somehow
+                return;
             }
-        } else {
-            let s = self.clone();
-            tokio::spawn(async move {
-                let name = member.name;
-                s.add_connection(
-                    &name,
-                    member.peer_ur_ls.first().unwrap().to_owned(),
-                    Some(member.id),
-                )
-                .await;
-            });
+            // their address has changed so drop the old connection and connect to the new one
+            info!(?member, old_address=?syncer.address, "Reconnecting to updated member");
+            inner.connections.remove(&member.id);
+        }
+        let s = self.clone();
+        tokio::spawn(async move {
+            let name = member.name;
+            s.add_connection(
+                &name,
+                member.peer_ur_ls.first().unwrap().to_owned(),
+                Some(member.id),
+            )
+            .await;
+        });
+    }
+
+    /// Drop the connection to a member that has been removed from the cluster.
+    pub async fn member_removed(&self, id: u64) {
+        if self.inner.lock().await.connections.remove(&id).is_some() {
+            info!(member_id=?id, "Dropped connection to removed member");
         }
     }
 
@@ -449,6 +587,48 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         }
     }
 
+    /// Apply a sync message from a peer, whether it came on a stream or on its own.
+    async fn handle_sync_message(&self, message: SyncMessage) -> Result<(), tonic::Status> {
+        let SyncMessage {
+            from,
+            to,
+            name,
+            data,
+            hlc: _,
+        } = message;
+        self.check_not_removed(from).await?;
+        let message = sync::Message::decode(&data).unwrap();
+        self.receive_message(from, to, name, message).await;
+        Ok(())
+    }
+
+    /// Apply changes pushed from a peer, whether they came on a stream or on their own.
+    async fn handle_sync_changes(&self, changes: SyncChanges) -> Result<(), tonic::Status> {
+        let SyncChanges {
+            from,
+            to,
+            name,
+            changes,
+            hlc: _,
+        } = changes;
+        self.check_not_removed(from).await?;
+        let changes = changes
+            .into_iter()
+            .filter_map(|c| automerge::Change::from_bytes(c).ok());
+        self.receive_changes(from, to, name, changes).await;
+        Ok(())
+    }
+
+    /// Refuse requests from members that have been removed from the cluster.
+    async fn check_not_removed(&self, id: u64) -> Result<(), tonic::Status> {
+        let inner = self.inner.lock().await;
+        if inner.document.lock().await.is_member_removed(id) {
+            warn!(member_id=?id, "Refusing request from removed member");
+            return Err(dismerge_core::Error::MemberRemoved.into());
+        }
+        Ok(())
+    }
+
     /// Check whether a connection has been set up to a peer.
     pub async fn has_connection(&self, id: &u64) -> bool {
         self.inner.lock().await.connections.contains_key(id)
@@ -460,7 +640,19 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         info!("Started adding peer connection");
         let us = self.inner.lock().await.member().await;
         let ca_cert = self.inner.lock().await.ca_certificate.clone();
-        let (id, syncer) = PeerSyncer::new(address.clone(), &ca_cert, us.clone(), their_id).await;
+        let document = Arc::clone(&self.inner.lock().await.document);
+        let compression = self.inner.lock().await.compression;
+        let metrics = self.inner.lock().await.metrics.clone();
+        let (id, syncer) = PeerSyncer::new(
+            document,
+            address.clone(),
+            &ca_cert,
+            us.clone(),
+            their_id,
+            compression,
+            &metrics,
+        )
+        .await;
         self.inner.lock().await.connections.insert(id, syncer);
         info!("Finished adding peer connection");
         id
@@ -506,10 +698,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
                 debug!("Initiating reverse connection");
                 let us = inner.document.lock().await.member();
                 let (id, syncer) = PeerSyncer::new(
+                    Arc::clone(&inner.document),
                     member.peer_ur_ls.first().unwrap().to_owned(),
                     &inner.ca_certificate,
                     us,
                     Some(from),
+                    inner.compression,
+                    &inner.metrics,
                 )
                 .await;
                 debug!("Setup reverse connection");
@@ -557,10 +752,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
                 debug!("Initiating reverse connection");
                 let us = inner.document.lock().await.member();
                 let (id, syncer) = PeerSyncer::new(
+                    Arc::clone(&inner.document),
                     member.peer_ur_ls.first().unwrap().to_owned(),
                     &inner.ca_certificate,
                     us,
                     Some(from),
+                    inner.compression,
+                    &inner.metrics,
                 )
                 .await;
                 debug!("Setup reverse connection");
@@ -577,14 +775,47 @@ impl<P: DocPersister, V: Value> peer_proto::peer_server::Peer for PeerServer<P,
 where
     P::Error: Send,
 {
+    type SyncStream = Pin<Box<dyn Stream<Item = Result<SyncAck, tonic::Status>> + Send + 'static>>;
+
     async fn sync(
         &self,
-        request: tonic::Request<tonic::Streaming<SyncMessage>>,
-    ) -> Result<tonic::Response<peer_proto::Empty>, tonic::Status> {
-        let _request = request.into_inner();
-        warn!("got sync request that isn't implemented");
+        request: tonic::Request<tonic::Streaming<PeerMessage>>,
+    ) -> Result<tonic::Response<Self::SyncStream>, tonic::Status> {
+        let mut messages = request.into_inner();
+        debug!("SYNC stream from peer");
+        let (ack_sender, ack_receiver) = mpsc::channel(MAX_IN_FLIGHT_MESSAGES);
+        let s = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = match messages.message().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(error) => {
+                        debug!(%error, "Sync stream from peer broke");
+                        break;
+                    }
+                };
+                let result = match message.message {
+                    Some(peer_message::Message::SyncMessage(message)) => {
+                        s.handle_sync_message(message).await
+                    }
+                    Some(peer_message::Message::SyncChanges(changes)) => {
+                        s.handle_sync_changes(changes).await
+                    }
+                    // liveness isn't tracked here, so there is nothing to do with heartbeats
+                    Some(peer_message::Message::Heartbeat(_)) | None => Ok(()),
+                };
+                let refused = result.is_err();
+                // stop once the peer has gone away or we've refused it
+                if ack_sender.send(result.map(|()| SyncAck {})).await.is_err() || refused {
+                    break;
+                }
+            }
+        });
 
-        Ok(tonic::Response::new(peer_proto::Empty {}))
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(
+            ack_receiver,
+        ))))
     }
 
     #[tracing::instrument(skip(self, request))]
@@ -594,14 +825,7 @@ where
     ) -> Result<tonic::Response<peer_proto::Empty>, tonic::Status> {
         let request = request.into_inner();
         debug!(?request, "SYNC_ONE from peer");
-        let SyncMessage {
-            from,
-            to,
-            name,
-            data,
-        } = request;
-        let message = sync::Message::decode(&data).unwrap();
-        self.receive_message(from, to, name, message).await;
+        self.handle_sync_message(request).await?;
 
         Ok(tonic::Response::new(peer_proto::Empty {}))
     }
@@ -617,16 +841,7 @@ where
             changes = request.changes.len(),
             "SYNC_CHANGES from peer"
         );
-        let SyncChanges {
-            from,
-            to,
-            name,
-            changes,
-        } = request;
-        let changes = changes
-            .into_iter()
-            .filter_map(|c| automerge::Change::from_bytes(c).ok());
-        self.receive_changes(from, to, name, changes).await;
+        self.handle_sync_changes(request).await?;
 
         Ok(tonic::Response::new(peer_proto::SyncChangesResponse {}))
     }
@@ -639,6 +854,7 @@ where
         let request = request.into_inner();
         debug!(?request, "HELLO from peer");
         let them = request.myself.unwrap();
+        self.check_not_removed(them.id).await?;
         let us = self.inner.lock().await.member().await;
         let s = self.clone();
         if !self.has_connection(&them.id).await {
@@ -703,9 +919,3 @@ pub fn split_initial_cluster(s: &str) -> HashMap<String, String> {
     }
     cluster
 }
-
-#[derive(Clone)]
-enum Message {
-    SyncMessage(SyncMessage),
-    SyncChanges(SyncChanges),
-}
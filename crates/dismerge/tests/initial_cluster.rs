@@ -11,12 +11,18 @@ use std::{
 };
 use tempdir::TempDir;
 use test_log::test;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Certificate;
 use tonic::transport::Channel;
 use tonic::transport::ClientTlsConfig;
 use tracing::info;
 
-use mergeable_proto::etcdserverpb::{MemberAddRequest, PutRequest, RangeRequest, RangeResponse};
+use mergeable_proto::etcdserverpb::watch_client::WatchClient;
+use mergeable_proto::etcdserverpb::watch_request::RequestUnion;
+use mergeable_proto::etcdserverpb::{
+    MemberAddRequest, PutRequest, RangeRequest, RangeResponse, WatchCreateRequest,
+    WatchProgressRequest, WatchRequest,
+};
 
 static BASE_PORT: AtomicU32 = AtomicU32::new(2379);
 const CERT_FILE: &str = "../../certs/server.crt";
@@ -338,6 +344,170 @@ async fn initial_cluster_double() {
     assert_eq!(response1, response2);
 }
 
+#[test(tokio::test)]
+async fn watch_progress_while_buffered() {
+    let data_dir = tempdir::TempDir::new("").unwrap();
+    let (client, peer, metrics) = get_addresses_single();
+    let node_opts = dismerge::Options {
+        name: "node1".to_owned(),
+        data_dir: Some(data_dir.path().to_owned()),
+        advertise_client_urls: vec![client.clone()],
+        initial_advertise_peer_urls: vec![peer.clone()],
+        initial_cluster: format!("node1={peer}"),
+        listen_client_urls: vec![client.clone()],
+        listen_metrics_urls: vec![metrics.clone()],
+        listen_peer_urls: vec![peer.clone()],
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        dismerge::run::<Bytes>(node_opts).await;
+    });
+
+    poll_ready(&metrics).await;
+
+    // on its own channel so that not reading it doesn't hold up the puts
+    let channel = get_channel(None, client.parse().unwrap()).await;
+    let mut watch_client = WatchClient::new(channel);
+    let (request_sender, request_receiver) = tokio::sync::mpsc::channel(1);
+    request_sender
+        .send(WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: b"key".to_vec(),
+                range_end: b"kez".to_vec(),
+                ..Default::default()
+            })),
+        })
+        .await
+        .unwrap();
+    let mut responses = watch_client
+        .watch(ReceiverStream::new(request_receiver))
+        .await
+        .unwrap()
+        .into_inner();
+    let created = responses.message().await.unwrap().unwrap();
+    assert!(created.created);
+
+    // more events than the stream holds without being read, so the watch has to buffer some
+    let puts = 2000;
+    let mut kv_client = get_kv_client(&client).await;
+    for i in 0..puts {
+        kv_client
+            .put(PutRequest {
+                key: format!("key{i}").into_bytes(),
+                value: vec![0; 1024],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    request_sender
+        .send(WatchRequest {
+            request_union: Some(RequestUnion::ProgressRequest(WatchProgressRequest {})),
+        })
+        .await
+        .unwrap();
+
+    let mut events = 0;
+    let progress = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let response = responses.message().await.unwrap().unwrap();
+            if response.events.is_empty() {
+                break response;
+            }
+            events += response.events.len();
+        }
+    })
+    .await
+    .expect("progress notification was dropped");
+    // every event comes before the progress notification
+    assert_eq!(events, puts);
+    assert_eq!(progress.watch_id, -1);
+    assert!(progress.header.is_some());
+}
+
+#[test(tokio::test)]
+async fn initial_cluster_double_compressed() {
+    let (client1, peer1, metrics1) = get_addresses_single();
+    let (client2, peer2, metrics2) = get_addresses_single();
+    let mut data_dirs = Vec::new();
+    for (name, client, peer, metrics) in [
+        ("node1", &client1, &peer1, &metrics1),
+        ("node2", &client2, &peer2, &metrics2),
+    ] {
+        let data_dir = tempdir::TempDir::new("").unwrap();
+        let node_opts = dismerge::Options {
+            name: name.to_owned(),
+            data_dir: Some(data_dir.path().to_owned()),
+            advertise_client_urls: vec![client.clone()],
+            initial_advertise_peer_urls: vec![peer.clone()],
+            initial_cluster: format!("node1={peer1},node2={peer2}"),
+            listen_client_urls: vec![client.clone()],
+            listen_metrics_urls: vec![metrics.clone()],
+            listen_peer_urls: vec![peer.clone()],
+            peer_compression: true,
+            ..Default::default()
+        };
+        data_dirs.push(data_dir);
+        tokio::spawn(async move {
+            dismerge::run::<Bytes>(node_opts).await;
+        });
+
+        poll_ready(metrics).await;
+    }
+
+    let mut kv_client1 = get_kv_client(&client1).await;
+    let mut kv_client2 = get_kv_client(&client2).await;
+    // something that compresses well
+    let value = vec![1; 64 * 1024];
+    kv_client1
+        .put(PutRequest {
+            key: b"key1".to_vec(),
+            value: value.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // give it a chance to sync
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = kv_client2
+        .range(RangeRequest {
+            key: b"key1".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.kvs.len(), 1);
+    assert_eq!(response.kvs[0].value, value);
+
+    let metrics = reqwest::get(&format!("{}/metrics", metrics1))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let compressed = metric_total(&metrics, "peer_sent_compressed_bytes_total");
+    let uncompressed = metric_total(&metrics, "peer_sent_uncompressed_bytes_total");
+    assert!(compressed > 0);
+    assert!(
+        compressed < uncompressed,
+        "compressed {compressed} should be less than uncompressed {uncompressed}"
+    );
+}
+
+/// Sum a counter across all of its labels in a metrics scrape.
+fn metric_total(metrics: &str, name: &str) -> u64 {
+    metrics
+        .lines()
+        .filter(|line| {
+            line.starts_with(&format!("{name}{{")) || line.starts_with(&format!("{name} "))
+        })
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+        .sum()
+}
+
 #[test(tokio::test)]
 #[ignore]
 async fn double_cluster_explicit_add() {
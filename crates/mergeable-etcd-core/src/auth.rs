@@ -0,0 +1,127 @@
+/// The user that must exist, with the root role, before auth can be enabled.
+pub const ROOT_USER: &str = "root";
+/// The role that grants access to everything, including managing auth.
+pub const ROOT_ROLE: &str = "root";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    /// The bcrypt hash of the user's password, users without one can't authenticate with a password.
+    pub password: Option<String>,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionType {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl PermissionType {
+    /// Whether a permission of this type grants the given access.
+    pub fn allows(self, access: PermissionType) -> bool {
+        self == PermissionType::ReadWrite || self == access
+    }
+}
+
+impl From<etcd_proto::authpb::permission::Type> for PermissionType {
+    fn from(value: etcd_proto::authpb::permission::Type) -> Self {
+        match value {
+            etcd_proto::authpb::permission::Type::Read => Self::Read,
+            etcd_proto::authpb::permission::Type::Write => Self::Write,
+            etcd_proto::authpb::permission::Type::Readwrite => Self::ReadWrite,
+        }
+    }
+}
+
+impl From<PermissionType> for etcd_proto::authpb::permission::Type {
+    fn from(value: PermissionType) -> Self {
+        match value {
+            PermissionType::Read => Self::Read,
+            PermissionType::Write => Self::Write,
+            PermissionType::ReadWrite => Self::Readwrite,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permission {
+    pub perm_type: PermissionType,
+    pub key: Vec<u8>,
+    /// The exclusive end of the range, `\0` means all keys from the key.
+    pub range_end: Option<Vec<u8>>,
+}
+
+impl TryFrom<etcd_proto::authpb::Permission> for Permission {
+    type Error = tonic::Status;
+    fn try_from(value: etcd_proto::authpb::Permission) -> Result<Self, Self::Error> {
+        Ok(Self {
+            perm_type: etcd_proto::authpb::permission::Type::from_i32(value.perm_type)
+                .ok_or_else(|| tonic::Status::invalid_argument("unknown permission type"))?
+                .into(),
+            key: value.key,
+            range_end: if value.range_end.is_empty() {
+                None
+            } else {
+                Some(value.range_end)
+            },
+        })
+    }
+}
+
+impl From<Permission> for etcd_proto::authpb::Permission {
+    fn from(value: Permission) -> Self {
+        Self {
+            perm_type: etcd_proto::authpb::permission::Type::from(value.perm_type) as i32,
+            key: value.key,
+            range_end: value.range_end.unwrap_or_default(),
+        }
+    }
+}
+
+/// Check whether the permissions together cover every key in the range `[key, range_end)`.
+pub(crate) fn covers<'a>(
+    permissions: impl Iterator<Item = &'a Permission>,
+    key: &[u8],
+    range_end: Option<&[u8]>,
+) -> bool {
+    // work with half-open intervals, a single key runs up to its immediate successor and an
+    // unbounded end is None
+    let interval = |key: &[u8], range_end: Option<&[u8]>| {
+        let end = match range_end {
+            None => {
+                let mut successor = key.to_vec();
+                successor.push(0);
+                Some(successor)
+            }
+            Some([0]) => None,
+            Some(end) => Some(end.to_vec()),
+        };
+        (key.to_vec(), end)
+    };
+
+    let (start, end) = interval(key, range_end);
+    let mut intervals = permissions
+        .map(|permission| interval(&permission.key, permission.range_end.as_deref()))
+        .collect::<Vec<_>>();
+    intervals.sort();
+
+    // everything from the start up to here is covered
+    let mut covered = start;
+    for (permission_start, permission_end) in intervals {
+        if permission_start > covered {
+            // there is a gap that no later interval can fill
+            break;
+        }
+        match permission_end {
+            None => return true,
+            Some(permission_end) => {
+                if permission_end > covered {
+                    covered = permission_end;
+                }
+            }
+        }
+    }
+    matches!(end, Some(end) if covered >= end)
+}
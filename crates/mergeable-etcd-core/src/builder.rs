@@ -6,6 +6,7 @@ use rand::SeedableRng;
 use rand::{rngs::StdRng, Rng};
 use tokio::sync::watch;
 
+use crate::hlc::{HybridLogicalClock, DEFAULT_MAX_CLOCK_OFFSET_MILLIS};
 use crate::value::Value;
 use crate::{Document, Syncer, Watcher};
 
@@ -22,6 +23,8 @@ pub struct DocumentBuilder<P, S, W, V> {
     auto_flush: bool,
     auto_sync: bool,
     max_outstanding: u64,
+    quota_backend_bytes: Option<u64>,
+    max_clock_offset_millis: u64,
     _value_type: PhantomData<V>,
 }
 
@@ -40,6 +43,8 @@ impl<V> Default for DocumentBuilder<MemoryPersister, (), (), V> {
             auto_flush: true,
             auto_sync: true,
             max_outstanding: 100,
+            quota_backend_bytes: None,
+            max_clock_offset_millis: DEFAULT_MAX_CLOCK_OFFSET_MILLIS,
             _value_type: PhantomData::default(),
         }
     }
@@ -61,6 +66,8 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
+            max_clock_offset_millis: self.max_clock_offset_millis,
             _value_type: PhantomData::default(),
         }
     }
@@ -80,6 +87,8 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
+            max_clock_offset_millis: self.max_clock_offset_millis,
             _value_type: PhantomData::default(),
         }
     }
@@ -99,6 +108,8 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
             auto_flush: self.auto_flush,
             auto_sync: self.auto_sync,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
+            max_clock_offset_millis: self.max_clock_offset_millis,
             _value_type: PhantomData::default(),
         }
     }
@@ -201,6 +212,30 @@ impl<P, S, W, V> DocumentBuilder<P, S, W, V> {
         self.max_outstanding = max_outstanding;
         self
     }
+
+    /// Raise a NOSPACE alarm once the storage grows beyond this many bytes.
+    #[must_use]
+    pub fn with_quota_backend_bytes(mut self, quota_backend_bytes: u64) -> Self {
+        self.quota_backend_bytes = Some(quota_backend_bytes);
+        self
+    }
+
+    pub fn set_quota_backend_bytes(&mut self, quota_backend_bytes: u64) -> &mut Self {
+        self.quota_backend_bytes = Some(quota_backend_bytes);
+        self
+    }
+
+    /// How far ahead of our wall clock another member's clock can be for ours to follow it.
+    #[must_use]
+    pub fn with_max_clock_offset_millis(mut self, max_clock_offset_millis: u64) -> Self {
+        self.max_clock_offset_millis = max_clock_offset_millis;
+        self
+    }
+
+    pub fn set_max_clock_offset_millis(&mut self, max_clock_offset_millis: u64) -> &mut Self {
+        self.max_clock_offset_millis = max_clock_offset_millis;
+        self
+    }
 }
 
 impl<S, W, V> DocumentBuilder<MemoryPersister, S, W, V> {
@@ -238,6 +273,7 @@ where
             members_objid: automerge::ObjId::Root,
             leases_objid: automerge::ObjId::Root,
             cluster_objid: automerge::ObjId::Root,
+            auth_objid: automerge::ObjId::Root,
             rng: StdRng::seed_from_u64(self.seed),
             cache: Default::default(),
             flush_notifier,
@@ -246,9 +282,12 @@ where
             auto_sync: self.auto_sync,
             outstanding: 0,
             max_outstanding: self.max_outstanding,
+            quota_backend_bytes: self.quota_backend_bytes,
             _value_type: PhantomData::default(),
             peer_heads: HashMap::default(),
+            peer_last_heard: HashMap::default(),
             local_changes: Vec::new(),
+            clock: HybridLogicalClock::new(self.max_clock_offset_millis),
         };
         s.init(self.cluster_id);
         s
@@ -8,7 +8,7 @@ pub struct KvCache {
 
 #[derive(Debug)]
 pub struct Cache {
-    kvs: HashMap<String, KvCache>,
+    kvs: HashMap<Vec<u8>, KvCache>,
     // the server revision
     revision: u64,
 }
@@ -23,19 +23,19 @@ impl Default for Cache {
 }
 
 impl Cache {
-    pub fn get(&self, key: &str) -> Option<&KvCache> {
+    pub fn get(&self, key: &[u8]) -> Option<&KvCache> {
         self.kvs.get(key)
     }
 
-    pub fn get_mut(&mut self, key: &str) -> Option<&mut KvCache> {
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut KvCache> {
         self.kvs.get_mut(key)
     }
 
-    pub fn insert(&mut self, key: String, kv_cache: KvCache) {
+    pub fn insert(&mut self, key: Vec<u8>, kv_cache: KvCache) {
         self.kvs.insert(key, kv_cache);
     }
 
-    pub fn remove(&mut self, key: &str) {
+    pub fn remove(&mut self, key: &[u8]) {
         self.kvs.remove(key);
     }
 
@@ -1,5 +1,5 @@
 use crate::value::Value;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 
 use automerge::op_observer::HasPatches;
@@ -19,13 +19,16 @@ use tracing::warn;
 use tracing::{debug, info};
 
 use crate::{
+    alarm::{Alarm, AlarmType},
+    auth::{Permission, PermissionType, User, ROOT_ROLE, ROOT_USER},
     cache::KvCache,
+    hlc::{HlcTimestamp, HybridLogicalClock},
     req_resp::{
         DeleteRangeRequest, DeleteRangeResponse, Header, PutRequest, PutResponse, RangeRequest,
         RangeResponse,
     },
-    transaction::{get_create_mod_version_slow_inner, increment_revision},
-    Syncer, TxnRequest, TxnResponse, VecWatcher, Watcher,
+    transaction::{get_create_mod_version_slow, increment_revision},
+    KvRequest, Syncer, TxnRequest, TxnResponse, VecWatcher, WatchEvent, Watcher,
 };
 
 #[cfg(test)]
@@ -34,14 +37,30 @@ mod tests;
 /// time to live, in seconds
 const DEFAULT_LEASE_TTL: i64 = 30;
 
+/// How often a member lets its peers know that it is still alive when there is nothing to sync,
+/// in milliseconds.
+pub const MEMBER_HEARTBEAT_INTERVAL: u64 = 1_000;
+
+/// How long a member can go without us hearing from it before it is considered down, in
+/// milliseconds.
+const MEMBER_LIVENESS_TIMEOUT: u64 = 5 * MEMBER_HEARTBEAT_INTERVAL;
+
 /// The store of all shared data in the node.
 /// this includes things like cluster_id, members lists, leases and kvs.
 ///
 /// {
 ///   "kvs": { "key1": { "revs": { "001": 0x00, "003": 0x01 }, "lease_id": 0 } },
 ///   "leases": { "1": (), "5": () },
-///   "cluster": { "cluster_id": 0x00, "revision": 4 }
+///   "cluster": {
+///     "cluster_id": 0x00, "revision": 4, "compact_revision": 2, "alarms": { "1/NOSPACE": true },
+///     "removed_members": { "3": true }, "learners": { "4": true }
+///   }
 ///   "members": { 0: {"name": "default", "peer_urls":[], "client_urls":[]} }
+///   "auth": {
+///     "enabled": false,
+///     "users": { "root": { "password": "$2b$...", "roles": { "root": () } } },
+///     "roles": { "reader": { "permissions": { "key1": { "key5": 0 } } } }
+///   }
 /// }
 #[derive(Debug)]
 pub struct Document<P, S, W, V> {
@@ -56,10 +75,14 @@ pub struct Document<P, S, W, V> {
     pub(crate) members_objid: ObjId,
     pub(crate) leases_objid: ObjId,
     pub(crate) cluster_objid: ObjId,
+    pub(crate) auth_objid: ObjId,
     pub(crate) rng: StdRng,
     pub(crate) cache: crate::cache::Cache,
     pub(crate) flush_notifier: watch::Sender<()>,
     pub(crate) peer_heads: HashMap<u64, Vec<ChangeHash>>,
+    /// When we last heard from each peer, in milliseconds from our clock. Only kept locally, each
+    /// member judges for itself which of the others are alive.
+    pub(crate) peer_last_heard: HashMap<u64, u64>,
     // keep this around so that we don't close the channel
     #[allow(dead_code)]
     pub(crate) flush_notifier_receiver: watch::Receiver<()>,
@@ -67,7 +90,12 @@ pub struct Document<P, S, W, V> {
     pub(crate) auto_sync: bool,
     pub(crate) outstanding: u64,
     pub(crate) max_outstanding: u64,
+    /// Raise a NOSPACE alarm when the storage grows beyond this.
+    pub(crate) quota_backend_bytes: Option<u64>,
     pub(crate) local_changes: Vec<automerge::Change>,
+    /// Timestamps for lease refreshes, kept in step with the other members' clocks through the
+    /// sync messages and heartbeats.
+    pub(crate) clock: HybridLogicalClock,
 
     pub(crate) _value_type: PhantomData<V>,
 }
@@ -81,8 +109,17 @@ where
 {
     pub(crate) fn init(&mut self, cluster_id: Option<u64>) {
         if self.am.document_mut().get_heads().is_empty() {
-            self.init_document();
+            debug!("Initialising document with 0 actor");
+            self.am.document_mut().set_actor(ActorId::from(vec![0]));
+        } else {
+            // anything missing from a document written by an older version is made by this member
+            // rather than the shared 0 actor, which already has changes in the document
+            debug!("Loading existing document");
+            self.am
+                .document_mut()
+                .set_actor(ActorId::from(self.member_id.to_be_bytes()));
         }
+        self.init_document();
 
         self.am
             .document_mut()
@@ -99,12 +136,22 @@ where
 
         // new cluster (assuming we are the first node so add ourselves to the members_list)
         self.add_member_local();
+
+        // don't give out timestamps from before a restart
+        let last_refresh = self
+            .all_lease_ids()
+            .unwrap()
+            .into_iter()
+            .filter_map(|id| self.last_lease_refresh(id))
+            .max();
+        if let Some(last_refresh) = last_refresh {
+            self.clock.restore(last_refresh);
+        }
     }
 
-    /// set up the document's initial structure
+    /// set up the document's structure, finding the parts that a loaded document, such as a
+    /// snapshot, already has and making any that it is missing
     fn init_document(&mut self) {
-        debug!("Initialising document with 0 actor");
-        self.am.document_mut().set_actor(ActorId::from(vec![0]));
         self.am
             .transact::<_, _, AutomergeError>(|tx| {
                 self.kvs_objid = if let Some((_, id)) = tx.get(ROOT, "kvs").unwrap() {
@@ -121,6 +168,14 @@ where
                     tx.put(&self.cluster_objid, "revision", ScalarValue::Uint(1))
                         .unwrap();
                 }
+                // made up front so that members don't make conflicting ones with their first
+                // removals or learners
+                for map in ["removed_members", "learners"] {
+                    if tx.get(&self.cluster_objid, map).unwrap().is_none() {
+                        tx.put_object(&self.cluster_objid, map, ObjType::Map)
+                            .unwrap();
+                    }
+                }
                 self.members_objid = if let Some((_, id)) = tx.get(ROOT, "members").unwrap() {
                     id
                 } else {
@@ -133,6 +188,17 @@ where
                     tx.put_object(ROOT, "leases", ObjType::Map).unwrap()
                 };
 
+                self.auth_objid = if let Some((_, id)) = tx.get(ROOT, "auth").unwrap() {
+                    id
+                } else {
+                    tx.put_object(ROOT, "auth", ObjType::Map).unwrap()
+                };
+                for map in ["users", "roles"] {
+                    if tx.get(&self.auth_objid, map).unwrap().is_none() {
+                        tx.put_object(&self.auth_objid, map, ObjType::Map).unwrap();
+                    }
+                }
+
                 Ok(())
             })
             .unwrap();
@@ -173,6 +239,55 @@ where
         changes + document + sync_states
     }
 
+    /// Save the document to storage and truncate the log of changes it was built from, returning
+    /// the stored sizes from before and after.
+    pub fn defragment(&mut self) -> (StoredSizes, StoredSizes) {
+        // make sure all changes are in storage before replacing them
+        self.flush();
+        let before = self.am.persister().sizes();
+        self.am.compact(&[]).unwrap();
+        self.flush();
+        let after = self.am.persister().sizes();
+        (before, after)
+    }
+
+    /// Save the whole document in its compacted form, for backing up or seeding new nodes from.
+    pub fn snapshot(&mut self) -> Vec<u8> {
+        self.am.document_mut().save()
+    }
+
+    /// Hash the whole document, members that have the same changes have the same hash.
+    pub fn hash(&mut self) -> u32 {
+        crc32fast::hash(&self.snapshot())
+    }
+
+    /// Hash the live key-values at the revision, or the latest one, so that members can check
+    /// that they have converged.
+    pub fn hash_kv(&mut self, revision: Option<u64>) -> crate::Result<u32> {
+        self.check_revision(revision)?;
+        let (_, response, _) = self.range_or_delete_revision(RangeRequest {
+            start: vec![0],
+            end: Some(vec![0]),
+            revision,
+            ..Default::default()
+        })?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        for kv in response.values {
+            let value: Vec<u8> = kv.value.into();
+            // prefix the variable length fields with their lengths so they can't run together
+            hasher.update(&(kv.key.len() as u64).to_be_bytes());
+            hasher.update(&kv.key);
+            hasher.update(&(value.len() as u64).to_be_bytes());
+            hasher.update(&value);
+            hasher.update(&kv.create_revision.to_be_bytes());
+            hasher.update(&kv.mod_revision.to_be_bytes());
+            hasher.update(&kv.version.to_be_bytes());
+            hasher.update(&kv.lease.unwrap_or_default().to_be_bytes());
+        }
+        Ok(hasher.finalize())
+    }
+
     pub fn heads(&mut self) -> Vec<ChangeHash> {
         self.am.document_mut().get_heads()
     }
@@ -194,6 +309,50 @@ where
         self.cache.revision()
     }
 
+    /// Get the revision that the key-value store has been compacted to, revisions before this are
+    /// no longer available.
+    pub fn compact_revision(&self) -> u64 {
+        let document = self.am.document();
+        document
+            .get(ROOT, "cluster")
+            .unwrap()
+            .map_or(0, |(_, cluster)| {
+                document
+                    // take the maximum in case of concurrent compactions
+                    .get_all(&cluster, "compact_revision")
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|(r, _)| r.to_u64())
+                    .max()
+                    .unwrap_or(0)
+            })
+    }
+
+    /// Check that a request for the given revision can be served.
+    fn check_revision(&self, revision: Option<u64>) -> crate::Result<()> {
+        if let Some(revision) = revision {
+            if revision < self.compact_revision() {
+                return Err(crate::Error::Compacted);
+            }
+            if revision > self.revision() {
+                return Err(crate::Error::FutureRevision);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that all of the ranges in a txn request can be served.
+    fn check_txn_revisions(&self, request: &TxnRequest<V>) -> crate::Result<()> {
+        for op in request.success.iter().chain(request.failure.iter()) {
+            match op {
+                KvRequest::Range(range) => self.check_revision(range.revision)?,
+                KvRequest::Txn(txn) => self.check_txn_revisions(txn)?,
+                KvRequest::Put(_) | KvRequest::DeleteRange(_) => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn flush(&mut self) -> usize {
         debug!("flushing!");
         let heads = self.heads();
@@ -204,6 +363,7 @@ where
             debug!(?flushed_bytes, "Flushed db");
         }
         self.flush_notifier.send(()).unwrap();
+        self.check_quota();
         flushed_bytes
     }
 
@@ -246,6 +406,8 @@ where
         &mut self,
         request: PutRequest<V>,
     ) -> crate::Result<oneshot::Receiver<(Header, PutResponse<V>)>> {
+        self.check_not_learner()?;
+        self.check_space()?;
         let mut temp_watcher = VecWatcher::default();
         let result = self
             .am
@@ -284,6 +446,7 @@ where
         &mut self,
         request: DeleteRangeRequest,
     ) -> crate::Result<oneshot::Receiver<(Header, DeleteRangeResponse<V>)>> {
+        self.check_not_learner()?;
         let mut temp_watcher = VecWatcher::default();
         let result = self
             .am
@@ -324,6 +487,7 @@ where
         &mut self,
         request: RangeRequest,
     ) -> crate::Result<oneshot::Receiver<(Header, RangeResponse<V>)>> {
+        self.check_revision(request.revision)?;
         let (result, _) = self
             .am
             .transact::<_, _, AutomergeError>(|txn| {
@@ -346,28 +510,29 @@ where
         Ok(receiver)
     }
 
-    /// Get the values in the half-open interval `[start, end)`.
-    /// Delete revisions are a mapping from the keys that are deleted to the revision they were
-    /// deleted at.
-    // TODO: make this non-mut
-    pub fn range_or_delete_revision(
-        &mut self,
-        request: RangeRequest,
-    ) -> crate::Result<(Header, RangeResponse<V>, BTreeMap<String, u64>)> {
-        let (result, delete_revision) = self
-            .am
-            .transact::<_, _, AutomergeError>(|txn| {
-                Ok(crate::transaction::range(txn, &mut self.cache, request))
-            })
-            .unwrap();
-        let header = self.header()?;
-        Ok((header, result, delete_revision))
+    /// Get the events for the keys in the half-open interval `[start, end)`, or just the start key
+    /// without an end, from the start revision onwards in the order they happened.
+    pub fn history(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        start_revision: u64,
+        prev_kv: bool,
+    ) -> Vec<WatchEvent<V>> {
+        crate::transaction::history(self.am.document(), start, end, start_revision, prev_kv)
     }
 
     pub async fn txn(
         &mut self,
         request: TxnRequest<V>,
     ) -> crate::Result<oneshot::Receiver<(Header, TxnResponse<V>)>> {
+        self.check_txn_revisions(&request)?;
+        if !request.is_read_only() {
+            self.check_not_learner()?;
+        }
+        if request.has_puts() {
+            self.check_space()?;
+        }
         let mut temp_watcher = VecWatcher::default();
         let revision = self.revision();
         let result = self
@@ -408,6 +573,35 @@ where
         Ok(receiver)
     }
 
+    /// Compact the history of the key-value store, removing all revisions older than the given
+    /// one other than those needed to serve reads at it.
+    ///
+    /// If `physical` is set then the document is also compacted in storage before returning.
+    pub fn compact(&mut self, revision: u64, physical: bool) -> crate::Result<Header> {
+        if revision <= self.compact_revision() {
+            return Err(crate::Error::Compacted);
+        }
+        if revision > self.revision() {
+            return Err(crate::Error::FutureRevision);
+        }
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                crate::transaction::compact(txn, revision);
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in compact");
+
+        self.document_changed();
+        if physical {
+            self.flush();
+            self.am.compact(&[]).unwrap();
+        }
+
+        self.header()
+    }
+
     /// Print out the entire document.
     pub fn dump(&self) {
         let serializable = automerge::AutoSerde::from(self.am.document());
@@ -420,12 +614,12 @@ where
         self.am.document_mut().dump()
     }
 
-    pub fn dump_key(&self, key: &str) {
-        println!("Dumping {}", key);
+    pub fn dump_key(&self, key: &[u8]) {
+        println!("Dumping {:?}", key);
         let doc = self.am.document();
         let kvs = doc.get_all(ROOT, "kvs").unwrap();
         for (_, kvs) in kvs {
-            for (_, key_obj) in doc.get_all(&kvs, key).unwrap() {
+            for (_, key_obj) in doc.get_all(&kvs, encode_key(key)).unwrap() {
                 println!("Found key_obj {}", key_obj);
                 let revs_objs = doc.get_all(&key_obj, "revs").unwrap();
                 for (_, revs_obj) in revs_objs {
@@ -445,6 +639,30 @@ where
             .unwrap()
     }
 
+    /// Pick up syncing with a peer after the connection to it was lost. Messages in flight may
+    /// never have arrived, so sync again from the heads last shared with it, which is all that
+    /// the persisted sync state keeps.
+    pub fn resume_sync(&mut self, peer_id: u64) {
+        debug!(?peer_id, "resuming sync");
+        let peer = peer_id.to_be_bytes();
+        let saved = match self.am.persister().get_sync_state(&peer) {
+            Ok(saved) => saved,
+            Err(error) => {
+                warn!(?error, ?peer_id, "Failed to load sync state to resume from");
+                None
+            }
+        };
+        if let Err(error) = self.am.reset_sync_state(&peer) {
+            warn!(?error, ?peer_id, "Failed to reset sync state");
+        }
+        if let Some(saved) = saved {
+            if let Err(error) = self.am.persister_mut().set_sync_state(peer.to_vec(), saved) {
+                warn!(?error, ?peer_id, "Failed to restore sync state");
+            }
+        }
+        self.sync();
+    }
+
     pub fn generate_sync_changes(
         &mut self,
         peer_id: u64,
@@ -470,6 +688,7 @@ where
         changes: impl Iterator<Item = automerge::Change>,
     ) -> crate::Result<()> {
         debug!(?peer_id, "Received sync message");
+        self.heard_from(peer_id);
 
         let mut observer = VecOpObserver::default();
         let heads = self.am.document_mut().get_heads();
@@ -490,6 +709,7 @@ where
         peer_id: u64,
         message: sync::Message<'_>,
     ) -> crate::Result<Result<(), automerge_persistent::Error<P::Error>>> {
+        self.heard_from(peer_id);
         let mut observer = VecOpObserver::default();
         let heads = self.am.document_mut().get_heads();
 
@@ -538,7 +758,7 @@ where
                         .skip(1)
                         .find_map(|parent| {
                             if parent.obj == self.kvs_objid {
-                                Some(parent.prop.to_string())
+                                Some(decode_key(&parent.prop.to_string()))
                             } else {
                                 None
                             }
@@ -553,6 +773,7 @@ where
                                 revision: None,
                                 limit: None,
                                 count_only: false,
+                                ..Default::default()
                             })?;
                         if response.values.is_empty() {
                             // delete occurred
@@ -566,6 +787,7 @@ where
                                         revision: Some(revision - 1),
                                         limit: None,
                                         count_only: false,
+                                        ..Default::default()
                                     })?;
                                 self.watcher
                                     .publish_event(
@@ -596,6 +818,7 @@ where
                                         ),
                                         limit: None,
                                         count_only: false,
+                                        ..Default::default()
                                     })?;
                                 self.watcher
                                     .publish_event(
@@ -611,14 +834,14 @@ where
                             }
                         }
                     } else if obj == self.members_objid {
-                        let member = self
-                            .get_member(
-                                rev.to_string()
-                                    .parse()
-                                    .map_err(|_| crate::Error::NotParseableAsId(rev.to_string()))?,
-                            )
-                            .unwrap();
-                        self.syncer.member_change(&member).await;
+                        // the member may have been removed since
+                        if let Some(member) = self.get_member(
+                            rev.to_string()
+                                .parse()
+                                .map_err(|_| crate::Error::NotParseableAsId(rev.to_string()))?,
+                        ) {
+                            self.syncer.member_change(&member).await;
+                        }
                     } else if let Some(member_id) = self
                         .am
                         .document()
@@ -632,14 +855,13 @@ where
                             }
                         })
                     {
-                        let member = self
-                            .get_member(
-                                member_id
-                                    .parse()
-                                    .map_err(|_| crate::Error::NotParseableAsId(member_id))?,
-                            )
-                            .unwrap();
-                        self.syncer.member_change(&member).await;
+                        if let Some(member) = self.get_member(
+                            member_id
+                                .parse()
+                                .map_err(|_| crate::Error::NotParseableAsId(member_id))?,
+                        ) {
+                            self.syncer.member_change(&member).await;
+                        }
                     }
                 }
                 automerge::op_observer::PatchAction::PutSeq {
@@ -654,7 +876,14 @@ where
                     values: _,
                     conflict: _,
                 } => {}
-                automerge::op_observer::PatchAction::DeleteMap { key: _, opid: _ } => {}
+                automerge::op_observer::PatchAction::DeleteMap { key, opid: _ } => {
+                    if obj == self.members_objid {
+                        let member_id = key
+                            .parse()
+                            .map_err(|_| crate::Error::NotParseableAsId(key.clone()))?;
+                        self.forget_member(member_id).await;
+                    }
+                }
                 automerge::op_observer::PatchAction::DeleteSeq {
                     index: _,
                     length: _,
@@ -677,14 +906,14 @@ where
         Ok(())
     }
 
-    fn refresh_kv_cache(&mut self, key: String) {
+    fn refresh_kv_cache(&mut self, key: Vec<u8>) {
         debug!(?key, "Started refreshing kv cache");
         let document = self.am.document();
-        if let Some((_, key_obj)) = document.get(&self.kvs_objid, &key).unwrap() {
+        if let Some((_, key_obj)) = document.get(&self.kvs_objid, encode_key(&key)).unwrap() {
             if let Some((_, revs_obj)) = document.get(&key_obj, "revs").unwrap() {
                 let revision = document.keys(&revs_obj).next().unwrap();
                 if let Some((create_revision, _mod_revision, version)) =
-                    get_create_mod_version_slow_inner(document.map_range(&revs_obj, ..), &revision)
+                    get_create_mod_version_slow(document, &key_obj, &revs_obj, &revision)
                 {
                     self.cache.insert(
                         key.clone(),
@@ -838,11 +1067,13 @@ where
             id,
             peer_ur_ls: peer_urls,
             client_ur_ls: client_urls,
-            is_learner: false, // unsupported
+            is_learner: self.is_member_learner(id),
         })
     }
 
-    pub async fn add_member(&mut self, peer_urls: Vec<String>) -> Member {
+    /// Add a cluster member with the given peer urls to connect to, learners only receive changes
+    /// until they are promoted.
+    pub async fn add_member(&mut self, peer_urls: Vec<String>, is_learner: bool) -> Member {
         let id: u64 = self.rng.gen();
         let result = self
             .am
@@ -853,13 +1084,25 @@ where
                 let peer_list = txn.put_object(&map, "peer_urls", ObjType::List).unwrap();
                 txn.splice(&peer_list, 0, 0, peer_urls.iter().map(|s| s.into()))
                     .unwrap();
+                if is_learner {
+                    // kept out of the member's map as the member may put its own over it when it
+                    // starts
+                    let learners_objid = if let Some((_, learners_objid)) =
+                        txn.get(&self.cluster_objid, "learners")?
+                    {
+                        learners_objid
+                    } else {
+                        txn.put_object(&self.cluster_objid, "learners", ObjType::Map)?
+                    };
+                    txn.put(&learners_objid, id.to_string(), true)?;
+                }
 
                 Ok(Member {
                     id,
                     name: String::new(),
                     peer_ur_ls: peer_urls,
                     client_ur_ls: vec![],
-                    is_learner: false,
+                    is_learner,
                 })
             })
             .unwrap();
@@ -909,8 +1152,160 @@ where
         result
     }
 
+    /// Remove a member from the cluster for good. The other members drop their connections to it
+    /// and refuse its syncs once they receive the removal.
+    pub async fn remove_member(&mut self, id: u64) -> crate::Result<()> {
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&self.members_objid, id.to_string())?;
+                let removed_objid = if let Some((_, removed_objid)) =
+                    txn.get(&self.cluster_objid, "removed_members")?
+                {
+                    removed_objid
+                } else {
+                    txn.put_object(&self.cluster_objid, "removed_members", ObjType::Map)?
+                };
+                txn.put(&removed_objid, id.to_string(), true)?;
+                if let Some((_, learners_objid)) = txn.get(&self.cluster_objid, "learners")? {
+                    if txn.get(&learners_objid, id.to_string())?.is_some() {
+                        txn.delete(&learners_objid, id.to_string())?;
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        self.forget_member(id).await;
+        debug!("document changed in remove_member");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Replace the peer urls of a member, the others reconnect to it at the new urls once they
+    /// receive the change.
+    pub async fn update_member(
+        &mut self,
+        id: u64,
+        peer_urls: Vec<String>,
+    ) -> crate::Result<Member> {
+        // a member needs somewhere for the others to reach it
+        if peer_urls.is_empty() {
+            return Err(crate::Error::MemberBadUrls);
+        }
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        let in_use = self.list_members()?.into_iter().any(|member| {
+            member.id != id && member.peer_ur_ls.iter().any(|url| peer_urls.contains(url))
+        });
+        if in_use {
+            return Err(crate::Error::PeerUrlExists);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, map) = txn
+                    .get(&self.members_objid, id.to_string())?
+                    .expect("member should exist");
+                let peer_list = txn.put_object(&map, "peer_urls", ObjType::List)?;
+                txn.splice(&peer_list, 0, 0, peer_urls.iter().map(|s| s.into()))?;
+                Ok(())
+            })
+            .unwrap();
+        if id == self.member_id {
+            self.peer_urls = peer_urls;
+        }
+        let member = self.get_member(id).unwrap();
+        self.syncer.member_change(&member).await;
+        debug!("document changed in update_member");
+        self.document_changed();
+        Ok(member)
+    }
+
+    /// Promote a learner to a full member once it has caught up with our changes.
+    pub async fn promote_member(&mut self, id: u64) -> crate::Result<Member> {
+        if self.get_member(id).is_none() {
+            return Err(crate::Error::MemberNotFound);
+        }
+        if !self.is_member_learner(id) {
+            return Err(crate::Error::MemberNotLearner);
+        }
+        if !self.has_caught_up(id) {
+            return Err(crate::Error::LearnerNotReady);
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, learners_objid) = txn
+                    .get(&self.cluster_objid, "learners")?
+                    .expect("learners should exist");
+                txn.delete(&learners_objid, id.to_string())?;
+                Ok(())
+            })
+            .unwrap();
+        let member = self.get_member(id).unwrap();
+        self.syncer.member_change(&member).await;
+        debug!("document changed in promote_member");
+        self.document_changed();
+        Ok(member)
+    }
+
+    /// Whether the member is a learner, so only receives changes.
+    pub fn is_member_learner(&self, id: u64) -> bool {
+        let document = self.am.document();
+        document
+            .get(&self.cluster_objid, "learners")
+            .unwrap()
+            .and_then(|(_, learners_objid)| document.get(&learners_objid, id.to_string()).unwrap())
+            .is_some()
+    }
+
+    /// Whether the member has all of our changes, going by the heads we last shared with it.
+    fn has_caught_up(&mut self, id: u64) -> bool {
+        let sync_state = match self.am.persister().get_sync_state(&id.to_be_bytes()) {
+            Ok(Some(sync_state)) => sync_state,
+            Ok(None) | Err(_) => return false,
+        };
+        let Ok(sync_state) = sync::State::decode(&sync_state) else {
+            return false;
+        };
+        let missing_changes = self.am.document_mut().get_changes(&sync_state.shared_heads);
+        matches!(missing_changes, Ok(changes) if changes.is_empty())
+    }
+
+    /// Check that this member can take writes, which learners can't until they are promoted.
+    pub fn check_not_learner(&self) -> crate::Result<()> {
+        if self.is_member_learner(self.member_id) {
+            Err(crate::Error::NotSupportedForLearner)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the member has been removed from the cluster, so should no longer be synced with.
+    pub fn is_member_removed(&self, id: u64) -> bool {
+        let document = self.am.document();
+        document
+            .get(&self.cluster_objid, "removed_members")
+            .unwrap()
+            .and_then(|(_, removed_objid)| document.get(&removed_objid, id.to_string()).unwrap())
+            .is_some()
+    }
+
+    /// Drop the state kept for syncing with a removed member.
+    async fn forget_member(&mut self, id: u64) {
+        info!(member_id=?id, "Forgetting removed member");
+        self.peer_heads.remove(&id);
+        self.peer_last_heard.remove(&id);
+        if let Err(error) = self.am.reset_sync_state(&id.to_be_bytes()) {
+            warn!(?error, member_id=?id, "Failed to remove sync state for removed member");
+        }
+        self.syncer.member_remove(id).await;
+    }
+
     /// Add a lease to the document with the given ttl, returns none if the id already existed.
     pub fn add_lease(&mut self, id: Option<i64>, ttl_seconds: Option<i64>) -> Option<(i64, i64)> {
+        let now = self.now();
         let (id, ttl) = self
             .am
             .transact::<_, _, AutomergeError>(|txn| {
@@ -942,8 +1337,8 @@ where
                 // and record when the last refresh happened
                 txn.put(
                     &lease_obj,
-                    "last_refresh_secs",
-                    ScalarValue::Timestamp(chrono::Utc::now().timestamp()),
+                    "last_refresh_hlc",
+                    ScalarValue::Uint(now.to_u64()),
                 )
                 .unwrap();
 
@@ -970,7 +1365,10 @@ where
         {
             let (_, keys_obj) = document.get(&lease_obj, "keys").unwrap().unwrap();
             // delete all of the keys that had this lease
-            let keys_to_remove = document.keys(keys_obj).collect::<Vec<_>>();
+            let keys_to_remove = document
+                .keys(keys_obj)
+                .map(|key| decode_key(&key))
+                .collect::<Vec<_>>();
 
             for key_to_remove in keys_to_remove {
                 // soft-delete each kv that has now expired
@@ -1008,17 +1406,18 @@ where
 
     /// Refresh a lease in the document and return the new ttl.
     pub fn refresh_lease(&mut self, id: i64) -> i64 {
+        let now = self.now();
         let ttl = self
             .am
             .transact::<_, _, AutomergeError>(|txn| {
-                let ttl = if let Some((_, lease_obj)) =
+                let ttl = if let Some((automerge::Value::Object(ObjType::Map), lease_obj)) =
                     txn.get(&self.leases_objid, make_lease_string(id)).unwrap()
                 {
                     // update the refresh time
                     txn.put(
                         &lease_obj,
-                        "last_refresh_secs",
-                        ScalarValue::Timestamp(chrono::Utc::now().timestamp()),
+                        "last_refresh_hlc",
+                        ScalarValue::Uint(now.to_u64()),
                     )
                     .unwrap();
 
@@ -1038,34 +1437,59 @@ where
         ttl
     }
 
-    /// Try and return the last refresh of this lease.
-    pub fn last_lease_refresh(&self, id: i64) -> Option<i64> {
-        if let Some((_, lease_obj)) = self
+    /// The object for the lease, if it hasn't been revoked.
+    fn lease_obj(&self, id: i64) -> Option<ObjId> {
+        match self
             .am
             .document()
             .get(&self.leases_objid, make_lease_string(id))
             .unwrap()
         {
-            let (refresh, _) = self
-                .am
-                .document()
-                .get(&lease_obj, "last_refresh_secs")
-                .unwrap()
-                .unwrap();
-            refresh.to_i64()
-        } else {
-            None
+            // revoked leases are left behind as null
+            Some((automerge::Value::Object(ObjType::Map), lease_obj)) => Some(lease_obj),
+            _ => None,
         }
     }
 
+    /// Try and return the last refresh of this lease.
+    pub fn last_lease_refresh(&self, id: i64) -> Option<HlcTimestamp> {
+        let lease_obj = self.lease_obj(id)?;
+        let document = self.am.document();
+        document
+            // take the latest in case of concurrent refreshes on different nodes
+            .get_all(&lease_obj, "last_refresh_hlc")
+            .unwrap()
+            .into_iter()
+            .filter_map(|(refresh, _)| refresh.to_u64())
+            .map(HlcTimestamp::from_u64)
+            .max()
+            .or_else(|| {
+                // leases from before the clock only have the wall clock seconds
+                let (refresh, _) = document.get(&lease_obj, "last_refresh_secs").unwrap()?;
+                Some(HlcTimestamp::from_millis(refresh.to_i64()? as u64 * 1000))
+            })
+    }
+
+    /// The time in milliseconds that the lease expires, if it isn't refreshed before then.
+    fn lease_expiry(&self, id: i64) -> Option<u64> {
+        let last_refresh = self.last_lease_refresh(id)?;
+        let granted_ttl = self.granted_lease_ttl(id)?;
+        Some(last_refresh.millis + granted_ttl as u64 * 1000)
+    }
+
+    /// Try and return the seconds left before this lease expires, counting from its last refresh
+    /// on any node.
+    pub fn remaining_lease_ttl(&self, id: i64) -> Option<i64> {
+        let expiry = self.lease_expiry(id)?;
+        let now = self.clock.current_millis(wall_clock_millis());
+        // a lease that has expired but not been revoked yet has no time left
+        let remaining = expiry.saturating_sub(now);
+        Some(remaining.div_ceil(1000) as i64)
+    }
+
     /// Try and return the ttl allowed for this lease.
     pub fn granted_lease_ttl(&self, id: i64) -> Option<i64> {
-        if let Some((_, lease_obj)) = self
-            .am
-            .document()
-            .get(&self.leases_objid, make_lease_string(id))
-            .unwrap()
-        {
+        if let Some(lease_obj) = self.lease_obj(id) {
             let (ttl, _) = self
                 .am
                 .document()
@@ -1080,9 +1504,13 @@ where
 
     /// List all current leases in the document.
     pub fn all_lease_ids(&self) -> crate::Result<Vec<i64>> {
-        let leases = self.am.document().keys(&self.leases_objid);
+        let leases = self.am.document().map_range(&self.leases_objid, ..);
         let mut ids = Vec::new();
-        for id in leases {
+        for (id, value, _) in leases {
+            if !matches!(value, automerge::Value::Object(ObjType::Map)) {
+                // revoked
+                continue;
+            }
             ids.push(
                 id.parse::<i64>()
                     .map_err(|_| crate::Error::NotParseableAsId(id.to_owned()))?,
@@ -1091,13 +1519,8 @@ where
         Ok(ids)
     }
 
-    pub fn keys_for_lease(&self, id: i64) -> Vec<String> {
-        if let Some((_, lease_obj)) = self
-            .am
-            .document()
-            .get(&self.leases_objid, make_lease_string(id))
-            .unwrap()
-        {
+    pub fn keys_for_lease(&self, id: i64) -> Vec<Vec<u8>> {
+        if let Some(lease_obj) = self.lease_obj(id) {
             let keys_obj = self
                 .am
                 .document()
@@ -1105,11 +1528,632 @@ where
                 .unwrap()
                 .unwrap()
                 .1;
-            self.am.document().keys(&keys_obj).collect()
+            self.am
+                .document()
+                .keys(&keys_obj)
+                .map(|key| decode_key(&key))
+                .collect()
         } else {
             vec![]
         }
     }
+
+    /// A timestamp from this member's clock for a local event.
+    pub fn now(&mut self) -> HlcTimestamp {
+        self.clock.now(wall_clock_millis())
+    }
+
+    /// Move this member's clock past a timestamp from another member, such as on a sync message.
+    ///
+    /// If their clock is too far ahead of ours to follow a CORRUPT alarm is raised for them, as
+    /// the leases they refresh would outlive their ttl.
+    pub fn update_clock(&mut self, from: u64, remote: HlcTimestamp) {
+        if let Err(exceeded) = self.clock.update(remote, wall_clock_millis()) {
+            let alarm = Alarm {
+                member_id: from,
+                alarm: AlarmType::Corrupt,
+            };
+            if self.activate_alarm(alarm) {
+                warn!(
+                    member_id = from,
+                    offset_millis = exceeded.offset_millis,
+                    "Member's clock is too far ahead of ours to follow"
+                );
+            }
+        }
+    }
+
+    /// Record that we have just heard from a peer, whether through syncing or a heartbeat.
+    pub fn heard_from(&mut self, peer_id: u64) {
+        let now = self.clock.current_millis(wall_clock_millis());
+        self.peer_last_heard.insert(peer_id, now);
+    }
+
+    /// The members we have heard from recently, always including this member, leaving out
+    /// learners.
+    pub fn live_members(&self, now: HlcTimestamp) -> crate::Result<Vec<u64>> {
+        let mut live = Vec::new();
+        for member in self.list_members()? {
+            if member.is_learner {
+                continue;
+            }
+            let since_heard = self
+                .peer_last_heard
+                .get(&member.id)
+                .map(|last| now.millis.saturating_sub(*last));
+            let heard_from = matches!(since_heard, Some(since) if since <= MEMBER_LIVENESS_TIMEOUT);
+            if member.id == self.member_id || heard_from {
+                live.push(member.id);
+            }
+        }
+        Ok(live)
+    }
+
+    /// The member responsible for expiring leases, the live member with the lowest id.
+    ///
+    /// Members agree on this while they can all hear from each other, so only one of them revokes
+    /// each lease. When we stop hearing from the owner it times out and the next member takes
+    /// over.
+    pub fn lease_expiry_owner(&self, now: HlcTimestamp) -> crate::Result<Option<u64>> {
+        Ok(self.live_members(now)?.into_iter().min())
+    }
+
+    /// Revoke the leases that have gone their ttl without a refresh, if this member is the one
+    /// responsible for expiring them, returning the revoked ids.
+    ///
+    /// The leases are found from the document each time so nothing needs rebuilding after a
+    /// restart.
+    pub async fn expire_leases(&mut self, now: HlcTimestamp) -> crate::Result<Vec<i64>> {
+        if self.lease_expiry_owner(now)? != Some(self.member_id) {
+            return Ok(Vec::new());
+        }
+        let mut expired = Vec::new();
+        for id in self.all_lease_ids()? {
+            if matches!(self.lease_expiry(id), Some(expiry) if now.millis >= expiry) {
+                expired.push(id);
+            }
+        }
+        for id in &expired {
+            debug!(lease_id = id, "Revoking expired lease");
+            self.remove_lease(*id).await;
+        }
+        Ok(expired)
+    }
+
+    /// Whether clients need to authenticate to make requests.
+    pub fn auth_enabled(&self) -> bool {
+        self.am
+            .document()
+            .get(&self.auth_objid, "enabled")
+            .unwrap()
+            .and_then(|(enabled, _)| enabled.to_bool())
+            .unwrap_or(false)
+    }
+
+    /// The alarms that are raised across the cluster.
+    pub fn alarms(&self) -> Vec<Alarm> {
+        let document = self.am.document();
+        let mut alarms = Vec::new();
+        if let Some((_, alarms_objid)) = document.get(&self.cluster_objid, "alarms").unwrap() {
+            for (key, _, _) in document.map_range(&alarms_objid, ..) {
+                if let Some(alarm) = Alarm::from_key(key) {
+                    alarms.push(alarm);
+                }
+            }
+        }
+        alarms
+    }
+
+    /// Raise the alarm, returns whether it was newly raised.
+    pub fn activate_alarm(&mut self, alarm: Alarm) -> bool {
+        if self.alarms().contains(&alarm) {
+            return false;
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let alarms_objid =
+                    if let Some((_, id)) = txn.get(&self.cluster_objid, "alarms").unwrap() {
+                        id
+                    } else {
+                        txn.put_object(&self.cluster_objid, "alarms", ObjType::Map)?
+                    };
+                txn.put(&alarms_objid, alarm.key(), true)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?alarm, "document changed in activate_alarm");
+        self.document_changed();
+        true
+    }
+
+    /// Disarm the alarm, returns whether it was raised.
+    pub fn deactivate_alarm(&mut self, alarm: Alarm) -> bool {
+        if !self.alarms().contains(&alarm) {
+            return false;
+        }
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let (_, alarms_objid) = txn.get(&self.cluster_objid, "alarms").unwrap().unwrap();
+                txn.delete(&alarms_objid, alarm.key())?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?alarm, "document changed in deactivate_alarm");
+        self.document_changed();
+        true
+    }
+
+    /// Raise a NOSPACE alarm for this member if the storage has grown beyond the quota.
+    fn check_quota(&mut self) {
+        if let Some(quota_backend_bytes) = self.quota_backend_bytes {
+            let db_size = self.db_size();
+            if db_size > quota_backend_bytes {
+                let alarm = Alarm {
+                    member_id: self.member_id,
+                    alarm: AlarmType::NoSpace,
+                };
+                if self.activate_alarm(alarm) {
+                    warn!(?db_size, ?quota_backend_bytes, "Database space exceeded");
+                }
+            }
+        }
+    }
+
+    /// Check that writes can take up more space, which they can't while any member has a NOSPACE
+    /// alarm.
+    pub fn check_space(&self) -> crate::Result<()> {
+        if self
+            .alarms()
+            .iter()
+            .any(|alarm| alarm.alarm == AlarmType::NoSpace)
+        {
+            Err(crate::Error::NoSpace)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Enable auth, the root user must exist and have the root role.
+    pub fn enable_auth(&mut self) -> crate::Result<()> {
+        let root = self
+            .get_user(ROOT_USER)
+            .map_err(|_| crate::Error::RootUserNotExist)?;
+        if !root.roles.iter().any(|role| role == ROOT_ROLE) {
+            return Err(crate::Error::RootRoleNotExist);
+        }
+        self.set_auth_enabled(true);
+        Ok(())
+    }
+
+    /// Disable auth, letting any client make requests.
+    pub fn disable_auth(&mut self) {
+        self.set_auth_enabled(false);
+    }
+
+    fn set_auth_enabled(&mut self, enabled: bool) {
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.put(&self.auth_objid, "enabled", enabled)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!(?enabled, "document changed in set_auth_enabled");
+        self.document_changed();
+    }
+
+    /// Get the users or roles map in the auth object.
+    fn auth_map(&self, map: &str) -> ObjId {
+        self.am
+            .document()
+            .get(&self.auth_objid, map)
+            .unwrap()
+            .unwrap()
+            .1
+    }
+
+    /// Get the object for the named user or role.
+    fn auth_entry(&self, map: &str, name: &str) -> Option<ObjId> {
+        match self.am.document().get(self.auth_map(map), name).unwrap() {
+            Some((automerge::Value::Object(ObjType::Map), obj)) => Some(obj),
+            _ => None,
+        }
+    }
+
+    /// Add a user, with the bcrypt hash of their password if they have one.
+    pub fn add_user(&mut self, name: &str, password: Option<String>) -> crate::Result<()> {
+        if name.is_empty() {
+            return Err(crate::Error::UserEmpty);
+        }
+        if self.auth_entry("users", name).is_some() {
+            return Err(crate::Error::UserAlreadyExists);
+        }
+
+        let users = self.auth_map("users");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let user = txn.put_object(&users, name, ObjType::Map)?;
+                match password {
+                    Some(password) => txn.put(&user, "password", password)?,
+                    None => txn.put(&user, "password", ())?,
+                }
+                txn.put_object(&user, "roles", ObjType::Map)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in add_user");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> crate::Result<User> {
+        let user = self
+            .auth_entry("users", name)
+            .ok_or(crate::Error::UserNotFound)?;
+        let document = self.am.document();
+        let password = document
+            .get(&user, "password")
+            .unwrap()
+            .and_then(|(password, _)| password.into_string().ok());
+        let (_, roles) = document.get(&user, "roles").unwrap().unwrap();
+        Ok(User {
+            name: name.to_owned(),
+            password,
+            roles: document.keys(&roles).collect(),
+        })
+    }
+
+    /// List the names of all users, in order.
+    pub fn list_users(&self) -> Vec<String> {
+        self.am.document().keys(self.auth_map("users")).collect()
+    }
+
+    pub fn delete_user(&mut self, name: &str) -> crate::Result<()> {
+        // the root user is needed while auth is enabled
+        if self.auth_enabled() && name == ROOT_USER {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        if self.auth_entry("users", name).is_none() {
+            return Err(crate::Error::UserNotFound);
+        }
+
+        let users = self.auth_map("users");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.delete(&users, name))
+            .unwrap();
+        debug!("document changed in delete_user");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Change the bcrypt hash of the user's password.
+    pub fn change_password(&mut self, name: &str, password: Option<String>) -> crate::Result<()> {
+        let user = self
+            .auth_entry("users", name)
+            .ok_or(crate::Error::UserNotFound)?;
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                match password {
+                    Some(password) => txn.put(&user, "password", password)?,
+                    None => txn.put(&user, "password", ())?,
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in change_password");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn grant_role(&mut self, user: &str, role: &str) -> crate::Result<()> {
+        let user = self
+            .auth_entry("users", user)
+            .ok_or(crate::Error::UserNotFound)?;
+        // the root role doesn't need to be added before it is granted
+        if role != ROOT_ROLE && self.auth_entry("roles", role).is_none() {
+            return Err(crate::Error::RoleNotFound);
+        }
+        let (_, roles) = self.am.document().get(&user, "roles").unwrap().unwrap();
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.put(&roles, role, ()))
+            .unwrap();
+        debug!("document changed in grant_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn revoke_role(&mut self, user: &str, role: &str) -> crate::Result<()> {
+        // the root user needs to keep the root role while auth is enabled
+        if self.auth_enabled() && user == ROOT_USER && role == ROOT_ROLE {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        let user = self
+            .auth_entry("users", user)
+            .ok_or(crate::Error::UserNotFound)?;
+        let (_, roles) = self.am.document().get(&user, "roles").unwrap().unwrap();
+        if self.am.document().get(&roles, role).unwrap().is_none() {
+            return Err(crate::Error::RoleNotGranted);
+        }
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| txn.delete(&roles, role))
+            .unwrap();
+        debug!("document changed in revoke_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    pub fn add_role(&mut self, name: &str) -> crate::Result<()> {
+        if name.is_empty() {
+            return Err(crate::Error::RoleEmpty);
+        }
+        if self.auth_entry("roles", name).is_some() {
+            return Err(crate::Error::RoleAlreadyExists);
+        }
+
+        let roles = self.auth_map("roles");
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let role = txn.put_object(&roles, name, ObjType::Map)?;
+                txn.put_object(&role, "permissions", ObjType::Map)?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in add_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Get the permissions granted to the role.
+    pub fn get_role(&self, name: &str) -> crate::Result<Vec<Permission>> {
+        let role = self
+            .auth_entry("roles", name)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let document = self.am.document();
+        let (_, permissions) = document.get(&role, "permissions").unwrap().unwrap();
+        let mut result = Vec::new();
+        for (key, _, key_obj) in document.map_range(&permissions, ..) {
+            for (range_end, perm_type, _) in document.map_range(&key_obj, ..) {
+                let perm_type = perm_type.to_i64().unwrap() as i32;
+                // only known types are granted, skip anything else rather than failing
+                let Some(perm_type) = etcd_proto::authpb::permission::Type::from_i32(perm_type)
+                else {
+                    continue;
+                };
+                result.push(Permission {
+                    perm_type: perm_type.into(),
+                    key: decode_key(key),
+                    range_end: if range_end.is_empty() {
+                        None
+                    } else {
+                        Some(decode_key(range_end))
+                    },
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    /// List the names of all roles, in order.
+    pub fn list_roles(&self) -> Vec<String> {
+        self.am.document().keys(self.auth_map("roles")).collect()
+    }
+
+    /// Delete the role, revoking it from any users that have it.
+    pub fn delete_role(&mut self, name: &str) -> crate::Result<()> {
+        // the root role is needed while auth is enabled
+        if self.auth_enabled() && name == ROOT_ROLE {
+            return Err(crate::Error::InvalidAuthMgmt);
+        }
+        if self.auth_entry("roles", name).is_none() {
+            return Err(crate::Error::RoleNotFound);
+        }
+
+        let roles = self.auth_map("roles");
+        let user_roles = self
+            .list_users()
+            .into_iter()
+            .filter_map(|user| {
+                let user = self.auth_entry("users", &user)?;
+                let (_, roles) = self.am.document().get(&user, "roles").unwrap()?;
+                self.am.document().get(&roles, name).unwrap()?;
+                Some(roles)
+            })
+            .collect::<Vec<_>>();
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&roles, name)?;
+                for user_roles in &user_roles {
+                    txn.delete(user_roles, name)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in delete_role");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Grant the permission to the role, replacing any existing one for the same range.
+    pub fn grant_permission(&mut self, role: &str, permission: Permission) -> crate::Result<()> {
+        let role = self
+            .auth_entry("roles", role)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let (_, permissions) = self
+            .am
+            .document()
+            .get(&role, "permissions")
+            .unwrap()
+            .unwrap();
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                let key = encode_key(&permission.key);
+                let key_obj = if let Some((_, key_obj)) = txn.get(&permissions, &key)? {
+                    key_obj
+                } else {
+                    txn.put_object(&permissions, &key, ObjType::Map)?
+                };
+                let perm_type = etcd_proto::authpb::permission::Type::from(permission.perm_type);
+                txn.put(
+                    &key_obj,
+                    encode_key(permission.range_end.as_deref().unwrap_or_default()),
+                    perm_type as i64,
+                )?;
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in grant_permission");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Revoke the permission for the range `[key, range_end)` from the role.
+    pub fn revoke_permission(
+        &mut self,
+        role: &str,
+        key: &[u8],
+        range_end: Option<&[u8]>,
+    ) -> crate::Result<()> {
+        let role = self
+            .auth_entry("roles", role)
+            .ok_or(crate::Error::RoleNotFound)?;
+        let document = self.am.document();
+        let (_, permissions) = document.get(&role, "permissions").unwrap().unwrap();
+        let key = encode_key(key);
+        let range_end = encode_key(range_end.unwrap_or_default());
+        let (_, key_obj) = document
+            .get(&permissions, &key)
+            .unwrap()
+            .ok_or(crate::Error::PermissionNotGranted)?;
+        if document.get(&key_obj, &range_end).unwrap().is_none() {
+            return Err(crate::Error::PermissionNotGranted);
+        }
+
+        self.am
+            .transact::<_, _, AutomergeError>(|txn| {
+                txn.delete(&key_obj, range_end)?;
+                if txn.length(&key_obj) == 0 {
+                    txn.delete(&permissions, key)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        debug!("document changed in revoke_permission");
+        self.document_changed();
+        Ok(())
+    }
+
+    /// Check that the user has the root role, needed to manage auth and the cluster.
+    pub fn check_admin(&self, user: &str) -> crate::Result<()> {
+        let user = self
+            .get_user(user)
+            .map_err(|_| crate::Error::PermissionDenied)?;
+        if user.roles.iter().any(|role| role == ROOT_ROLE) {
+            Ok(())
+        } else {
+            Err(crate::Error::PermissionDenied)
+        }
+    }
+
+    /// Check that the user's roles give them the access to every key in the range
+    /// `[key, range_end)`.
+    pub fn check_permission(
+        &self,
+        user: &str,
+        key: &[u8],
+        range_end: Option<&[u8]>,
+        access: PermissionType,
+    ) -> crate::Result<()> {
+        let user = self
+            .get_user(user)
+            .map_err(|_| crate::Error::PermissionDenied)?;
+        if user.roles.iter().any(|role| role == ROOT_ROLE) {
+            return Ok(());
+        }
+
+        let permissions = user
+            .roles
+            .iter()
+            .filter_map(|role| self.get_role(role).ok())
+            .flatten()
+            .filter(|permission| permission.perm_type.allows(access))
+            .collect::<Vec<_>>();
+        if crate::auth::covers(permissions.iter(), key, range_end) {
+            Ok(())
+        } else {
+            Err(crate::Error::PermissionDenied)
+        }
+    }
+
+    pub fn check_range_permission(&self, user: &str, request: &RangeRequest) -> crate::Result<()> {
+        self.check_permission(
+            user,
+            &request.start,
+            request.end.as_deref(),
+            PermissionType::Read,
+        )
+    }
+
+    pub fn check_put_permission(&self, user: &str, request: &PutRequest<V>) -> crate::Result<()> {
+        self.check_permission(user, &request.key, None, PermissionType::Write)?;
+        // returning the previous value needs read access too
+        if request.prev_kv {
+            self.check_permission(user, &request.key, None, PermissionType::Read)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_delete_range_permission(
+        &self,
+        user: &str,
+        request: &DeleteRangeRequest,
+    ) -> crate::Result<()> {
+        let end = request.end.as_deref();
+        self.check_permission(user, &request.start, end, PermissionType::Write)?;
+        if request.prev_kv {
+            self.check_permission(user, &request.start, end, PermissionType::Read)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the user has the access needed for all of the comparisons and operations in a
+    /// txn request.
+    pub fn check_txn_permission(&self, user: &str, request: &TxnRequest<V>) -> crate::Result<()> {
+        for compare in &request.compare {
+            self.check_permission(
+                user,
+                &compare.key,
+                compare.range_end.as_deref(),
+                PermissionType::Read,
+            )?;
+        }
+        for op in request.success.iter().chain(request.failure.iter()) {
+            match op {
+                KvRequest::Range(range) => self.check_range_permission(user, range)?,
+                KvRequest::Put(put) => self.check_put_permission(user, put)?,
+                KvRequest::DeleteRange(delete_range) => {
+                    self.check_delete_range_permission(user, delete_range)?
+                }
+                KvRequest::Txn(txn) => self.check_txn_permission(user, txn)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encode a key into a string for use in the document.
+///
+/// Each byte maps to the char with the same value, so ASCII keys are unchanged and the ordering of
+/// the strings matches the ordering of the bytes.
+pub fn encode_key(key: &[u8]) -> String {
+    key.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Convert an encoded key string back to the key.
+pub fn decode_key(s: &str) -> Vec<u8> {
+    s.chars().map(|c| c as u8).collect()
 }
 
 /// Make a lease id into a string by padding it with zeros
@@ -1117,6 +2161,11 @@ pub fn make_lease_string(lease_id: i64) -> String {
     format!("{:0>8}", lease_id)
 }
 
+/// The current wall clock time in milliseconds since the unix epoch.
+fn wall_clock_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
 /// Make a revision into a string by padding it with zeros
 pub fn make_revision_string(revision: u64) -> String {
     let anti_rev = u64::MAX - revision;
@@ -4,8 +4,14 @@ use test_log::test;
 use tokio::sync::Mutex;
 
 use crate::{
-    syncer::LocalSyncer, value::Bytes, watcher::TestWatcher, Compare, CompareResult, CompareTarget,
-    DocumentBuilder, KeyValue, KvRequest, KvResponse, WatchEvent, WatchServer,
+    alarm::{Alarm, AlarmType},
+    auth::{Permission, PermissionType, User},
+    hlc::{ClockOffsetExceeded, HlcTimestamp, HybridLogicalClock},
+    syncer::LocalSyncer,
+    value::Bytes,
+    watcher::TestWatcher,
+    Compare, CompareResult, CompareTarget, DocumentBuilder, KeyValue, KvRequest, KvResponse,
+    SortOrder, SortTarget, WatchEvent, WatchFilter, WatchServer,
 };
 
 use pretty_assertions::assert_eq;
@@ -25,7 +31,7 @@ fn single_node_doc() -> TestDocumentBuilder {
 #[tokio::test]
 async fn write_value() {
     let mut doc = single_node_doc().build();
-    let key = "key1".to_owned();
+    let key = b"key1".to_vec();
     let value = Bytes::from(b"value1".to_vec());
     assert_eq!(
         doc.put(PutRequest {
@@ -54,6 +60,7 @@ async fn write_value() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -74,6 +81,7 @@ async fn write_value() {
                     lease: None
                 }],
                 count: 1,
+                more: false
             }
         )
     );
@@ -113,6 +121,7 @@ async fn write_value() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -132,7 +141,8 @@ async fn write_value() {
                     version: 2,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -143,6 +153,7 @@ async fn write_value() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -163,6 +174,7 @@ async fn write_value() {
                     lease: None
                 }],
                 count: 1,
+                more: false
             }
         )
     );
@@ -171,7 +183,7 @@ async fn write_value() {
 #[tokio::test]
 async fn delete_value() {
     let mut doc = single_node_doc().build();
-    let key = "key1".to_owned();
+    let key = b"key1".to_vec();
     let value = Bytes::from(b"value1".to_vec());
     assert_eq!(
         doc.put(PutRequest {
@@ -201,6 +213,7 @@ async fn delete_value() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -220,7 +233,8 @@ async fn delete_value() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -262,6 +276,7 @@ async fn delete_value() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -274,7 +289,8 @@ async fn delete_value() {
             },
             RangeResponse {
                 values: vec![],
-                count: 0
+                count: 0,
+                more: false
             }
         )
     );
@@ -285,6 +301,7 @@ async fn delete_value() {
             revision: Some(2),
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -304,7 +321,8 @@ async fn delete_value() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -313,10 +331,10 @@ async fn delete_value() {
 #[tokio::test]
 async fn range() {
     let mut doc = single_node_doc().build();
-    let key1 = "key1".to_owned();
-    let key2 = "key1/key2".to_owned();
-    let key3 = "key1/key3".to_owned();
-    let key4 = "key4".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key1/key2".to_vec();
+    let key3 = b"key1/key3".to_vec();
+    let key4 = b"key4".to_vec();
     let value = Bytes::from(b"value1".to_vec());
 
     assert_eq!(
@@ -406,6 +424,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -425,7 +444,8 @@ async fn range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -436,6 +456,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -455,7 +476,8 @@ async fn range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -466,6 +488,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -485,7 +508,8 @@ async fn range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -496,6 +520,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -515,7 +540,8 @@ async fn range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -529,6 +555,7 @@ async fn range() {
         RangeResponse {
             values: vec![],
             count: 0,
+            more: false,
         },
     );
     assert_eq!(
@@ -538,6 +565,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -551,6 +579,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -588,7 +617,8 @@ async fn range() {
                         lease: None
                     }
                 ],
-                count: 3
+                count: 3,
+                more: false
             }
         )
     );
@@ -599,6 +629,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -618,7 +649,8 @@ async fn range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -629,6 +661,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -658,7 +691,8 @@ async fn range() {
                         lease: None
                     }
                 ],
-                count: 2
+                count: 2,
+                more: false
             }
         )
     );
@@ -669,6 +703,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -682,6 +717,7 @@ async fn range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -693,10 +729,10 @@ async fn range() {
 #[tokio::test]
 async fn remove_range() {
     let mut doc = single_node_doc().build();
-    let key1 = "key1".to_owned();
-    let key2 = "key1/key2".to_owned();
-    let key3 = "key1/key3".to_owned();
-    let key4 = "key4".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key1/key2".to_vec();
+    let key3 = b"key1/key3".to_vec();
+    let key4 = b"key4".to_vec();
     let value = Bytes::from(b"value1".to_vec());
 
     assert_eq!(
@@ -786,6 +822,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -805,7 +842,8 @@ async fn remove_range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -816,6 +854,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -835,7 +874,8 @@ async fn remove_range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -846,6 +886,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -865,7 +906,8 @@ async fn remove_range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -876,6 +918,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -895,7 +938,8 @@ async fn remove_range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -909,6 +953,7 @@ async fn remove_range() {
         RangeResponse {
             values: vec![],
             count: 0,
+            more: false,
         },
     );
     assert_eq!(
@@ -918,6 +963,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -931,6 +977,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -969,6 +1016,7 @@ async fn remove_range() {
                     }
                 ],
                 count: 3,
+                more: false
             }
         )
     );
@@ -979,6 +1027,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -998,7 +1047,8 @@ async fn remove_range() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -1009,6 +1059,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1039,6 +1090,7 @@ async fn remove_range() {
                     }
                 ],
                 count: 2,
+                more: false
             }
         )
     );
@@ -1049,6 +1101,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1062,6 +1115,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1118,6 +1172,7 @@ async fn remove_range() {
         RangeResponse {
             values: vec![],
             count: 0,
+            more: false,
         },
     );
     assert_eq!(
@@ -1127,6 +1182,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1140,6 +1196,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1160,6 +1217,7 @@ async fn remove_range() {
                     lease: None
                 }],
                 count: 1,
+                more: false
             }
         )
     );
@@ -1170,6 +1228,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1190,6 +1249,7 @@ async fn remove_range() {
                     lease: None
                 }],
                 count: 1,
+                more: false
             }
         )
     );
@@ -1200,6 +1260,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1220,6 +1281,7 @@ async fn remove_range() {
                     lease: None
                 }],
                 count: 1,
+                more: false
             }
         )
     );
@@ -1230,6 +1292,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1243,6 +1306,7 @@ async fn remove_range() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1254,7 +1318,7 @@ async fn remove_range() {
 #[tokio::test]
 async fn delete_non_existent_key() {
     let mut doc = single_node_doc().build();
-    let key = "key1".to_owned();
+    let key = b"key1".to_vec();
     assert_eq!(
         doc.delete_range(DeleteRangeRequest {
             start: key,
@@ -1303,7 +1367,7 @@ async fn increment_revision() {
 #[tokio::test]
 async fn put_no_prev_kv() {
     let mut doc = single_node_doc().build();
-    let key = "key".to_owned();
+    let key = b"key".to_vec();
     let value = Bytes::from(b"value".to_vec());
     assert_eq!(
         doc.put(PutRequest {
@@ -1350,7 +1414,7 @@ async fn put_no_prev_kv() {
 #[tokio::test]
 async fn delete_range_no_prev_kv() {
     let mut doc = single_node_doc().build();
-    let key = "key".to_owned();
+    let key = b"key".to_vec();
     let value = Bytes::from(b"value".to_vec());
     assert_eq!(
         doc.put(PutRequest {
@@ -1399,7 +1463,7 @@ async fn delete_range_no_prev_kv() {
 #[tokio::test]
 async fn transaction() {
     let mut doc = single_node_doc().build();
-    let key = "key1".to_owned();
+    let key = b"key1".to_vec();
     let value = Bytes::from(b"value".to_vec());
     // success
     assert_eq!(doc.revision(), 1);
@@ -1412,6 +1476,7 @@ async fn transaction() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Put(PutRequest {
                     key: key.clone(),
@@ -1425,6 +1490,7 @@ async fn transaction() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::DeleteRange(DeleteRangeRequest {
                     start: key.clone(),
@@ -1455,7 +1521,8 @@ async fn transaction() {
                 responses: vec![
                     KvResponse::Range(RangeResponse {
                         values: vec![],
-                        count: 0
+                        count: 0,
+                        more: false
                     }),
                     KvResponse::Put(PutResponse { prev_kv: None }),
                     KvResponse::Range(RangeResponse {
@@ -1467,7 +1534,8 @@ async fn transaction() {
                             version: 1,
                             lease: None
                         }],
-                        count: 1
+                        count: 1,
+                        more: false
                     }),
                     KvResponse::DeleteRange(DeleteRangeResponse {
                         deleted: 1,
@@ -1500,6 +1568,7 @@ async fn transaction() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Put(PutRequest {
                     key: key.clone(),
@@ -1513,6 +1582,7 @@ async fn transaction() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::DeleteRange(DeleteRangeRequest {
                     start: key.clone(),
@@ -1549,7 +1619,8 @@ async fn transaction() {
                             version: 1,
                             lease: None
                         }],
-                        count: 1
+                        count: 1,
+                        more: false
                     }),
                     KvResponse::Put(PutResponse { prev_kv: None }),
                     KvResponse::Range(RangeResponse {
@@ -1561,7 +1632,8 @@ async fn transaction() {
                             version: 2,
                             lease: None
                         }],
-                        count: 1
+                        count: 1,
+                        more: false
                     }),
                     KvResponse::DeleteRange(DeleteRangeResponse {
                         deleted: 1,
@@ -1577,8 +1649,8 @@ async fn transaction() {
 #[tokio::test]
 async fn transaction_single_revision() {
     let mut doc = single_node_doc().build();
-    let key1 = "key1".to_owned();
-    let key2 = "key2".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     assert_eq!(doc.revision(), 1);
@@ -1604,6 +1676,7 @@ async fn transaction_single_revision() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
                 KvRequest::Range(RangeRequest {
                     start: key2.clone(),
@@ -1611,6 +1684,7 @@ async fn transaction_single_revision() {
                     revision: None,
                     limit: None,
                     count_only: false,
+                    ..Default::default()
                 }),
             ],
             failure: vec![]
@@ -1639,7 +1713,8 @@ async fn transaction_single_revision() {
                             version: 1,
                             lease: None
                         }],
-                        count: 1
+                        count: 1,
+                        more: false
                     }),
                     KvResponse::Range(RangeResponse {
                         values: vec![KeyValue {
@@ -1650,7 +1725,8 @@ async fn transaction_single_revision() {
                             version: 1,
                             lease: None
                         }],
-                        count: 1
+                        count: 1,
+                        more: false
                     })
                 ]
             }
@@ -1663,7 +1739,7 @@ async fn transaction_single_revision() {
 async fn transaction_no_modification() {
     let mut doc = single_node_doc().build();
 
-    let key = "key1".to_owned();
+    let key = b"key1".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     assert_eq!(doc.revision(), 1);
@@ -1687,6 +1763,7 @@ async fn transaction_no_modification() {
                 revision: None,
                 limit: None,
                 count_only: false,
+                ..Default::default()
             }),],
             failure: vec![]
         })
@@ -1711,7 +1788,8 @@ async fn transaction_no_modification() {
                         version: 1,
                         lease: None
                     }],
-                    count: 1
+                    count: 1,
+                    more: false
                 }),]
             }
         )
@@ -1727,6 +1805,7 @@ async fn transaction_no_modification() {
                 revision: None,
                 limit: None,
                 count_only: false,
+                ..Default::default()
             }),],
             failure: vec![]
         })
@@ -1751,7 +1830,8 @@ async fn transaction_no_modification() {
                         version: 1,
                         lease: None
                     }],
-                    count: 1
+                    count: 1,
+                    more: false
                 }),]
             }
         )
@@ -1793,7 +1873,7 @@ async fn sync_two_documents() {
         other_documents: vec![(id2, Arc::clone(&doc2))],
     };
 
-    let key = "key".to_owned();
+    let key = b"key".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     doc1.lock()
@@ -1817,6 +1897,7 @@ async fn sync_two_documents() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1833,6 +1914,7 @@ async fn sync_two_documents() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1869,7 +1951,7 @@ async fn sync_two_documents_conflicting_puts_same_revision() {
         other_documents: vec![(id2, Arc::clone(&doc2))],
     };
 
-    let key = "key".to_owned();
+    let key = b"key".to_vec();
     let value1 = Bytes::from(b"value1".to_vec());
     let value2 = Bytes::from(b"value2".to_vec());
 
@@ -1910,6 +1992,7 @@ async fn sync_two_documents_conflicting_puts_same_revision() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1923,6 +2006,7 @@ async fn sync_two_documents_conflicting_puts_same_revision() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -1960,8 +2044,8 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
         other_documents: vec![(id2, Arc::clone(&doc2))],
     };
 
-    let key = "key".to_owned();
-    let other_key = "okey".to_owned();
+    let key = b"key".to_vec();
+    let other_key = b"okey".to_vec();
     let value1 = Bytes::from(b"value1".to_vec());
     let value2 = Bytes::from(b"value2".to_vec());
 
@@ -2017,6 +2101,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2039,6 +2124,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             revision: None,
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2070,6 +2156,7 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
             revision: Some(2),
             limit: None,
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -2093,7 +2180,8 @@ async fn sync_two_documents_conflicting_puts_different_revisions() {
                 version: 1,
                 lease: None
             }],
-            count: 1
+            count: 1,
+            more: false
         }
     );
 }
@@ -2109,9 +2197,9 @@ async fn watch_value_creation() {
         .with_in_memory()
         .with_watcher(watcher)
         .build();
-    let key1 = "key1".to_owned();
-    let key2 = "key2".to_owned();
-    let key3 = "key3".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     doc.put(PutRequest {
@@ -2329,9 +2417,9 @@ async fn watch_server_value_creation() {
     let mut watch_server = WatchServer::default();
 
     let mut doc = single_node_doc().with_watcher(watcher).build();
-    let key1 = "key1".to_owned();
-    let key2 = "key2".to_owned();
-    let key3 = "key3".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     let (sender, mut receiver) = mpsc::channel(100);
@@ -2341,8 +2429,10 @@ async fn watch_server_value_creation() {
             key1.clone(),
             Some(key3.clone()),
             false,
+            vec![],
             None,
-            sender,
+            None,
+            sender.clone(),
         )
         .await
         .unwrap();
@@ -2552,7 +2642,8 @@ async fn watch_server_value_creation() {
         ))
     );
 
-    watch_server.remove_watch(watch_id);
+    watch_server.remove_watch(watch_id, &sender);
+    drop(sender);
 
     for (header, event) in std::mem::take(&mut *events.lock().await) {
         watch_server.receive_event(header, event).await
@@ -2603,10 +2694,10 @@ async fn sync_two_documents_trigger_watches() {
         other_documents: vec![(id2, Arc::clone(&doc2))],
     };
 
-    let key1 = "key1".to_owned();
-    let key2 = "key2".to_owned();
-    let key3 = "key3".to_owned();
-    let other_key = "okey".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
+    let other_key = b"okey".to_vec();
     let value1 = Bytes::from(b"value1".to_vec());
     let value2 = Bytes::from(b"value2".to_vec());
 
@@ -2617,6 +2708,8 @@ async fn sync_two_documents_trigger_watches() {
             key1.clone(),
             Some(key3.clone()),
             false,
+            vec![],
+            None,
             None,
             sender1,
         )
@@ -2629,6 +2722,8 @@ async fn sync_two_documents_trigger_watches() {
             key1.clone(),
             Some(key3.clone()),
             false,
+            vec![],
+            None,
             None,
             sender2,
         )
@@ -3315,13 +3410,275 @@ async fn cluster_startup_3() {
     assert_eq!(members, result);
 }
 
+#[test(tokio::test)]
+async fn remove_member() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+    syncer1.sync_all().await;
+    let heads = doc2.lock().await.heads();
+    doc1.lock().await.peer_heads.insert(id2, heads);
+
+    doc1.lock().await.remove_member(id2).await.unwrap();
+    {
+        let doc1 = doc1.lock().await;
+        let member_ids = doc1
+            .list_members()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+        assert_eq!(member_ids, vec![id1]);
+        assert!(doc1.is_member_removed(id2));
+        assert!(!doc1.is_member_removed(id1));
+        assert!(!doc1.peer_heads.contains_key(&id2));
+    }
+
+    // it can't be removed twice
+    let result = doc1.lock().await.remove_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::MemberNotFound)));
+
+    // the other members learn of the removal through syncing
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.is_member_removed(id2));
+    assert_eq!(doc2.lock().await.get_member(id2), None);
+}
+
+#[tokio::test]
+async fn resume_sync_after_lost_messages() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+    syncer1.sync_all().await;
+
+    let key = b"key1".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+    doc1.lock()
+        .await
+        .put(PutRequest {
+            key: key.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    // the messages carrying the change never arrive, such as when the stream to the peer breaks,
+    // but the sync state still counts the change as sent so it wouldn't be sent again
+    for _ in 0..3 {
+        let _lost = doc1.lock().await.generate_sync_message(id2);
+    }
+    assert_ne!(doc1.lock().await.heads(), doc2.lock().await.heads());
+
+    // resuming on the new stream syncs again from what the peer is known to have
+    doc1.lock().await.resume_sync(id2);
+    syncer1.sync_all().await;
+
+    let (_header, range) = doc2
+        .lock()
+        .await
+        .range(RangeRequest {
+            start: key.clone(),
+            end: None,
+            revision: None,
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(range.values.len(), 1);
+    assert_eq!(range.values[0].value, value);
+}
+
+#[test(tokio::test)]
+async fn update_member() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .with_peer_urls(vec!["http://a:2380".to_owned()])
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .with_peer_urls(vec!["http://b:2380".to_owned()])
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+    syncer1.sync_all().await;
+
+    let member = doc1
+        .lock()
+        .await
+        .update_member(id2, vec!["http://c:2380".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(member.peer_ur_ls, vec!["http://c:2380".to_owned()]);
+
+    // urls can't be shared with other members
+    let result = doc1
+        .lock()
+        .await
+        .update_member(id2, vec!["http://a:2380".to_owned()])
+        .await;
+    assert!(matches!(result, Err(crate::Error::PeerUrlExists)));
+
+    // and there has to be at least one
+    let result = doc1.lock().await.update_member(id2, vec![]).await;
+    assert!(matches!(result, Err(crate::Error::MemberBadUrls)));
+    assert_eq!(
+        doc1.lock().await.get_member(id2).unwrap().peer_ur_ls,
+        vec!["http://c:2380".to_owned()]
+    );
+
+    let result = doc1
+        .lock()
+        .await
+        .update_member(3, vec!["http://d:2380".to_owned()])
+        .await;
+    assert!(matches!(result, Err(crate::Error::MemberNotFound)));
+
+    // the other members see the new urls after syncing
+    syncer1.sync_all().await;
+    assert_eq!(doc2.lock().await.get_member(id2), Some(member));
+}
+
+#[test(tokio::test)]
+async fn learner_member() {
+    let id1 = 1;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let member = doc1.lock().await.add_member(vec![], true).await;
+    assert!(member.is_learner);
+    let id2 = member.id;
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+
+    // it can't be promoted until it has caught up
+    let result = doc1.lock().await.promote_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::LearnerNotReady)));
+
+    let now = doc1.lock().await.now();
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.get_member(id2).unwrap().is_learner);
+
+    // learners don't take writes
+    let result = doc2
+        .lock()
+        .await
+        .put(PutRequest {
+            key: b"key1".to_vec(),
+            value: Bytes::from(b"value1".to_vec()),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await;
+    assert!(matches!(result, Err(crate::Error::NotSupportedForLearner)));
+
+    // or expire leases
+    assert_eq!(doc1.lock().await.live_members(now).unwrap(), vec![id1]);
+    assert_eq!(doc2.lock().await.live_members(now).unwrap(), vec![id1]);
+
+    let member = doc1.lock().await.promote_member(id2).await.unwrap();
+    assert!(!member.is_learner);
+    let result = doc1.lock().await.promote_member(id2).await;
+    assert!(matches!(result, Err(crate::Error::MemberNotLearner)));
+
+    // once promoted it takes writes like any other member
+    syncer1.sync_all().await;
+    assert!(doc2.lock().await.check_not_learner().is_ok());
+}
+
 #[tokio::test]
 async fn range_limited() {
     let mut doc = single_node_doc().build();
-    let key1 = "key1".to_owned();
-    let key2 = "key1/key2".to_owned();
-    let key3 = "key1/key3".to_owned();
-    let key4 = "key4".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key1/key2".to_vec();
+    let key3 = b"key1/key3".to_vec();
+    let key4 = b"key4".to_vec();
     let value = Bytes::from(b"value1".to_vec());
 
     assert_eq!(
@@ -3411,6 +3768,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3430,7 +3788,8 @@ async fn range_limited() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3441,6 +3800,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3460,7 +3820,8 @@ async fn range_limited() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3471,6 +3832,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3490,7 +3852,8 @@ async fn range_limited() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3501,6 +3864,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3520,7 +3884,8 @@ async fn range_limited() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3534,6 +3899,7 @@ async fn range_limited() {
         RangeResponse {
             values: vec![],
             count: 0,
+            more: false,
         },
     );
     assert_eq!(
@@ -3543,6 +3909,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3556,6 +3923,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3585,7 +3953,8 @@ async fn range_limited() {
                         lease: None
                     },
                 ],
-                count: 2
+                count: 3,
+                more: true
             }
         )
     );
@@ -3596,6 +3965,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3615,7 +3985,8 @@ async fn range_limited() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3626,6 +3997,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3655,7 +4027,8 @@ async fn range_limited() {
                         lease: None
                     }
                 ],
-                count: 2
+                count: 2,
+                more: false
             }
         )
     );
@@ -3666,6 +4039,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3679,6 +4053,7 @@ async fn range_limited() {
             revision: None,
             limit: Some(2),
             count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3690,10 +4065,10 @@ async fn range_limited() {
 #[tokio::test]
 async fn range_count_only() {
     let mut doc = single_node_doc().build();
-    let key1 = "key1".to_owned();
-    let key2 = "key1/key2".to_owned();
-    let key3 = "key1/key3".to_owned();
-    let key4 = "key4".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key1/key2".to_vec();
+    let key3 = b"key1/key3".to_vec();
+    let key4 = b"key4".to_vec();
     let value = Bytes::from(b"value1".to_vec());
 
     assert_eq!(
@@ -3782,7 +4157,8 @@ async fn range_count_only() {
             end: None,
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3795,7 +4171,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3805,7 +4182,8 @@ async fn range_count_only() {
             end: None,
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3818,7 +4196,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3828,7 +4207,8 @@ async fn range_count_only() {
             end: None,
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3841,7 +4221,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3851,7 +4232,8 @@ async fn range_count_only() {
             end: None,
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3864,7 +4246,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3878,6 +4261,7 @@ async fn range_count_only() {
         RangeResponse {
             values: vec![],
             count: 0,
+            more: false,
         },
     );
     assert_eq!(
@@ -3886,7 +4270,8 @@ async fn range_count_only() {
             end: Some(key4.clone()),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3899,7 +4284,8 @@ async fn range_count_only() {
             end: Some(key4),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3912,7 +4298,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 3
+                count: 3,
+                more: false
             }
         )
     );
@@ -3922,7 +4309,8 @@ async fn range_count_only() {
             end: Some(key2.clone()),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3935,7 +4323,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 1
+                count: 1,
+                more: false
             }
         )
     );
@@ -3945,7 +4334,8 @@ async fn range_count_only() {
             end: Some(key3.clone()),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3958,7 +4348,8 @@ async fn range_count_only() {
             },
             RangeResponse {
                 values: vec![],
-                count: 2
+                count: 2,
+                more: false
             }
         )
     );
@@ -3968,7 +4359,8 @@ async fn range_count_only() {
             end: Some(key1),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -3981,7 +4373,8 @@ async fn range_count_only() {
             end: Some(key2),
             revision: None,
             limit: None,
-            count_only: true
+            count_only: true,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -4000,9 +4393,9 @@ async fn watch_server_value_creation_start_revision() {
     let mut watch_server = WatchServer::default();
 
     let mut doc = single_node_doc().with_watcher(watcher).build();
-    let key1 = "key1".to_owned();
-    let key2 = "key2".to_owned();
-    let key3 = "key3".to_owned();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
     let value = Bytes::from(b"value".to_vec());
 
     doc.put(PutRequest {
@@ -4080,8 +4473,10 @@ async fn watch_server_value_creation_start_revision() {
             key1.clone(),
             Some(key3.clone()),
             true, // prev_kv
+            vec![],
             Some(3),
-            sender,
+            None,
+            sender.clone(),
         )
         .await
         .unwrap();
@@ -4200,7 +4595,8 @@ async fn watch_server_value_creation_start_revision() {
         ))
     );
 
-    watch_server.remove_watch(watch_id);
+    watch_server.remove_watch(watch_id, &sender);
+    drop(sender);
 
     for (header, event) in std::mem::take(&mut *events.lock().await) {
         watch_server.receive_event(header, event).await
@@ -4214,7 +4610,7 @@ async fn watch_server_value_creation_start_revision() {
 async fn txn_compare() {
     let mut doc = single_node_doc().build();
 
-    let key1 = "key1".to_owned();
+    let key1 = b"key1".to_vec();
 
     let res = doc
         .txn(TxnRequest {
@@ -4236,6 +4632,7 @@ async fn txn_compare() {
                 revision: None,
                 limit: None,
                 count_only: false,
+                ..Default::default()
             })],
         })
         .await
@@ -4272,6 +4669,7 @@ async fn txn_compare() {
                 revision: None,
                 limit: None,
                 count_only: false,
+                ..Default::default()
             })],
         })
         .await
@@ -4293,7 +4691,8 @@ async fn txn_compare() {
                     version: 1,
                     lease: None
                 }],
-                count: 1
+                count: 1,
+                more: false
             })]
         }
     );
@@ -4319,6 +4718,7 @@ async fn txn_compare() {
                 revision: None,
                 limit: None,
                 count_only: false,
+                ..Default::default()
             })],
         })
         .await
@@ -4365,8 +4765,17 @@ async fn remove_lease() {
     let mut doc = single_node_doc().build();
 
     let (id, _ttl) = doc.add_lease(None, None).unwrap();
+    assert_eq!(doc.all_lease_ids().unwrap(), vec![id]);
 
     doc.remove_lease(id).await;
+
+    // the revoked lease is left behind as null but is gone as far as clients can tell
+    assert_eq!(doc.last_lease_refresh(id), None);
+    assert_eq!(doc.granted_lease_ttl(id), None);
+    assert_eq!(doc.remaining_lease_ttl(id), None);
+    assert_eq!(doc.keys_for_lease(id), Vec::<Vec<u8>>::new());
+    assert_eq!(doc.all_lease_ids().unwrap(), Vec::<i64>::new());
+    assert_eq!(doc.refresh_lease(id), 0);
 }
 
 #[test]
@@ -4385,48 +4794,235 @@ fn refresh_lease() {
     assert!(second_refresh > first_refresh);
 }
 
-#[tokio::test]
-async fn kv_leases() {
+#[test]
+fn remaining_lease_ttl() {
     let mut doc = single_node_doc().build();
 
-    let (id, _ttl) = doc.add_lease(None, None).unwrap();
+    let (id, ttl) = doc.add_lease(None, Some(20)).unwrap();
 
-    let key = "key".to_owned();
+    // the clock may have ticked over since the grant
+    let remaining = doc.remaining_lease_ttl(id).unwrap();
+    assert!(ttl - 1 <= remaining && remaining <= ttl);
 
-    doc.put(PutRequest {
-        key: key.clone(),
-        value: Bytes::from(vec![]),
-        lease_id: Some(id),
-        prev_kv: false,
-    })
-    .await
-    .unwrap()
-    .await
-    .unwrap();
+    assert_eq!(doc.remaining_lease_ttl(id + 1), None);
+}
 
+#[tokio::test]
+async fn expire_leases() {
+    let mut doc = single_node_doc().build();
+
+    let (id, ttl) = doc.add_lease(None, Some(5)).unwrap();
+    let granted_at = doc.last_lease_refresh(id).unwrap();
+    let after = |millis| HlcTimestamp::from_millis(granted_at.millis + millis);
+    let ttl_millis = ttl as u64 * 1000;
+
+    // the lease still has until the end of its ttl
     assert_eq!(
-        doc.range(RangeRequest {
-            start: key.clone(),
-            end: None,
-            revision: None,
-            limit: None,
-            count_only: false
-        })
-        .unwrap()
-        .await
-        .unwrap()
-        .1,
-        RangeResponse {
-            values: vec![KeyValue {
-                key: key.clone(),
-                value: Bytes::from(vec![]),
-                create_revision: 2,
-                mod_revision: 2,
-                version: 1,
-                lease: Some(id)
-            }],
-            count: 1
-        }
+        doc.expire_leases(after(ttl_millis - 1)).await.unwrap(),
+        vec![]
+    );
+    assert_eq!(
+        doc.expire_leases(after(ttl_millis)).await.unwrap(),
+        vec![id]
+    );
+
+    // revoked leases are gone for good
+    assert_eq!(doc.all_lease_ids().unwrap(), vec![]);
+    assert_eq!(doc.remaining_lease_ttl(id), None);
+    assert_eq!(doc.expire_leases(after(ttl_millis)).await.unwrap(), vec![]);
+}
+
+#[tokio::test]
+async fn lease_expiry_failover() {
+    let id1 = 1;
+    let id2 = 2;
+    let cluster_id = 1;
+
+    let doc1 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id1)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc1 = Arc::new(Mutex::new(doc1));
+
+    let doc2 = TestDocumentBuilder::default()
+        .with_in_memory()
+        .with_member_id(id2)
+        .with_cluster_id(cluster_id)
+        .with_syncer(())
+        .build();
+    let doc2 = Arc::new(Mutex::new(doc2));
+
+    let syncer1 = LocalSyncer {
+        local_id: id1,
+        local_document: Arc::clone(&doc1),
+        other_documents: vec![(id2, Arc::clone(&doc2))],
+    };
+
+    let (id, ttl) = doc1.lock().await.add_lease(None, Some(5)).unwrap();
+    let granted_at = doc1.lock().await.last_lease_refresh(id).unwrap();
+    let after = |millis| HlcTimestamp::from_millis(granted_at.millis + millis);
+    syncer1.sync_all().await;
+
+    // both members agree that the lowest id expires leases, so the other leaves them alone
+    let expired_at = after(ttl as u64 * 1000);
+    assert_eq!(
+        doc1.lock().await.lease_expiry_owner(expired_at).unwrap(),
+        Some(id1)
+    );
+    assert_eq!(
+        doc2.lock().await.lease_expiry_owner(expired_at).unwrap(),
+        Some(id1)
+    );
+    assert_eq!(
+        doc2.lock().await.expire_leases(expired_at).await.unwrap(),
+        vec![]
+    );
+
+    // once we stop hearing from the owner the next member takes over
+    let timed_out_at = after(2 * MEMBER_LIVENESS_TIMEOUT);
+    assert_eq!(
+        doc2.lock().await.lease_expiry_owner(timed_out_at).unwrap(),
+        Some(id2)
+    );
+    assert_eq!(
+        doc2.lock().await.expire_leases(timed_out_at).await.unwrap(),
+        vec![id]
+    );
+}
+
+#[test]
+fn hybrid_logical_clock() {
+    let mut clock = HybridLogicalClock::default();
+
+    // follows the wall clock while it moves forwards
+    assert_eq!(clock.now(100), HlcTimestamp::from_millis(100));
+    assert_eq!(clock.now(200), HlcTimestamp::from_millis(200));
+
+    // but never goes backwards
+    assert_eq!(
+        clock.now(150),
+        HlcTimestamp {
+            millis: 200,
+            counter: 1
+        }
+    );
+
+    // and moves past timestamps from members with clocks that are ahead
+    let remote = HlcTimestamp {
+        millis: 1_000,
+        counter: 5,
+    };
+    assert_eq!(
+        clock.update(remote, 300),
+        Ok(HlcTimestamp {
+            millis: 1_000,
+            counter: 6
+        })
+    );
+    let local = clock.now(300);
+    assert!(local > remote);
+
+    // packing keeps the order
+    assert!(remote.to_u64() < local.to_u64());
+    assert_eq!(HlcTimestamp::from_u64(remote.to_u64()), remote);
+
+    // unless they are too far ahead of our wall clock
+    let mut clock = HybridLogicalClock::new(1_000);
+    let far_ahead = HlcTimestamp::from_millis(300 + 1_001);
+    assert_eq!(
+        clock.update(far_ahead, 300),
+        Err(ClockOffsetExceeded {
+            offset_millis: 1_001
+        })
+    );
+    assert_eq!(clock.now(300), HlcTimestamp::from_millis(300));
+
+    // but our own timestamps from before a restart are always restored
+    clock.restore(far_ahead);
+    assert!(clock.now(300) > far_ahead);
+}
+
+#[test]
+fn lease_refresh_with_clock_ahead() {
+    let mut doc = single_node_doc().build();
+
+    // another member's clock is a minute ahead of ours
+    let ahead = HlcTimestamp::from_millis(doc.now().millis + 60_000);
+    doc.update_clock(2, ahead);
+
+    // so the refresh is stamped after its time and it won't see the lease as already expiring
+    let (id, ttl) = doc.add_lease(None, Some(5)).unwrap();
+    assert!(doc.last_lease_refresh(id).unwrap() > ahead);
+    assert_eq!(doc.remaining_lease_ttl(id), Some(ttl));
+}
+
+#[test]
+fn clock_too_far_ahead_raises_alarm() {
+    let mut doc = single_node_doc()
+        .with_max_clock_offset_millis(60_000)
+        .build();
+
+    // another member's clock is broken, too far ahead of ours to follow
+    let far_ahead = HlcTimestamp::from_millis(doc.now().millis + 120_000);
+    doc.update_clock(2, far_ahead);
+
+    // so ours stays put and they are flagged for the operator to look at
+    assert!(doc.now() < far_ahead);
+    assert_eq!(
+        doc.alarms(),
+        vec![Alarm {
+            member_id: 2,
+            alarm: AlarmType::Corrupt,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn kv_leases() {
+    let mut doc = single_node_doc().build();
+
+    let (id, _ttl) = doc.add_lease(None, None).unwrap();
+
+    let key = b"key".to_vec();
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: Bytes::from(vec![]),
+        lease_id: Some(id),
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    assert_eq!(
+        doc.range(RangeRequest {
+            start: key.clone(),
+            end: None,
+            revision: None,
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap()
+        .1,
+        RangeResponse {
+            values: vec![KeyValue {
+                key: key.clone(),
+                value: Bytes::from(vec![]),
+                create_revision: 2,
+                mod_revision: 2,
+                version: 1,
+                lease: Some(id)
+            }],
+            count: 1,
+            more: false
+        }
     );
 
     doc.remove_lease(id).await;
@@ -4437,7 +5033,8 @@ async fn kv_leases() {
             end: None,
             revision: None,
             limit: None,
-            count_only: false
+            count_only: false,
+            ..Default::default()
         })
         .unwrap()
         .await
@@ -4445,7 +5042,1098 @@ async fn kv_leases() {
         .1,
         RangeResponse {
             values: vec![],
-            count: 0
+            count: 0,
+            more: false
+        }
+    );
+}
+
+#[tokio::test]
+async fn compact() {
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+
+    // revisions 2, 3 and 4
+    for _ in 0..3 {
+        doc.put(PutRequest {
+            key: key1.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+    // revision 5
+    doc.put(PutRequest {
+        key: key2.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+    // revision 6
+    doc.delete_range(DeleteRangeRequest {
+        start: key2.clone(),
+        end: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    let revs_len = |doc: &Document<_, _, _, _>, key: &[u8]| {
+        let document = doc.am.document();
+        let (_, key_obj) = document
+            .get(&doc.kvs_objid, encode_key(key))
+            .unwrap()
+            .unwrap();
+        let (_, revs_obj) = document.get(&key_obj, "revs").unwrap().unwrap();
+        document.length(&revs_obj)
+    };
+
+    assert!(matches!(
+        doc.compact(7, false),
+        Err(crate::Error::FutureRevision)
+    ));
+
+    assert_eq!(
+        doc.compact(5, false).unwrap(),
+        Header {
+            cluster_id: 1,
+            member_id: 1,
+            revision: 6
+        }
+    );
+    assert_eq!(doc.compact_revision(), 5);
+    assert_eq!(revs_len(&doc, &key1), 1);
+    assert_eq!(revs_len(&doc, &key2), 2);
+
+    assert!(matches!(
+        doc.range(RangeRequest {
+            start: key1.clone(),
+            end: None,
+            revision: Some(4),
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        }),
+        Err(crate::Error::Compacted)
+    ));
+
+    // the latest value at the compaction revision should still be available, with its version
+    let key1_value = KeyValue {
+        key: key1.clone(),
+        value: value.clone(),
+        create_revision: 2,
+        mod_revision: 4,
+        version: 3,
+        lease: None,
+    };
+    assert_eq!(
+        doc.range(RangeRequest {
+            start: key1.clone(),
+            end: None,
+            revision: Some(5),
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap()
+        .1,
+        RangeResponse {
+            values: vec![key1_value.clone()],
+            count: 1,
+            more: false
+        }
+    );
+    assert_eq!(
+        doc.range(RangeRequest {
+            start: key1.clone(),
+            end: None,
+            revision: None,
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap()
+        .1,
+        RangeResponse {
+            values: vec![key1_value],
+            count: 1,
+            more: false
+        }
+    );
+
+    // deleted keys are removed entirely
+    doc.compact(6, true).unwrap();
+    assert_eq!(revs_len(&doc, &key2), 0);
+    assert!(matches!(
+        doc.compact(6, false),
+        Err(crate::Error::Compacted)
+    ));
+
+    // versions continue on from the compacted ones
+    doc.put(PutRequest {
+        key: key1.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+    assert_eq!(
+        doc.range(RangeRequest {
+            start: key1.clone(),
+            end: None,
+            revision: Some(7),
+            limit: None,
+            count_only: false,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap()
+        .1,
+        RangeResponse {
+            values: vec![KeyValue {
+                key: key1.clone(),
+                value,
+                create_revision: 2,
+                mod_revision: 7,
+                version: 4,
+                lease: None,
+            }],
+            count: 1,
+            more: false
         }
     );
 }
+
+#[tokio::test]
+async fn range_sorted() {
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
+
+    for (key, value) in [(&key1, b"c"), (&key2, b"a"), (&key3, b"b"), (&key1, b"d")] {
+        doc.put(PutRequest {
+            key: key.clone(),
+            value: Bytes::from(value.to_vec()),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    let mut sorted_keys = |sort_order, sort_target, limit| {
+        let receiver = doc
+            .range(RangeRequest {
+                start: key1.clone(),
+                end: Some(b"key4".to_vec()),
+                limit,
+                sort_order,
+                sort_target,
+                ..Default::default()
+            })
+            .unwrap();
+        async move {
+            receiver
+                .await
+                .unwrap()
+                .1
+                .values
+                .into_iter()
+                .map(|kv| kv.key)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        sorted_keys(SortOrder::None, SortTarget::Key, None).await,
+        vec![key1.clone(), key2.clone(), key3.clone()]
+    );
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Key, None).await,
+        vec![key3.clone(), key2.clone(), key1.clone()]
+    );
+    // other targets default to ascending
+    assert_eq!(
+        sorted_keys(SortOrder::None, SortTarget::Version, None).await,
+        vec![key2.clone(), key3.clone(), key1.clone()]
+    );
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Create, None).await,
+        vec![key3.clone(), key2.clone(), key1.clone()]
+    );
+    assert_eq!(
+        sorted_keys(SortOrder::Ascend, SortTarget::Mod, None).await,
+        vec![key2.clone(), key3.clone(), key1.clone()]
+    );
+    // limit applies after sorting
+    assert_eq!(
+        sorted_keys(SortOrder::Descend, SortTarget::Value, Some(2)).await,
+        vec![key1.clone(), key3.clone()]
+    );
+    assert_eq!(
+        sorted_keys(SortOrder::Ascend, SortTarget::Value, Some(1)).await,
+        vec![key2.clone()]
+    );
+}
+
+#[tokio::test]
+async fn range_keys_only_and_filters() {
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+    let key2 = b"key2".to_vec();
+    let key3 = b"key3".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    // key1 created at 2 and modified at 5, key2 at 3 and key3 at 4
+    for key in [&key1, &key2, &key3, &key1] {
+        doc.put(PutRequest {
+            key: key.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(
+        doc.range(RangeRequest {
+            start: key1.clone(),
+            end: Some(b"key4".to_vec()),
+            keys_only: true,
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap()
+        .1
+        .values,
+        vec![
+            KeyValue {
+                key: key1.clone(),
+                value: Bytes::default(),
+                create_revision: 2,
+                mod_revision: 5,
+                version: 2,
+                lease: None
+            },
+            KeyValue {
+                key: key2.clone(),
+                value: Bytes::default(),
+                create_revision: 3,
+                mod_revision: 3,
+                version: 1,
+                lease: None
+            },
+            KeyValue {
+                key: key3.clone(),
+                value: Bytes::default(),
+                create_revision: 4,
+                mod_revision: 4,
+                version: 1,
+                lease: None
+            },
+        ]
+    );
+
+    let mut filtered_keys =
+        |min_mod_revision, max_mod_revision, min_create_revision, max_create_revision| {
+            let receiver = doc
+                .range(RangeRequest {
+                    start: key1.clone(),
+                    end: Some(b"key4".to_vec()),
+                    min_mod_revision,
+                    max_mod_revision,
+                    min_create_revision,
+                    max_create_revision,
+                    ..Default::default()
+                })
+                .unwrap();
+            async move {
+                receiver
+                    .await
+                    .unwrap()
+                    .1
+                    .values
+                    .into_iter()
+                    .map(|kv| kv.key)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+    assert_eq!(
+        filtered_keys(Some(4), None, None, None).await,
+        vec![key1.clone(), key3.clone()]
+    );
+    assert_eq!(
+        filtered_keys(None, Some(4), None, None).await,
+        vec![key2.clone(), key3.clone()]
+    );
+    assert_eq!(
+        filtered_keys(None, None, Some(3), Some(3)).await,
+        vec![key2.clone()]
+    );
+    assert_eq!(
+        filtered_keys(Some(5), None, None, Some(2)).await,
+        vec![key1.clone()]
+    );
+}
+
+#[tokio::test]
+async fn binary_keys() {
+    let mut doc = single_node_doc().build();
+    let key1 = b"key\x00".to_vec();
+    let key2 = b"key\x7f".to_vec();
+    let key3 = b"key\x80".to_vec();
+    let key4 = b"key\xff\xfe".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    // insert out of order to check the ordering comes from the keys
+    for key in [&key4, &key2, &key3, &key1] {
+        doc.put(PutRequest {
+            key: key.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(decode_key(&encode_key(&key4)), key4);
+
+    let mut range_keys = |start: &[u8], end: Option<Vec<u8>>| {
+        let receiver = doc
+            .range(RangeRequest {
+                start: start.to_vec(),
+                end,
+                ..Default::default()
+            })
+            .unwrap();
+        async move {
+            receiver
+                .await
+                .unwrap()
+                .1
+                .values
+                .into_iter()
+                .map(|kv| kv.key)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(range_keys(&key3, None).await, vec![key3.clone()]);
+    assert_eq!(
+        range_keys(&key1, Some(key3.clone())).await,
+        vec![key1.clone(), key2.clone()]
+    );
+    // an end of `\0` gets all keys from the start
+    assert_eq!(
+        range_keys(&key2, Some(vec![0])).await,
+        vec![key2.clone(), key3.clone(), key4.clone()]
+    );
+    assert_eq!(
+        range_keys(b"\0", Some(vec![0])).await,
+        vec![key1.clone(), key2.clone(), key3.clone(), key4.clone()]
+    );
+
+    assert_eq!(
+        doc.delete_range(DeleteRangeRequest {
+            start: key3.clone(),
+            end: Some(vec![0]),
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap()
+        .1
+        .deleted,
+        2
+    );
+}
+
+#[tokio::test]
+async fn auth_permissions() {
+    let mut doc = single_node_doc().build();
+    assert!(!doc.auth_enabled());
+
+    assert!(matches!(
+        doc.enable_auth(),
+        Err(crate::Error::RootUserNotExist)
+    ));
+    doc.add_user("root", Some("hash".to_owned())).unwrap();
+    assert!(matches!(
+        doc.enable_auth(),
+        Err(crate::Error::RootRoleNotExist)
+    ));
+    doc.grant_role("root", "root").unwrap();
+    doc.enable_auth().unwrap();
+    assert!(doc.auth_enabled());
+    assert!(matches!(
+        doc.revoke_role("root", "root"),
+        Err(crate::Error::InvalidAuthMgmt)
+    ));
+
+    doc.add_user("user", None).unwrap();
+    assert!(matches!(
+        doc.add_user("user", None),
+        Err(crate::Error::UserAlreadyExists)
+    ));
+    assert!(matches!(
+        doc.grant_role("user", "reader"),
+        Err(crate::Error::RoleNotFound)
+    ));
+    doc.add_role("reader").unwrap();
+    doc.grant_role("user", "reader").unwrap();
+    assert_eq!(
+        doc.get_user("user").unwrap(),
+        User {
+            name: "user".to_owned(),
+            password: None,
+            roles: vec!["reader".to_owned()],
+        }
+    );
+    assert_eq!(doc.list_users(), vec!["root".to_owned(), "user".to_owned()]);
+
+    let read = |key: &[u8], range_end: Option<Vec<u8>>| Permission {
+        perm_type: PermissionType::Read,
+        key: key.to_vec(),
+        range_end,
+    };
+    doc.grant_permission("reader", read(b"a", Some(b"c".to_vec())))
+        .unwrap();
+    doc.grant_permission("reader", read(b"c", Some(b"e".to_vec())))
+        .unwrap();
+    doc.grant_permission("reader", read(b"x", None)).unwrap();
+    assert_eq!(
+        doc.get_role("reader").unwrap(),
+        vec![
+            read(b"a", Some(b"c".to_vec())),
+            read(b"c", Some(b"e".to_vec())),
+            read(b"x", None)
+        ]
+    );
+
+    let check = |doc: &Document<_, _, _, _>, user, key: &[u8], range_end: Option<&[u8]>, access| {
+        doc.check_permission(user, key, range_end, access).is_ok()
+    };
+    // root can do anything
+    assert!(check(&doc, "root", b"z", None, PermissionType::Write));
+    assert!(check(&doc, "user", b"b", None, PermissionType::Read));
+    assert!(!check(&doc, "user", b"b", None, PermissionType::Write));
+    // adjacent permissions cover the range between them
+    assert!(check(
+        &doc,
+        "user",
+        b"a",
+        Some(b"e".as_slice()),
+        PermissionType::Read
+    ));
+    assert!(!check(
+        &doc,
+        "user",
+        b"a",
+        Some(b"f".as_slice()),
+        PermissionType::Read
+    ));
+    assert!(check(&doc, "user", b"x", None, PermissionType::Read));
+    assert!(!check(
+        &doc,
+        "user",
+        b"x",
+        Some(b"y".as_slice()),
+        PermissionType::Read
+    ));
+    assert!(!check(
+        &doc,
+        "user",
+        b"a",
+        Some(b"\0".as_slice()),
+        PermissionType::Read
+    ));
+    assert!(!check(&doc, "missing", b"a", None, PermissionType::Read));
+
+    doc.revoke_permission("reader", b"c", Some(b"e".as_slice()))
+        .unwrap();
+    assert!(matches!(
+        doc.revoke_permission("reader", b"c", Some(b"e".as_slice())),
+        Err(crate::Error::PermissionNotGranted)
+    ));
+    assert!(!check(
+        &doc,
+        "user",
+        b"a",
+        Some(b"e".as_slice()),
+        PermissionType::Read
+    ));
+
+    doc.delete_role("reader").unwrap();
+    assert_eq!(doc.get_user("user").unwrap().roles, Vec::<String>::new());
+    assert!(!check(&doc, "user", b"b", None, PermissionType::Read));
+    assert!(matches!(
+        doc.delete_user("root"),
+        Err(crate::Error::InvalidAuthMgmt)
+    ));
+    doc.delete_user("user").unwrap();
+    assert_eq!(doc.list_users(), vec!["root".to_owned()]);
+}
+
+#[tokio::test]
+async fn snapshot_restore() {
+    let mut doc = single_node_doc().build();
+    let key = b"key1".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    let snapshot = doc.snapshot();
+
+    // seed a new node's storage with the snapshot
+    let mut persister = MemoryPersister::default();
+    persister.set_document(snapshot).unwrap();
+    let mut restored = TestDocumentBuilder::default()
+        .with_persister(persister)
+        .with_member_id(2)
+        .build();
+
+    assert_eq!(restored.cluster_id(), Some(1));
+    let (_, response) = restored
+        .range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(response.values.len(), 1);
+    assert_eq!(response.values[0].key, key);
+    assert_eq!(response.values[0].value, value);
+}
+
+#[tokio::test]
+async fn load_document_without_auth() {
+    // a document written before there was any auth
+    let mut old = automerge::AutoCommit::new();
+    for map in ["kvs", "cluster", "members", "leases"] {
+        old.put_object(ROOT, map, ObjType::Map).unwrap();
+    }
+
+    let mut persister = MemoryPersister::default();
+    persister.set_document(old.save()).unwrap();
+    let mut doc = TestDocumentBuilder::default()
+        .with_persister(persister)
+        .build();
+
+    assert!(!doc.auth_enabled());
+    assert_eq!(doc.list_users(), Vec::<String>::new());
+    doc.add_user("user", None).unwrap();
+    assert_eq!(doc.list_users(), vec!["user".to_owned()]);
+}
+
+#[tokio::test]
+async fn hash_kv() {
+    let mut doc1 = single_node_doc().build();
+    let mut doc2 = single_node_doc().with_member_id(2).build();
+    let key = b"key1".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+
+    let empty = doc1.hash_kv(None).unwrap();
+    assert_eq!(doc2.hash_kv(None).unwrap(), empty);
+
+    for doc in [&mut doc1, &mut doc2] {
+        doc.put(PutRequest {
+            key: key.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    // the same writes give the same hash, whichever member made them
+    let hash = doc1.hash_kv(None).unwrap();
+    assert_ne!(hash, empty);
+    assert_eq!(doc2.hash_kv(None).unwrap(), hash);
+    assert_eq!(doc1.hash_kv(Some(1)).unwrap(), empty);
+    assert!(matches!(
+        doc1.hash_kv(Some(3)),
+        Err(crate::Error::FutureRevision)
+    ));
+}
+
+#[tokio::test]
+async fn alarm_no_space() {
+    let mut doc = single_node_doc().build();
+    let key = b"key1".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+    let put = || PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    };
+    let alarm = Alarm {
+        member_id: 1,
+        alarm: AlarmType::NoSpace,
+    };
+
+    assert!(doc.activate_alarm(alarm));
+    assert!(!doc.activate_alarm(alarm));
+    assert_eq!(doc.alarms(), vec![alarm]);
+
+    assert!(matches!(doc.put(put()).await, Err(crate::Error::NoSpace)));
+    assert!(matches!(
+        doc.txn(TxnRequest {
+            compare: vec![],
+            success: vec![KvRequest::Put(put())],
+            failure: vec![],
+        })
+        .await,
+        Err(crate::Error::NoSpace)
+    ));
+    // reads and deletes don't take up more space
+    doc.txn(TxnRequest {
+        compare: vec![],
+        success: vec![KvRequest::Range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })],
+        failure: vec![],
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    assert!(doc.deactivate_alarm(alarm));
+    assert!(!doc.deactivate_alarm(alarm));
+    assert_eq!(doc.alarms(), vec![]);
+    doc.put(put()).await.unwrap().await.unwrap();
+}
+
+#[tokio::test]
+async fn defragment() {
+    let mut doc = single_node_doc().build();
+    let key = b"key1".to_vec();
+    let value = Bytes::from(b"value1".to_vec());
+
+    doc.put(PutRequest {
+        key: key.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    })
+    .await
+    .unwrap()
+    .await
+    .unwrap();
+
+    let (_, after) = doc.defragment();
+    assert_eq!(after.changes, 0);
+
+    let (_, response) = doc
+        .range(RangeRequest {
+            start: key.clone(),
+            ..Default::default()
+        })
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(response.values.len(), 1);
+}
+
+#[tokio::test]
+async fn watch_filters() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let watcher = TestWatcher {
+        events: Arc::clone(&events),
+    };
+
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().with_watcher(watcher).build();
+    let key1 = b"key1".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    let put = || PutRequest {
+        key: key1.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    };
+    let delete = || DeleteRangeRequest {
+        start: key1.clone(),
+        end: None,
+        prev_kv: false,
+    };
+
+    doc.put(put()).await.unwrap().await.unwrap();
+    doc.delete_range(delete()).await.unwrap().await.unwrap();
+    doc.put(put()).await.unwrap().await.unwrap();
+    events.lock().await.clear();
+
+    let (sender, mut receiver) = mpsc::channel(100);
+    watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![WatchFilter::NoPut],
+            Some(2),
+            None,
+            sender,
+        )
+        .await
+        .unwrap();
+
+    // the history only gives back the deletes
+    let mut replayed = Vec::new();
+    while let Ok((_, _, event)) = receiver.try_recv() {
+        replayed.push(event.typ);
+    }
+    assert!(replayed.contains(&crate::watcher::WatchEventType::Delete(key1.clone(), 3)));
+    assert!(replayed
+        .iter()
+        .all(|typ| matches!(typ, crate::watcher::WatchEventType::Delete(..))));
+
+    doc.put(put()).await.unwrap().await.unwrap();
+    doc.delete_range(delete()).await.unwrap().await.unwrap();
+
+    for (header, event) in std::mem::take(&mut *events.lock().await) {
+        watch_server.receive_event(header, event).await
+    }
+
+    let (_, _, event) = receiver.try_recv().unwrap();
+    assert_eq!(
+        event.typ,
+        crate::watcher::WatchEventType::Delete(key1.clone(), 6)
+    );
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+}
+
+#[tokio::test]
+async fn watch_ids_per_stream() {
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+
+    let (sender1, _receiver1) = mpsc::channel(100);
+    let (sender2, _receiver2) = mpsc::channel(100);
+
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            Some(1),
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+
+    // the same stream can't reuse an id
+    let result = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            Some(1),
+            sender1.clone(),
+        )
+        .await;
+    assert!(matches!(result, Err(crate::Error::DuplicateWatchId)));
+
+    // but other streams can
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            Some(1),
+            sender2.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+
+    // picked ids skip the ones the stream has chosen
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            None,
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 2);
+
+    // removing the watch frees its id on that stream
+    watch_server.remove_watch(1, &sender1);
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            Some(1),
+            sender1.clone(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(watch_id, 1);
+}
+
+#[tokio::test]
+async fn watch_history_after_compaction() {
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    let put = || PutRequest {
+        key: key1.clone(),
+        value: value.clone(),
+        lease_id: None,
+        prev_kv: false,
+    };
+    doc.put(put()).await.unwrap().await.unwrap();
+    doc.put(put()).await.unwrap().await.unwrap();
+    doc.compact(3, false).unwrap();
+    doc.put(put()).await.unwrap().await.unwrap();
+
+    let (sender, mut receiver) = mpsc::channel(100);
+    let watch_id = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            true,
+            vec![],
+            Some(3),
+            None,
+            sender,
+        )
+        .await
+        .unwrap();
+
+    let header = Header {
+        cluster_id: 1,
+        member_id: 1,
+        revision: 4,
+    };
+    let kv3 = KeyValue {
+        key: key1.clone(),
+        value: value.clone(),
+        create_revision: 2,
+        mod_revision: 3,
+        version: 2,
+        lease: None,
+    };
+    // the revision before the compaction is gone so there is no previous value for the first
+    assert_eq!(
+        receiver.try_recv(),
+        Ok((
+            watch_id,
+            header.clone(),
+            WatchEvent {
+                typ: crate::watcher::WatchEventType::Put(kv3.clone()),
+                prev_kv: None,
+            }
+        ))
+    );
+    assert_eq!(
+        receiver.try_recv(),
+        Ok((
+            watch_id,
+            header,
+            WatchEvent {
+                typ: crate::watcher::WatchEventType::Put(KeyValue {
+                    key: key1.clone(),
+                    value: value.clone(),
+                    create_revision: 2,
+                    mod_revision: 4,
+                    version: 3,
+                    lease: None,
+                }),
+                prev_kv: Some(kv3),
+            }
+        ))
+    );
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+}
+
+#[tokio::test]
+async fn watch_compacted() {
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().build();
+    let key1 = b"key1".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    for _ in 0..3 {
+        doc.put(PutRequest {
+            key: key1.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+    doc.compact(3, false).unwrap();
+
+    let (sender, _receiver) = mpsc::channel(100);
+    // the history before the compaction is gone
+    let result = watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            Some(2),
+            None,
+            sender.clone(),
+        )
+        .await;
+    assert!(matches!(result, Err(crate::Error::Compacted)));
+
+    watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            Some(3),
+            None,
+            sender,
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn watch_slow_catch_up() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let watcher = TestWatcher {
+        events: Arc::clone(&events),
+    };
+
+    let mut watch_server = WatchServer::default();
+
+    let mut doc = single_node_doc().with_watcher(watcher).build();
+    let key1 = b"key1".to_vec();
+    let value = Bytes::from(b"value".to_vec());
+
+    // a stream with no room for more than a single event
+    let (sender, mut receiver) = mpsc::channel(1);
+    watch_server
+        .create_watch(
+            &mut doc,
+            key1.clone(),
+            None,
+            false,
+            vec![],
+            None,
+            None,
+            sender.clone(),
+        )
+        .await
+        .unwrap();
+
+    let puts = crate::watch_server::MAX_BUFFERED_EVENTS as u64 + 2;
+    for _ in 0..puts {
+        doc.put(PutRequest {
+            key: key1.clone(),
+            value: value.clone(),
+            lease_id: None,
+            prev_kv: false,
+        })
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    }
+
+    // handing the events over doesn't wait on the stream
+    for (header, event) in std::mem::take(&mut *events.lock().await) {
+        watch_server.receive_event(header, event).await
+    }
+    assert!(!watch_server.is_synced(&sender));
+
+    // the watch falls back to the history and still gets every revision, once each
+    let mut revisions = Vec::new();
+    loop {
+        watch_server.flush();
+        watch_server.catch_up(&doc);
+        match receiver.try_recv() {
+            Ok((_, _, event)) => revisions.push(*event.typ.mod_revision()),
+            Err(_) => break,
+        }
+    }
+    assert_eq!(revisions, (2..puts + 2).collect::<Vec<_>>());
+    assert!(watch_server.is_synced(&sender));
+}
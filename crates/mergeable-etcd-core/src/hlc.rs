@@ -0,0 +1,116 @@
+/// A timestamp from a hybrid logical clock, ordered by wall clock time and then by the counter
+/// for events within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HlcTimestamp {
+    /// Milliseconds since the unix epoch.
+    pub millis: u64,
+    pub counter: u16,
+}
+
+impl HlcTimestamp {
+    pub fn from_millis(millis: u64) -> Self {
+        Self { millis, counter: 0 }
+    }
+
+    /// Pack the timestamp into a single integer that orders the same way, for storing in the
+    /// document and sending to peers.
+    pub fn to_u64(self) -> u64 {
+        (self.millis << 16) | u64::from(self.counter)
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        Self {
+            millis: value >> 16,
+            counter: value as u16,
+        }
+    }
+
+    /// The next timestamp after this one.
+    fn increment(self) -> Self {
+        if self.counter == u16::MAX {
+            Self::from_millis(self.millis + 1)
+        } else {
+            Self {
+                millis: self.millis,
+                counter: self.counter + 1,
+            }
+        }
+    }
+}
+
+/// The default for how far ahead of our wall clock a timestamp from another member can be before
+/// we stop following it, in milliseconds.
+pub const DEFAULT_MAX_CLOCK_OFFSET_MILLIS: u64 = 5 * 60 * 1000;
+
+/// A timestamp from another member that is too far ahead of our wall clock to follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffsetExceeded {
+    /// How far ahead of our wall clock the timestamp was, in milliseconds.
+    pub offset_millis: u64,
+}
+
+/// A hybrid logical clock, giving out timestamps that stay close to the wall clock but never go
+/// backwards or behind a timestamp seen from another member, so members with skewed clocks still
+/// agree on the order of events between them.
+#[derive(Debug)]
+pub struct HybridLogicalClock {
+    last: HlcTimestamp,
+    /// How far ahead of the wall clock a remote timestamp can be for the clock to follow it, so
+    /// one member with a broken clock can't drag everyone else's clocks along.
+    max_offset_millis: u64,
+}
+
+impl Default for HybridLogicalClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CLOCK_OFFSET_MILLIS)
+    }
+}
+
+impl HybridLogicalClock {
+    pub fn new(max_offset_millis: u64) -> Self {
+        Self {
+            last: HlcTimestamp::default(),
+            max_offset_millis,
+        }
+    }
+
+    /// A timestamp for a local event, after every timestamp given out or seen before.
+    pub fn now(&mut self, wall_millis: u64) -> HlcTimestamp {
+        self.tick(HlcTimestamp::default(), wall_millis)
+    }
+
+    /// Move the clock past a timestamp received from another member, failing without moving it if
+    /// the timestamp is too far ahead of the wall clock.
+    pub fn update(
+        &mut self,
+        remote: HlcTimestamp,
+        wall_millis: u64,
+    ) -> Result<HlcTimestamp, ClockOffsetExceeded> {
+        let offset_millis = remote.millis.saturating_sub(wall_millis);
+        if offset_millis > self.max_offset_millis {
+            return Err(ClockOffsetExceeded { offset_millis });
+        }
+        Ok(self.tick(remote, wall_millis))
+    }
+
+    /// Move the clock past a timestamp it gave out before a restart, however far ahead of the wall
+    /// clock that is, so it never gives out an earlier one.
+    pub fn restore(&mut self, last: HlcTimestamp) {
+        self.last = std::cmp::max(self.last, last);
+    }
+
+    fn tick(&mut self, remote: HlcTimestamp, wall_millis: u64) -> HlcTimestamp {
+        let latest = std::cmp::max(self.last, remote);
+        self.last = if wall_millis > latest.millis {
+            HlcTimestamp::from_millis(wall_millis)
+        } else {
+            latest.increment()
+        };
+        self.last
+    }
+
+    /// The clock's current time in milliseconds, without ticking it.
+    pub fn current_millis(&self, wall_millis: u64) -> u64 {
+        std::cmp::max(self.last.millis, wall_millis)
+    }
+}
@@ -1,7 +1,10 @@
+pub mod alarm;
+pub mod auth;
 mod builder;
 mod cache;
 mod document;
 mod error;
+pub mod hlc;
 mod req_resp;
 mod syncer;
 mod transaction;
@@ -11,6 +14,7 @@ mod watcher;
 
 pub use builder::DocumentBuilder;
 pub use document::Document;
+pub use document::MEMBER_HEARTBEAT_INTERVAL;
 pub use error::Error;
 pub use error::Result;
 pub use req_resp::Compare;
@@ -26,9 +30,12 @@ pub use req_resp::PutRequest;
 pub use req_resp::PutResponse;
 pub use req_resp::RangeRequest;
 pub use req_resp::RangeResponse;
+pub use req_resp::SortOrder;
+pub use req_resp::SortTarget;
 pub use req_resp::TxnRequest;
 pub use req_resp::TxnResponse;
 pub use syncer::Syncer;
+pub use watch_server::WatchFilter;
 pub use watch_server::WatchServer;
 pub use watcher::VecWatcher;
 pub use watcher::WatchEvent;
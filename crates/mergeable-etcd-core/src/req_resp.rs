@@ -2,7 +2,7 @@ use crate::value::Value;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct KeyValue<V> {
-    pub key: String,
+    pub key: Vec<u8>,
     pub value: V,
     pub create_revision: u64,
     pub mod_revision: u64,
@@ -13,7 +13,7 @@ pub struct KeyValue<V> {
 impl<V: Value> From<KeyValue<V>> for etcd_proto::mvccpb::KeyValue {
     fn from(kv: KeyValue<V>) -> Self {
         etcd_proto::mvccpb::KeyValue {
-            key: kv.key.into_bytes(),
+            key: kv.key,
             create_revision: kv.create_revision as i64,
             mod_revision: kv.mod_revision as i64,
             version: kv.version as i64,
@@ -23,17 +23,70 @@ impl<V: Value> From<KeyValue<V>> for etcd_proto::mvccpb::KeyValue {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct RangeRequest {
-    pub start: String,
-    pub end: Option<String>,
+    pub start: Vec<u8>,
+    /// The exclusive end of the range, `\0` means all keys from the start.
+    pub end: Option<Vec<u8>>,
     pub revision: Option<u64>,
     pub limit: Option<u64>,
     pub count_only: bool,
+    pub sort_order: SortOrder,
+    pub sort_target: SortTarget,
+    /// Only return the keys, leaving the values as their default.
+    pub keys_only: bool,
+    pub min_mod_revision: Option<u64>,
+    pub max_mod_revision: Option<u64>,
+    pub min_create_revision: Option<u64>,
+    pub max_create_revision: Option<u64>,
 }
 
-impl From<etcd_proto::etcdserverpb::RangeRequest> for RangeRequest {
-    fn from(
+/// The order to return the values of a range in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Leave the values in key order.
+    #[default]
+    None,
+    Ascend,
+    Descend,
+}
+
+impl From<etcd_proto::etcdserverpb::range_request::SortOrder> for SortOrder {
+    fn from(sort_order: etcd_proto::etcdserverpb::range_request::SortOrder) -> Self {
+        match sort_order {
+            etcd_proto::etcdserverpb::range_request::SortOrder::None => SortOrder::None,
+            etcd_proto::etcdserverpb::range_request::SortOrder::Ascend => SortOrder::Ascend,
+            etcd_proto::etcdserverpb::range_request::SortOrder::Descend => SortOrder::Descend,
+        }
+    }
+}
+
+/// The field of the values in a range to sort by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortTarget {
+    #[default]
+    Key,
+    Version,
+    Create,
+    Mod,
+    Value,
+}
+
+impl From<etcd_proto::etcdserverpb::range_request::SortTarget> for SortTarget {
+    fn from(sort_target: etcd_proto::etcdserverpb::range_request::SortTarget) -> Self {
+        match sort_target {
+            etcd_proto::etcdserverpb::range_request::SortTarget::Key => SortTarget::Key,
+            etcd_proto::etcdserverpb::range_request::SortTarget::Version => SortTarget::Version,
+            etcd_proto::etcdserverpb::range_request::SortTarget::Create => SortTarget::Create,
+            etcd_proto::etcdserverpb::range_request::SortTarget::Mod => SortTarget::Mod,
+            etcd_proto::etcdserverpb::range_request::SortTarget::Value => SortTarget::Value,
+        }
+    }
+}
+
+impl TryFrom<etcd_proto::etcdserverpb::RangeRequest> for RangeRequest {
+    type Error = tonic::Status;
+    fn try_from(
         etcd_proto::etcdserverpb::RangeRequest {
             key,
             range_end,
@@ -50,38 +103,49 @@ impl From<etcd_proto::etcdserverpb::RangeRequest> for RangeRequest {
             min_create_revision,
             max_create_revision,
         }: etcd_proto::etcdserverpb::RangeRequest,
-    ) -> Self {
-        assert_eq!(sort_order, 0);
-        assert_eq!(sort_target, 0);
+    ) -> Result<Self, Self::Error> {
         assert!(!serializable);
-        assert!(!keys_only);
-        assert_eq!(min_mod_revision, 0);
-        assert_eq!(max_mod_revision, 0);
-        assert_eq!(min_create_revision, 0);
-        assert_eq!(max_create_revision, 0);
-
-        RangeRequest {
-            start: String::from_utf8(key).unwrap(),
-            end: if range_end.is_empty() {
-                None
-            } else {
-                Some(String::from_utf8(range_end).unwrap())
-            },
-            revision: if revision > 0 {
+
+        let non_zero = |revision: i64| {
+            if revision > 0 {
                 Some(revision as u64)
             } else {
                 None
+            }
+        };
+
+        Ok(RangeRequest {
+            start: key,
+            end: if range_end.is_empty() {
+                None
+            } else {
+                Some(range_end)
             },
-            limit: if limit > 0 { Some(limit as u64) } else { None },
+            revision: non_zero(revision),
+            limit: non_zero(limit),
             count_only,
-        }
+            sort_order: etcd_proto::etcdserverpb::range_request::SortOrder::from_i32(sort_order)
+                .ok_or_else(|| tonic::Status::invalid_argument("unknown sort order"))?
+                .into(),
+            sort_target: etcd_proto::etcdserverpb::range_request::SortTarget::from_i32(sort_target)
+                .ok_or_else(|| tonic::Status::invalid_argument("unknown sort target"))?
+                .into(),
+            keys_only,
+            min_mod_revision: non_zero(min_mod_revision),
+            max_mod_revision: non_zero(max_mod_revision),
+            min_create_revision: non_zero(min_create_revision),
+            max_create_revision: non_zero(max_create_revision),
+        })
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct RangeResponse<V> {
     pub values: Vec<KeyValue<V>>,
+    /// The number of values that matched the request, before any limit.
     pub count: usize,
+    /// Whether there were more values than the limit allowed.
+    pub more: bool,
 }
 
 impl<V: Value> RangeResponse<V> {
@@ -89,8 +153,7 @@ impl<V: Value> RangeResponse<V> {
         etcd_proto::etcdserverpb::RangeResponse {
             header: Some(header.into()),
             kvs: self.values.into_iter().map(|v| v.into()).collect(),
-            // FIXME: once we have paging support...
-            more: false,
+            more: self.more,
             count: self.count as i64,
         }
     }
@@ -98,7 +161,7 @@ impl<V: Value> RangeResponse<V> {
 
 #[derive(Debug, PartialEq)]
 pub struct PutRequest<V> {
-    pub key: String,
+    pub key: Vec<u8>,
     pub value: V,
     pub lease_id: Option<i64>,
     pub prev_kv: bool,
@@ -123,7 +186,7 @@ where
         assert!(!ignore_lease);
 
         Ok(PutRequest {
-            key: String::from_utf8(key).unwrap(),
+            key,
             value: value.try_into()?,
             lease_id: if lease == 0 { None } else { Some(lease) },
             prev_kv,
@@ -147,8 +210,9 @@ impl<V: Value> PutResponse<V> {
 
 #[derive(Debug, PartialEq)]
 pub struct DeleteRangeRequest {
-    pub start: String,
-    pub end: Option<String>,
+    pub start: Vec<u8>,
+    /// The exclusive end of the range, `\0` means all keys from the start.
+    pub end: Option<Vec<u8>>,
     pub prev_kv: bool,
 }
 
@@ -161,11 +225,11 @@ impl From<etcd_proto::etcdserverpb::DeleteRangeRequest> for DeleteRangeRequest {
         }: etcd_proto::etcdserverpb::DeleteRangeRequest,
     ) -> Self {
         DeleteRangeRequest {
-            start: String::from_utf8(key).unwrap(),
+            start: key,
             end: if range_end.is_empty() {
                 None
             } else {
-                Some(String::from_utf8(range_end).unwrap())
+                Some(range_end)
             },
             prev_kv,
         }
@@ -208,8 +272,8 @@ impl From<Header> for etcd_proto::etcdserverpb::ResponseHeader {
 
 #[derive(Debug, PartialEq)]
 pub struct Compare {
-    pub key: String,
-    pub range_end: Option<String>,
+    pub key: Vec<u8>,
+    pub range_end: Option<Vec<u8>>,
     pub target: CompareTarget,
     pub result: CompareResult,
 }
@@ -273,9 +337,9 @@ impl From<etcd_proto::etcdserverpb::Compare> for Compare {
         }: etcd_proto::etcdserverpb::Compare,
     ) -> Self {
         Compare {
-            key: String::from_utf8(key).unwrap(),
+            key,
             range_end: if !range_end.is_empty() {
-                Some(String::from_utf8(range_end).unwrap())
+                Some(range_end)
             } else {
                 None
             },
@@ -294,11 +358,37 @@ pub struct TxnRequest<V> {
     pub failure: Vec<KvRequest<V>>,
 }
 
+impl<V> TxnRequest<V> {
+    /// Whether the transaction may put any values, whichever branch it takes.
+    pub fn has_puts(&self) -> bool {
+        self.success
+            .iter()
+            .chain(self.failure.iter())
+            .any(|op| match op {
+                KvRequest::Put(_) => true,
+                KvRequest::Range(_) | KvRequest::DeleteRange(_) => false,
+                KvRequest::Txn(txn) => txn.has_puts(),
+            })
+    }
+
+    /// Whether the transaction only reads, whichever branch it takes.
+    pub fn is_read_only(&self) -> bool {
+        self.success
+            .iter()
+            .chain(self.failure.iter())
+            .all(|op| match op {
+                KvRequest::Range(_) => true,
+                KvRequest::Put(_) | KvRequest::DeleteRange(_) => false,
+                KvRequest::Txn(txn) => txn.is_read_only(),
+            })
+    }
+}
+
 impl<V: Value> TryFrom<etcd_proto::etcdserverpb::TxnRequest> for TxnRequest<V>
 where
     <V as TryFrom<Vec<u8>>>::Error: std::fmt::Debug,
 {
-    type Error = <V as TryFrom<Vec<u8>>>::Error;
+    type Error = tonic::Status;
     fn try_from(
         etcd_proto::etcdserverpb::TxnRequest {
             compare,
@@ -350,16 +440,18 @@ impl<V: Value> TryFrom<etcd_proto::etcdserverpb::RequestOp> for KvRequest<V>
 where
     <V as TryFrom<Vec<u8>>>::Error: std::fmt::Debug,
 {
-    type Error = <V as TryFrom<Vec<u8>>>::Error;
+    type Error = tonic::Status;
     fn try_from(
         etcd_proto::etcdserverpb::RequestOp { request }: etcd_proto::etcdserverpb::RequestOp,
     ) -> Result<Self, Self::Error> {
         let val = match request.unwrap() {
             etcd_proto::etcdserverpb::request_op::Request::RequestRange(req) => {
-                KvRequest::Range(req.into())
+                KvRequest::Range(req.try_into()?)
             }
             etcd_proto::etcdserverpb::request_op::Request::RequestPut(req) => {
-                KvRequest::Put(req.try_into()?)
+                KvRequest::Put(req.try_into().map_err(|err| {
+                    tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
+                })?)
             }
             etcd_proto::etcdserverpb::request_op::Request::RequestDeleteRange(req) => {
                 KvRequest::DeleteRange(req.into())
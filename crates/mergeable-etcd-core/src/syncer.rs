@@ -24,6 +24,10 @@ pub trait Syncer {
     fn send_local_changes(&self, local_changes: Vec<automerge::Change>);
 
     async fn member_change(&mut self, member: &Member);
+
+    /// Called when a member has been removed from the cluster, expecting the syncer to stop
+    /// syncing with it.
+    async fn member_remove(&mut self, id: u64);
 }
 
 #[tonic::async_trait]
@@ -33,6 +37,9 @@ impl Syncer for () {
     async fn member_change(&mut self, _member: &Member) {
         ready(()).await
     }
+    async fn member_remove(&mut self, _id: u64) {
+        ready(()).await
+    }
 }
 
 #[cfg(test)]
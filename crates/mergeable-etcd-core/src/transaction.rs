@@ -1,16 +1,20 @@
 use autosurgeon::hydrate_prop;
 use autosurgeon::reconcile_prop;
 use std::collections::BTreeMap;
+use std::ops::Bound;
 use std::ops::RangeFull;
 use tracing::debug;
 use tracing::warn;
 
 use crate::cache::Cache;
 use crate::cache::KvCache;
+use crate::document::decode_key;
+use crate::document::encode_key;
 use crate::document::make_lease_string;
 use crate::document::make_revision_string;
 use crate::document::parse_revision_string;
 use crate::value::Value;
+use crate::watcher::WatchEventType;
 use crate::Compare;
 use crate::DeleteRangeRequest;
 use crate::DeleteRangeResponse;
@@ -21,9 +25,12 @@ use crate::PutRequest;
 use crate::PutResponse;
 use crate::RangeRequest;
 use crate::RangeResponse;
+use crate::SortOrder;
+use crate::SortTarget;
 use crate::TxnRequest;
 use crate::TxnResponse;
 use crate::VecWatcher;
+use crate::WatchEvent;
 use automerge::iter::MapRange;
 use automerge::transaction::Transactable;
 use automerge::AutoCommit;
@@ -56,16 +63,125 @@ pub fn increment_revision(txn: &mut AutoCommit, cache: &mut Cache) -> u64 {
 }
 
 /// Get the create_revision, mod_revision and version of the key.
-pub fn get_create_mod_version_slow(
-    txn: &AutoCommit,
+pub fn get_create_mod_version_slow<R: ReadDoc>(
+    doc: &R,
+    key_obj: &ObjId,
     revs_obj: &ObjId,
     revision_string: &str,
 ) -> Option<(u64, u64, u64)> {
     // let revs = txn.range(revs_obj, ..=revision_string.to_owned());
     // could use range here but it is unlikely that we'll be looking very far in the past and so we
     // can avoid the calls to `contains` internally
-    let revs = txn.map_range(revs_obj, ..);
-    get_create_mod_version_slow_inner(revs, revision_string)
+    let revs = doc.map_range(revs_obj, ..);
+    let (create_revision, mod_revision, version) =
+        get_create_mod_version_slow_inner(revs, revision_string)?;
+    // older revisions of this key may have been compacted away, in which case the oldest
+    // remaining revision records what was removed
+    if let Some((create_revision, compacted_versions)) =
+        get_compacted_base(doc, key_obj, &make_revision_string(create_revision))
+    {
+        Some((create_revision, mod_revision, version + compacted_versions))
+    } else {
+        Some((create_revision, mod_revision, version))
+    }
+}
+
+/// Get the create_revision and number of removed versions recorded for the given revision of a
+/// key when it was compacted.
+fn get_compacted_base<R: ReadDoc>(
+    doc: &R,
+    key_obj: &ObjId,
+    revision_string: &str,
+) -> Option<(u64, u64)> {
+    doc.get_all(key_obj, "compacted")
+        .unwrap()
+        .into_iter()
+        .find_map(|(_, compacted_obj)| {
+            let (_, base_obj) = doc.get(&compacted_obj, revision_string).unwrap()?;
+            let (create_revision, _) = doc.get(&base_obj, "create_revision").unwrap()?;
+            let (version, _) = doc.get(&base_obj, "version").unwrap()?;
+            Some((create_revision.to_u64()?, version.to_u64()?))
+        })
+}
+
+/// Remove the revisions of all keys that are older than the given revision.
+///
+/// The latest revision of each key at the compaction revision is kept so that reads at or after
+/// it are unaffected, unless it was a delete in which case it is removed too.
+pub fn compact(txn: &mut AutoCommit, revision: u64) {
+    let server = txn.get(ROOT, "cluster").unwrap();
+    let server = if let Some(server) = server {
+        server.1
+    } else {
+        txn.put_object(ROOT, "cluster", ObjType::Map).unwrap()
+    };
+    txn.put(&server, "compact_revision", revision).unwrap();
+
+    let Some((_, kvs)) = txn.get(ROOT, "kvs").unwrap() else {
+        return;
+    };
+    let revision_string = make_revision_string(revision);
+    let keys: Vec<_> = txn
+        .map_range(&kvs, ..)
+        .map(|(_, _, key_obj)| key_obj)
+        .collect();
+    let mut removed = 0;
+    for key_obj in keys {
+        let Some((_, revs_obj)) = txn.get(&key_obj, "revs").unwrap() else {
+            continue;
+        };
+        // revisions are ordered newest first so this starts with the value live at the compaction
+        // revision
+        let older_revs: Vec<_> = txn
+            .map_range(&revs_obj, revision_string.clone()..)
+            .map(|(rev, value, _)| (rev.to_owned(), value.is_null()))
+            .collect();
+        let Some(((live_rev, deleted), older_revs)) = older_revs.split_first() else {
+            continue;
+        };
+
+        // keep track of what is being removed so that the version and create_revision of the live
+        // value can still be calculated
+        let base = if !deleted && !older_revs.is_empty() {
+            get_create_mod_version_slow(txn, &key_obj, &revs_obj, live_rev)
+                .map(|(create_revision, _, version)| (create_revision, version - 1))
+        } else {
+            None
+        };
+        let compacted_obj = txn.get(&key_obj, "compacted").unwrap().map(|(_, id)| id);
+        if let Some(compacted_obj) = &compacted_obj {
+            let stale: Vec<_> = txn
+                .keys(compacted_obj)
+                .filter(|rev| base.is_none() || rev != live_rev)
+                .collect();
+            for rev in stale {
+                txn.delete(compacted_obj, rev).unwrap();
+            }
+        }
+        if let Some((create_revision, compacted_versions)) = base {
+            let compacted_obj = if let Some(compacted_obj) = compacted_obj {
+                compacted_obj
+            } else {
+                txn.put_object(&key_obj, "compacted", ObjType::Map).unwrap()
+            };
+            let base_obj = txn
+                .put_object(&compacted_obj, live_rev.as_str(), ObjType::Map)
+                .unwrap();
+            txn.put(&base_obj, "create_revision", create_revision)
+                .unwrap();
+            txn.put(&base_obj, "version", compacted_versions).unwrap();
+        }
+
+        if *deleted {
+            txn.delete(&revs_obj, live_rev.as_str()).unwrap();
+            removed += 1;
+        }
+        for (rev, _) in older_revs {
+            txn.delete(&revs_obj, rev.as_str()).unwrap();
+            removed += 1;
+        }
+    }
+    debug!(?revision, ?removed, "Processed compaction");
 }
 
 /// Get the create_revision, mod_revision and version of the key.
@@ -101,150 +217,320 @@ pub fn get_create_mod_version_slow_inner(
     }
 }
 
+/// A value found in a range, before it is hydrated.
+struct RangeEntry<V> {
+    key: Vec<u8>,
+    revs_obj: ObjId,
+    rev: String,
+    create_revision: u64,
+    mod_revision: u64,
+    version: u64,
+    lease: Option<i64>,
+    value: Option<V>,
+}
+
+impl<V: Value> RangeEntry<V> {
+    fn into_key_value(self, txn: &AutoCommit, keys_only: bool) -> KeyValue<V> {
+        let value = if keys_only {
+            V::default()
+        } else {
+            self.value
+                .unwrap_or_else(|| hydrate_prop(txn, &self.revs_obj, self.rev.as_str()).unwrap())
+        };
+        KeyValue {
+            key: self.key,
+            value,
+            create_revision: self.create_revision,
+            mod_revision: self.mod_revision,
+            version: self.version,
+            lease: self.lease,
+        }
+    }
+}
+
+/// Get the bounds of the encoded keys in the half-open interval `[start, end)`.
+/// An end of `\0` means all keys from the start.
+pub fn key_range(start: &[u8], end: &[u8]) -> (Bound<String>, Bound<String>) {
+    let end = if end == [0] {
+        Bound::Unbounded
+    } else {
+        Bound::Excluded(encode_key(end))
+    };
+    (Bound::Included(encode_key(start)), end)
+}
+
 /// Get the values in the half-open interval `[start, end)`.
 /// Returns the usual response as well as the revision of a delete if one occurred.
 pub fn range<V: Value>(
     txn: &mut AutoCommit,
     cache: &mut Cache,
     request: RangeRequest,
-) -> (RangeResponse<V>, BTreeMap<String, u64>) {
+) -> (RangeResponse<V>, BTreeMap<Vec<u8>, u64>) {
     let RangeRequest {
         start,
         end,
         revision,
         limit,
         count_only,
+        sort_order,
+        sort_target,
+        keys_only,
+        min_mod_revision,
+        max_mod_revision,
+        min_create_revision,
+        max_create_revision,
     } = request;
-    let mut values = Vec::new();
-    let mut count = 0;
+    let in_bounds = |value: u64, min: Option<u64>, max: Option<u64>| {
+        !matches!(min, Some(min) if value < min) && !matches!(max, Some(max) if value > max)
+    };
+    let mut entries = Vec::new();
     let mut delete_revisions = BTreeMap::new();
     if let Some((_, kvs)) = txn.get(ROOT, "kvs").unwrap() {
-        if let Some(end) = &end {
-            let keys = txn.map_range(&kvs, start.clone()..end.clone());
-            for (i, (key, _value, key_obj)) in keys.enumerate() {
-                if let Some(limit) = limit {
-                    if i as u64 == limit {
-                        // reached the limit
-                        break;
-                    }
-                }
-
-                if let Some((_, revs_obj)) = txn.get(&key_obj, "revs").unwrap() {
-                    let mut revs = txn.keys(&revs_obj);
-                    let rev = if let Some(revision) = revision {
-                        let revision_string = make_revision_string(revision);
-                        revs.find(|x| x >= &revision_string)
-                    } else {
-                        revs.next()
-                    };
-                    if let Some(rev) = rev {
-                        if let Ok(value) = hydrate_prop(txn, &revs_obj, rev.as_str()) {
-                            let (create_revision, mod_revision, version) = if revision.is_some() {
-                                get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap()
-                            } else if let Some(kv_cache) = cache.get(key) {
-                                debug_assert_eq!(
-                                    get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap(),
-                                    (
-                                        kv_cache.create_revision,
-                                        parse_revision_string(&rev),
-                                        kv_cache.version
-                                    )
-                                );
-                                (
-                                    kv_cache.create_revision,
-                                    parse_revision_string(&rev),
-                                    kv_cache.version,
-                                )
-                            } else {
-                                get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap()
-                            };
-                            if !count_only {
-                                let lease = txn
-                                    .get(&key_obj, "lease_id")
-                                    .unwrap()
-                                    .and_then(|(v, _)| v.to_i64());
-
-                                values.push(KeyValue {
-                                    key: key.to_owned(),
-                                    value,
-                                    create_revision,
-                                    mod_revision,
-                                    version,
-                                    lease,
-                                });
-                            }
-                            count += 1;
-                        } else {
-                            delete_revisions.insert(key.to_owned(), parse_revision_string(&rev));
-                        }
-                    }
-                }
+        let keys: Vec<_> = if let Some(end) = &end {
+            txn.map_range(&kvs, key_range(&start, end))
+                .map(|(key, _value, key_obj)| (decode_key(key), key_obj))
+                .collect()
+        } else {
+            txn.get(&kvs, encode_key(&start))
+                .unwrap()
+                .map(|(_, key_obj)| (start.clone(), key_obj))
+                .into_iter()
+                .collect()
+        };
+        let revision_string = revision.map(make_revision_string);
+        for (key, key_obj) in keys {
+            let Some((_, revs_obj)) = txn.get(&key_obj, "revs").unwrap() else {
+                continue;
+            };
+            let mut revs = txn.map_range(&revs_obj, ..);
+            let rev = if let Some(revision_string) = &revision_string {
+                revs.find(|(rev, _, _)| *rev >= revision_string.as_str())
+            } else {
+                revs.next()
+            };
+            let Some((rev, value, _)) = rev else {
+                continue;
+            };
+            if value.is_null() {
+                // deleted value
+                delete_revisions.insert(key, parse_revision_string(rev));
+                continue;
+            }
+            let rev = rev.to_owned();
+
+            let (create_revision, mod_revision, version) = if revision.is_some() {
+                get_create_mod_version_slow(txn, &key_obj, &revs_obj, &rev).unwrap()
+            } else if let Some(kv_cache) = cache.get(&key) {
+                debug_assert_eq!(
+                    get_create_mod_version_slow(txn, &key_obj, &revs_obj, &rev).unwrap(),
+                    (
+                        kv_cache.create_revision,
+                        parse_revision_string(&rev),
+                        kv_cache.version
+                    )
+                );
+                (
+                    kv_cache.create_revision,
+                    parse_revision_string(&rev),
+                    kv_cache.version,
+                )
+            } else {
+                get_create_mod_version_slow(txn, &key_obj, &revs_obj, &rev).unwrap()
+            };
+            if !in_bounds(mod_revision, min_mod_revision, max_mod_revision)
+                || !in_bounds(create_revision, min_create_revision, max_create_revision)
+            {
+                continue;
             }
-        } else if let Some((_, key_obj)) = txn.get(&kvs, &start).unwrap() {
-            if let Some((_, revs_obj)) = txn.get(&key_obj, "revs").unwrap() {
-                let mut revs = txn.keys(&revs_obj);
-                let rev = if let Some(revision) = revision {
-                    let revision_string = make_revision_string(revision);
-                    revs.find(|x| x >= &revision_string)
-                } else {
-                    revs.next()
-                };
-                if let Some(rev) = rev {
-                    if let Ok(value) = hydrate_prop(txn, &revs_obj, rev.as_str()) {
-                        let (create_revision, mod_revision, version) = if revision.is_some() {
-                            get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap()
-                        } else if let Some(kv_cache) = cache.get(&start) {
-                            debug_assert_eq!(
-                                get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap(),
-                                (
-                                    kv_cache.create_revision,
-                                    parse_revision_string(&rev),
-                                    kv_cache.version
-                                )
-                            );
-                            (
-                                kv_cache.create_revision,
-                                parse_revision_string(&rev),
-                                kv_cache.version,
-                            )
-                        } else {
-                            get_create_mod_version_slow(txn, &revs_obj, &rev).unwrap()
-                        };
 
-                        let lease = txn
-                            .get(&key_obj, "lease_id")
-                            .unwrap()
-                            .and_then(|(v, _)| v.to_i64());
+            let lease = txn
+                .get(&key_obj, "lease_id")
+                .unwrap()
+                .and_then(|(v, _)| v.to_i64());
 
-                        if !count_only {
-                            values.push(KeyValue {
-                                key: start.clone(),
-                                value,
-                                create_revision,
-                                mod_revision,
-                                version,
-                                lease,
-                            });
-                        }
-                        count += 1;
-                    } else {
-                        // deleted value
-                        delete_revisions.insert(start.clone(), parse_revision_string(&rev));
-                    }
-                }
-            }
+            entries.push(RangeEntry {
+                key,
+                revs_obj,
+                rev,
+                create_revision,
+                mod_revision,
+                version,
+                lease,
+                value: None,
+            });
         }
     }
+
+    sort_range_entries(txn, &mut entries, sort_order, sort_target);
+
+    let count = entries.len();
+    let more = matches!(limit, Some(limit) if count as u64 > limit);
+    if let Some(limit) = limit {
+        entries.truncate(limit as usize);
+    }
+
+    // only the values within the limit need to be hydrated
+    let values = if count_only {
+        Vec::new()
+    } else {
+        entries
+            .into_iter()
+            .map(|entry| entry.into_key_value(txn, keys_only))
+            .collect()
+    };
     debug!(
         ?start,
         ?end,
         ?revision,
         ?limit,
         ?count_only,
+        ?sort_order,
+        ?sort_target,
+        ?keys_only,
         ?count,
+        ?more,
         "Processed range request"
     );
-    (RangeResponse { values, count }, delete_revisions)
+    (
+        RangeResponse {
+            values,
+            count,
+            more,
+        },
+        delete_revisions,
+    )
+}
+
+/// Get the events for the keys in the half-open interval `[start, end)`, or just the start key if
+/// there is no end, from the start revision onwards in revision order.
+///
+/// Each key's revisions are walked once from oldest to newest, tracking the create_revision and
+/// version along the way, rather than ranging over the keys at every revision.
+pub fn history<V: Value, R: ReadDoc>(
+    doc: &R,
+    start: &[u8],
+    end: Option<&[u8]>,
+    start_revision: u64,
+    prev_kv: bool,
+) -> Vec<WatchEvent<V>> {
+    let mut events = Vec::new();
+    let Some((_, kvs)) = doc.get(ROOT, "kvs").unwrap() else {
+        return Vec::new();
+    };
+    let keys: Vec<_> = if let Some(end) = end {
+        doc.map_range(&kvs, key_range(start, end))
+            .map(|(key, _value, key_obj)| (decode_key(key), key_obj))
+            .collect()
+    } else {
+        doc.get(&kvs, encode_key(start))
+            .unwrap()
+            .map(|(_, key_obj)| (start.to_vec(), key_obj))
+            .into_iter()
+            .collect()
+    };
+    for (key, key_obj) in keys {
+        let Some((_, revs_obj)) = doc.get(&key_obj, "revs").unwrap() else {
+            continue;
+        };
+        let lease = doc
+            .get(&key_obj, "lease_id")
+            .unwrap()
+            .and_then(|(v, _)| v.to_i64());
+        let to_kv = |(rev, create_revision, version): &(String, u64, u64)| KeyValue {
+            key: key.clone(),
+            value: hydrate_prop(doc, &revs_obj, rev.as_str()).unwrap(),
+            create_revision: *create_revision,
+            mod_revision: parse_revision_string(rev),
+            version: *version,
+            lease,
+        };
+
+        // revisions are stored newest first
+        let revs: Vec<_> = doc
+            .map_range(&revs_obj, ..)
+            .map(|(rev, value, _)| (rev.to_owned(), value.is_null()))
+            .collect();
+        // the live revision of the key, with its create_revision and version
+        let mut live: Option<(String, u64, u64)> = None;
+        for (rev, deleted) in revs.into_iter().rev() {
+            let revision = parse_revision_string(&rev);
+            let previous = live.take();
+            let prev_kv = if prev_kv && revision >= start_revision {
+                previous.as_ref().map(&to_kv)
+            } else {
+                None
+            };
+
+            if deleted {
+                if revision >= start_revision {
+                    let typ = WatchEventType::Delete(key.clone(), revision);
+                    events.push((revision, WatchEvent { typ, prev_kv }));
+                }
+                continue;
+            }
+
+            let (create_revision, version) = match &previous {
+                Some((_, create_revision, version)) => (*create_revision, version + 1),
+                // older revisions of this key may have been compacted away
+                None => match get_compacted_base(doc, &key_obj, &rev) {
+                    Some((create_revision, compacted_versions)) => {
+                        (create_revision, compacted_versions + 1)
+                    }
+                    None => (revision, 1),
+                },
+            };
+            let current = (rev, create_revision, version);
+            if revision >= start_revision {
+                let typ = WatchEventType::Put(to_kv(&current));
+                events.push((revision, WatchEvent { typ, prev_kv }));
+            }
+            live = Some(current);
+        }
+    }
+
+    // keys were visited in order so a stable sort keeps events in the same revision ordered by key
+    events.sort_by_key(|(revision, _)| *revision);
+    events.into_iter().map(|(_, event)| event).collect()
+}
+
+/// Sort the entries of a range, they start in ascending key order.
+fn sort_range_entries<V: Value>(
+    txn: &AutoCommit,
+    entries: &mut [RangeEntry<V>],
+    sort_order: SortOrder,
+    sort_target: SortTarget,
+) {
+    // targets other than the key default to ascending order, as in etcd
+    let sort_order = match (sort_order, sort_target) {
+        (SortOrder::None | SortOrder::Ascend, SortTarget::Key) => return,
+        (SortOrder::None, _) => SortOrder::Ascend,
+        (sort_order, _) => sort_order,
+    };
+
+    if sort_target == SortTarget::Value {
+        // values are needed to compare so hydrate them now, they will be reused in the response
+        for entry in entries.iter_mut() {
+            entry.value = Some(hydrate_prop(txn, &entry.revs_obj, entry.rev.as_str()).unwrap());
+        }
+    }
+
+    entries.sort_by(|a, b| {
+        let ordering = match sort_target {
+            SortTarget::Key => a.key.cmp(&b.key),
+            SortTarget::Version => a.version.cmp(&b.version),
+            SortTarget::Create => a.create_revision.cmp(&b.create_revision),
+            SortTarget::Mod => a.mod_revision.cmp(&b.mod_revision),
+            SortTarget::Value => {
+                let a: Vec<u8> = a.value.clone().unwrap().into();
+                let b: Vec<u8> = b.value.clone().unwrap().into();
+                a.cmp(&b)
+            }
+        };
+        match sort_order {
+            SortOrder::Descend => ordering.reverse(),
+            SortOrder::None | SortOrder::Ascend => ordering,
+        }
+    });
 }
 
 pub fn put<V: Value>(
@@ -267,11 +553,12 @@ pub fn put<V: Value>(
         txn.put_object(ROOT, "kvs", ObjType::Map).unwrap()
     };
 
-    let key_obj = txn.get(&kvs, &key).unwrap();
+    let encoded_key = encode_key(&key);
+    let key_obj = txn.get(&kvs, &encoded_key).unwrap();
     let key_obj = if let Some(key_obj) = key_obj {
         key_obj.1
     } else {
-        txn.put_object(&kvs, &key, ObjType::Map).unwrap()
+        txn.put_object(&kvs, &encoded_key, ObjType::Map).unwrap()
     };
 
     if let Some(lease_id) = lease_id {
@@ -280,7 +567,7 @@ pub fn put<V: Value>(
         if let Some((_, lease_objid)) = txn.get(&leases_objid, make_lease_string(lease_id)).unwrap()
         {
             let (_, lease_keys_objid) = txn.get(&lease_objid, "keys").unwrap().unwrap();
-            txn.put(&lease_keys_objid, key.clone(), ()).unwrap();
+            txn.put(&lease_keys_objid, encoded_key, ()).unwrap();
         } else {
             warn!(?lease_id, "Failed to find lease object to update");
         }
@@ -306,7 +593,7 @@ pub fn put<V: Value>(
                             kv_cache.version,
                         )
                     } else {
-                        get_create_mod_version_slow(txn, &revs_obj, revision).unwrap()
+                        get_create_mod_version_slow(txn, &key_obj, &revs_obj, revision).unwrap()
                     };
                 Some(KeyValue {
                     key: key.clone(),
@@ -331,7 +618,7 @@ pub fn put<V: Value>(
         (kv_cache.create_revision, revision, kv_cache.version)
     } else {
         let (create_revision, mod_revision, version) =
-            get_create_mod_version_slow(txn, &revs_obj, &revision_string).unwrap();
+            get_create_mod_version_slow(txn, &key_obj, &revs_obj, &revision_string).unwrap();
         cache.insert(
             key.clone(),
             KvCache {
@@ -393,8 +680,8 @@ pub fn delete_range<V: Value>(
 
     if let Some(end) = end {
         let keys: Vec<_> = txn
-            .map_range(&kvs, start..end)
-            .map(|(key, value, key_obj)| (key.to_owned(), value.to_owned(), key_obj))
+            .map_range(&kvs, key_range(&start, &end))
+            .map(|(key, value, key_obj)| (decode_key(key), value.to_owned(), key_obj))
             .collect();
         for (key, _value, key_obj) in keys {
             cache.remove(&key);
@@ -412,7 +699,8 @@ pub fn delete_range<V: Value>(
                             None
                         } else if let Ok(value) = hydrate_prop(txn, &revs_obj, revision) {
                             let (create_revision, mod_revision, version) =
-                                get_create_mod_version_slow(txn, &revs_obj, revision).unwrap();
+                                get_create_mod_version_slow(txn, &key_obj, &revs_obj, revision)
+                                    .unwrap();
                             Some(KeyValue {
                                 key: key.clone(),
                                 value,
@@ -454,7 +742,7 @@ pub fn delete_range<V: Value>(
             txn.put_object(ROOT, "kvs", ObjType::Map).unwrap()
         };
 
-        let key_obj = txn.get(&kvs, &start).unwrap();
+        let key_obj = txn.get(&kvs, encode_key(&start)).unwrap();
         if let Some((_, key_obj)) = key_obj {
             let revs = txn.get(&key_obj, "revs").unwrap();
             let revs_obj = if let Some(revs) = revs {
@@ -469,7 +757,8 @@ pub fn delete_range<V: Value>(
                         None
                     } else if let Ok(value) = hydrate_prop(txn, &revs_obj, revision) {
                         let (create_revision, mod_revision, version) =
-                            get_create_mod_version_slow(txn, &revs_obj, revision).unwrap();
+                            get_create_mod_version_slow(txn, &key_obj, &revs_obj, revision)
+                                .unwrap();
                         Some(KeyValue {
                             key: start.clone(),
                             value,
@@ -567,7 +856,7 @@ fn txn_compare<V: Value>(
         result,
     } = compare;
 
-    let RangeResponse { values, count: _ } = range::<V>(
+    let RangeResponse { values, .. } = range::<V>(
         txn,
         cache,
         RangeRequest {
@@ -576,6 +865,7 @@ fn txn_compare<V: Value>(
             revision: Some(revision),
             limit: None,
             count_only: false,
+            ..Default::default()
         },
     )
     .0;
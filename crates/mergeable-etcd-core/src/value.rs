@@ -10,6 +10,7 @@ pub trait Value:
     +std::hash::Hash
     +Eq
     + std::fmt::Debug
+    + Default // for placeholders when values aren't wanted
     + 'static
     + TryFrom<Vec<u8>> // for parsing from requests
     + Into<Vec<u8>> // for serializing to responses
@@ -1,24 +1,169 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
 
 use automerge_persistent::Persister;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tracing::warn;
 
-use crate::{value::Value, Document, Header, Syncer, WatchEvent, Watcher};
+use crate::{value::Value, watcher::WatchEventType, Document, Header, Syncer, WatchEvent, Watcher};
 
 type WatchId = i64;
 
+/// How many events a watch can hold while its stream is full before it is too far behind and has
+/// to catch up from the history instead.
+pub(crate) const MAX_BUFFERED_EVENTS: usize = 1024;
+
+/// Types of events that a watch can ask not to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFilter {
+    NoPut,
+    NoDelete,
+}
+
+impl WatchFilter {
+    fn filters<V>(self, typ: &WatchEventType<V>) -> bool {
+        matches!(
+            (self, typ),
+            (WatchFilter::NoPut, WatchEventType::Put(_))
+                | (WatchFilter::NoDelete, WatchEventType::Delete(..))
+        )
+    }
+}
+
+impl From<etcd_proto::etcdserverpb::watch_create_request::FilterType> for WatchFilter {
+    fn from(value: etcd_proto::etcdserverpb::watch_create_request::FilterType) -> Self {
+        match value {
+            etcd_proto::etcdserverpb::watch_create_request::FilterType::Noput => Self::NoPut,
+            etcd_proto::etcdserverpb::watch_create_request::FilterType::Nodelete => Self::NoDelete,
+        }
+    }
+}
+
 struct Watch<V> {
     watch_id: WatchId,
-    start: String,
-    end: Option<String>,
+    start: Vec<u8>,
+    end: Option<Vec<u8>>,
     sender: Sender<(WatchId, Header, WatchEvent<V>)>,
     /// Whether to include previous kvs in the events.
     prev_kv: bool,
+    filters: Vec<WatchFilter>,
+    /// Events waiting for room in the stream.
+    buffer: VecDeque<(Header, WatchEvent<V>)>,
+    /// The revision of the last event taken by the stream.
+    sent_revision: u64,
+    /// The revision to replay the history from for a watch that fell too far behind.
+    lagging_since: Option<u64>,
+    /// Events up to this revision have been replayed from the history, so live events for them
+    /// are duplicates.
+    replayed_revision: u64,
+    /// Whether events are being held back, such as while a progress notification is sent.
+    paused: bool,
+}
+
+impl<V> Watch<V> {
+    /// Whether the event is one that this watch asked not to be sent.
+    fn filters(&self, event: &WatchEvent<V>) -> bool {
+        self.filters.iter().any(|filter| filter.filters(&event.typ))
+    }
+
+    fn watches_key(&self, key: &[u8]) -> bool {
+        if let Some(end) = &self.end {
+            // an end of `\0` means all keys from the start
+            self.start.as_slice() <= key && (end == &[0] || key < end.as_slice())
+        } else {
+            self.start == key
+        }
+    }
+
+    /// Queue the event for the stream, unless the history will cover it.
+    fn push(&mut self, header: Header, mut event: WatchEvent<V>) {
+        if self.lagging_since.is_some() || *event.typ.mod_revision() <= self.replayed_revision {
+            return;
+        }
+        if !self.prev_kv {
+            event.prev_kv = None;
+        }
+        self.buffer.push_back((header, event));
+        self.flush();
+        if self.buffer.len() > MAX_BUFFERED_EVENTS {
+            self.fall_behind();
+        }
+    }
+
+    /// Send as many of the buffered events as the stream has room for.
+    fn flush(&mut self) {
+        if self.paused {
+            return;
+        }
+        while let Some((header, event)) = self.buffer.pop_front() {
+            let revision = *event.typ.mod_revision();
+            match self.sender.try_send((self.watch_id, header, event)) {
+                Ok(()) => self.sent_revision = revision,
+                Err(TrySendError::Full((_, header, event)))
+                | Err(TrySendError::Closed((_, header, event))) => {
+                    self.buffer.push_front((header, event));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Drop the buffered events so that the watch can catch up from the history once the stream
+    /// has room again.
+    fn fall_behind(&mut self) {
+        let Some((_, oldest)) = self.buffer.front() else {
+            return;
+        };
+        let oldest = *oldest.typ.mod_revision();
+        warn!(
+            watch_id = self.watch_id,
+            revision = oldest,
+            "Watch fell too far behind, catching up from the history"
+        );
+        if oldest == self.sent_revision {
+            // keep the rest of a partly sent revision so none of it is sent twice
+            let rest = self
+                .buffer
+                .iter()
+                .take_while(|(_, event)| *event.typ.mod_revision() == oldest)
+                .count();
+            self.buffer.truncate(rest);
+            self.lagging_since = Some(oldest + 1);
+        } else {
+            self.buffer.clear();
+            self.lagging_since = Some(oldest);
+        }
+    }
+
+    /// Buffer the events from the history, stopping at a revision boundary once the buffer is
+    /// full.
+    fn push_history(&mut self, header: &Header, events: Vec<WatchEvent<V>>) {
+        let mut last_revision = None;
+        for event in events {
+            if self.filters(&event) {
+                continue;
+            }
+            let revision = *event.typ.mod_revision();
+            if self.buffer.len() >= MAX_BUFFERED_EVENTS && last_revision != Some(revision) {
+                self.lagging_since = Some(revision);
+                break;
+            }
+            self.buffer.push_back((header.clone(), event));
+            last_revision = Some(revision);
+        }
+        self.flush();
+    }
 }
 
 pub struct WatchServer<V> {
-    watches: HashMap<WatchId, Watch<V>>,
+    /// Watch ids are only unique within a stream, so there can be one watch per stream for each.
+    watches: HashMap<WatchId, Vec<Watch<V>>>,
     max_id: WatchId,
+    /// Watches that were cancelled by the server, for their streams to tell the clients about.
+    cancelled: Vec<(
+        WatchId,
+        Sender<(WatchId, Header, WatchEvent<V>)>,
+        crate::Error,
+    )>,
 }
 
 impl<V> Default for WatchServer<V> {
@@ -26,6 +171,7 @@ impl<V> Default for WatchServer<V> {
         Self {
             watches: Default::default(),
             max_id: Default::default(),
+            cancelled: Default::default(),
         }
     }
 }
@@ -36,13 +182,18 @@ where
 {
     /// Create a new watcher watching the range `[start, end)` and streaming values from the
     /// start_revision to the sender.
+    ///
+    /// The watch gets the given id if there is one, otherwise the next free one for the stream.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_watch<P, S, W>(
         &mut self,
         document: &mut Document<P, S, W, V>,
-        start: String,
-        end: Option<String>,
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
         prev_kv: bool,
+        filters: Vec<WatchFilter>,
         start_revision: Option<u64>,
+        watch_id: Option<WatchId>,
         sender: Sender<(WatchId, Header, WatchEvent<V>)>,
     ) -> crate::Result<WatchId>
     where
@@ -50,142 +201,194 @@ where
         S: Syncer,
         W: Watcher<V>,
     {
-        self.max_id += 1;
-        let watch_id = self.max_id;
-        self.watches.insert(
-            watch_id,
-            Watch {
-                watch_id,
-                start: start.clone(),
-                end: end.clone(),
-                sender: sender.clone(),
-                prev_kv,
+        if let Some(start_revision) = start_revision {
+            // history before the compaction is no longer available
+            if start_revision < document.compact_revision() {
+                return Err(crate::Error::Compacted);
+            }
+        }
+
+        let watch_id = match watch_id {
+            Some(watch_id) => {
+                if self.exists_on_stream(watch_id, &sender) {
+                    return Err(crate::Error::DuplicateWatchId);
+                }
+                watch_id
+            }
+            None => loop {
+                self.max_id += 1;
+                if !self.exists_on_stream(self.max_id, &sender) {
+                    break self.max_id;
+                }
             },
-        );
+        };
+        let mut watch = Watch {
+            watch_id,
+            start,
+            end,
+            sender,
+            prev_kv,
+            filters,
+            buffer: VecDeque::new(),
+            sent_revision: 0,
+            lagging_since: None,
+            replayed_revision: 0,
+            paused: false,
+        };
         if let Some(start_revision) = start_revision {
-            let current_revision = document.revision();
             let header = document.header()?;
+            let events =
+                document.history(&watch.start, watch.end.as_deref(), start_revision, prev_kv);
+            watch.replayed_revision = header.revision as u64;
+            watch.push_history(&header, events);
+        }
 
-            let mut events = Vec::new();
-
-            // TODO: this isn't the most efficient, checking every revision since the start, could
-            // probably make it more efficient somehow
-            //
-            // we need to send values for all the revisions from the start revision.
-            //
-            // Iterate and naively get the range response from each.
-            for revision in start_revision..current_revision {
-                let (_header, response, delete_revisions) = document
-                    .range_or_delete_revision(crate::RangeRequest {
-                        start: start.clone(),
-                        end: end.clone(),
-                        revision: Some(revision),
-                        limit: None,
-                        count_only: false,
-                    })
-                    .expect("watch shouldn't be able to be created if the node isn't ready");
-
-                for kv in response.values {
-                    let prev_kv = if prev_kv {
-                        let (_header, past_response, _) = document
-                            .range_or_delete_revision(crate::RangeRequest {
-                                start: kv.key.clone(),
-                                end: None,
-                                revision: Some(kv.mod_revision - 1),
-                                limit: None,
-                                count_only: false,
-                            })
-                            .expect(
-                                "watch shouldn't be able to be created if the node isn't ready",
-                            );
-                        past_response.values.first().cloned()
-                    } else {
-                        None
-                    };
-
-                    events.push((
-                        header.clone(),
-                        WatchEvent {
-                            typ: crate::watcher::WatchEventType::Put(kv),
-                            prev_kv,
-                        },
-                    ));
-                }
+        self.watches.entry(watch_id).or_default().push(watch);
+        Ok(watch_id)
+    }
 
-                for (deleted_key, delete_revision) in delete_revisions {
-                    let prev_kv = if prev_kv {
-                        let (_header, past_response, _) = document
-                            .range_or_delete_revision(crate::RangeRequest {
-                                start: deleted_key.clone(),
-                                end: None,
-                                revision: Some(delete_revision - 1),
-                                limit: None,
-                                count_only: false,
-                            })
-                            .expect(
-                                "watch shouldn't be able to be created if the node isn't ready",
-                            );
-                        past_response.values.first().cloned()
-                    } else {
-                        None
-                    };
-
-                    events.push((
-                        header.clone(),
-                        WatchEvent {
-                            typ: crate::watcher::WatchEventType::Delete(
-                                deleted_key,
-                                delete_revision,
-                            ),
-                            prev_kv,
-                        },
-                    ));
-                }
-            }
+    /// Whether the stream with this sender already has a watch with the id.
+    fn exists_on_stream(
+        &self,
+        watch_id: WatchId,
+        sender: &Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> bool {
+        self.watches
+            .get(&watch_id)
+            .into_iter()
+            .flatten()
+            .any(|watch| watch.sender.same_channel(sender))
+    }
 
-            // prevent duplicates
-            let mut seen_events = HashSet::new();
-            for (header, event) in events.into_iter() {
-                if seen_events.insert(event.clone()) {
-                    // set didn't have this event
-                    sender.send((watch_id, header, event)).await.unwrap();
-                }
+    /// The watches on the stream with this sender.
+    fn stream_watches<'a>(
+        &'a mut self,
+        sender: &'a Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> impl Iterator<Item = &'a mut Watch<V>> {
+        self.watches
+            .values_mut()
+            .flatten()
+            .filter(|watch| watch.sender.same_channel(sender))
+    }
+
+    /// Remove the watch with the id from the stream with this sender.
+    pub fn remove_watch(&mut self, id: WatchId, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        if let Some(watches) = self.watches.get_mut(&id) {
+            watches.retain(|watch| !watch.sender.same_channel(sender));
+            if watches.is_empty() {
+                self.watches.remove(&id);
             }
         }
+    }
 
-        Ok(watch_id)
+    /// Take the watches on the stream with this sender that were cancelled by the server, along
+    /// with why.
+    pub fn take_cancelled(
+        &mut self,
+        sender: &Sender<(WatchId, Header, WatchEvent<V>)>,
+    ) -> Vec<(WatchId, crate::Error)> {
+        let (taken, kept) = std::mem::take(&mut self.cancelled)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, cancelled_sender, _)| {
+                cancelled_sender.same_channel(sender)
+            });
+        self.cancelled = kept;
+        taken
+            .into_iter()
+            .map(|(watch_id, _, error)| (watch_id, error))
+            .collect()
     }
 
-    pub fn remove_watch(&mut self, id: WatchId) {
-        self.watches.remove(&id);
+    /// Whether every watch on the stream with this sender has handed all of its events to the
+    /// stream.
+    pub fn is_synced(&self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) -> bool {
+        self.watches
+            .values()
+            .flatten()
+            .filter(|watch| watch.sender.same_channel(sender))
+            .all(|watch| watch.buffer.is_empty() && watch.lagging_since.is_none())
     }
 
-    pub async fn receive_event(&mut self, header: Header, event: WatchEvent<V>) {
-        for watcher in self.watches.values() {
-            let key = event.typ.key();
-            if let Some(end) = &watcher.end {
-                if watcher.start.as_str() <= key && key <= end.as_str() {
-                    let mut event = event.clone();
-                    if !watcher.prev_kv {
-                        event.prev_kv = None;
-                    }
-                    watcher
-                        .sender
-                        .send((watcher.watch_id, header.clone(), event))
-                        .await
-                        .unwrap();
+    /// Hold back events for the watches on the stream with this sender until it is resumed.
+    pub fn pause(&mut self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        for watch in self.stream_watches(sender) {
+            watch.paused = true;
+        }
+    }
+
+    pub fn resume(&mut self, sender: &Sender<(WatchId, Header, WatchEvent<V>)>) {
+        for watch in self.stream_watches(sender) {
+            watch.paused = false;
+            watch.flush();
+        }
+    }
+
+    /// Retry sending the events that streams didn't have room for and drop the watches of streams
+    /// that have gone away.
+    pub fn flush(&mut self) {
+        for watches in self.watches.values_mut() {
+            watches.retain(|watch| !watch.sender.is_closed());
+            for watch in watches.iter_mut() {
+                watch.flush();
+            }
+        }
+        self.watches.retain(|_, watches| !watches.is_empty());
+    }
+
+    /// Replay the history for watches that fell too far behind, once their streams have taken
+    /// everything else.
+    pub fn catch_up<P, S, W>(&mut self, document: &Document<P, S, W, V>)
+    where
+        P: Persister + 'static,
+        S: Syncer,
+        W: Watcher<V>,
+    {
+        let Ok(header) = document.header() else {
+            return;
+        };
+        let compact_revision = document.compact_revision();
+        for watches in self.watches.values_mut() {
+            let cancelled = &mut self.cancelled;
+            watches.retain_mut(|watch| {
+                let Some(lagging_since) = watch.lagging_since else {
+                    return true;
+                };
+                if !watch.buffer.is_empty() || watch.paused {
+                    return true;
                 }
-            } else if watcher.start == key {
-                let mut event = event.clone();
-                if !watcher.prev_kv {
-                    event.prev_kv = None;
+                if lagging_since < compact_revision {
+                    warn!(
+                        watch_id = watch.watch_id,
+                        lagging_since, "Watch fell behind the compaction, cancelling it"
+                    );
+                    cancelled.push((
+                        watch.watch_id,
+                        watch.sender.clone(),
+                        crate::Error::Compacted,
+                    ));
+                    return false;
                 }
-                watcher
-                    .sender
-                    .send((watcher.watch_id, header.clone(), event))
-                    .await
-                    .unwrap();
+                let events = document.history(
+                    &watch.start,
+                    watch.end.as_deref(),
+                    lagging_since,
+                    watch.prev_kv,
+                );
+                watch.lagging_since = None;
+                watch.replayed_revision = header.revision as u64;
+                watch.push_history(&header, events);
+                true
+            });
+        }
+        self.watches.retain(|_, watches| !watches.is_empty());
+    }
+
+    pub async fn receive_event(&mut self, header: Header, event: WatchEvent<V>) {
+        for watch in self.watches.values_mut().flatten() {
+            if watch.filters(&event) || !watch.watches_key(event.typ.key()) {
+                continue;
             }
+            watch.push(header.clone(), event.clone());
         }
     }
 }
@@ -11,7 +11,7 @@ pub trait Watcher<V> {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum WatchEventType<V> {
     Put(KeyValue<V>),
-    Delete(String, u64),
+    Delete(Vec<u8>, u64),
 }
 
 impl<V: Value> WatchEventType<V> {
@@ -26,7 +26,7 @@ impl<V: Value> WatchEventType<V> {
             WatchEventType::Delete(key, mod_revision) => (
                 etcd_proto::mvccpb::event::EventType::Delete,
                 etcd_proto::mvccpb::KeyValue {
-                    key: key.into_bytes(),
+                    key,
                     value: Vec::new(),
                     create_revision: 0,
                     mod_revision: mod_revision as i64,
@@ -37,7 +37,7 @@ impl<V: Value> WatchEventType<V> {
         }
     }
 
-    pub fn key(&self) -> &str {
+    pub fn key(&self) -> &[u8] {
         match self {
             WatchEventType::Put(kv) => &kv.key,
             WatchEventType::Delete(key, _) => key,
@@ -9,9 +9,12 @@ use tracing_subscriber::fmt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq, Serialize, Deserialize, Hydrate, Reconcile)]
+#[derive(
+    Debug, Clone, Default, PartialEq, Hash, Eq, Serialize, Deserialize, Hydrate, Reconcile,
+)]
 #[serde(untagged)]
 enum Json {
+    #[default]
     Null,
     Bool(bool),
     Int(i64),
@@ -1,12 +1,13 @@
 use mergeable_etcd_core::value::Value;
-use tracing::error;
 use tracing::info;
 
+use crate::auth::{request_credentials, Tokens};
 use crate::Doc;
 use crate::DocPersister;
 
 pub struct ClusterServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 #[tonic::async_trait]
@@ -17,10 +18,16 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::cluster_server::Cluste
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::MemberAddRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::MemberAddResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
         let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
         let header = document.header()?;
-        let member = document.add_member(request.peer_ur_ls.clone()).await;
+        let member = document
+            .add_member(request.peer_ur_ls.clone(), request.is_learner)
+            .await;
         info!(peer_urls=?request.peer_ur_ls, id=?member.id, "Added member");
         let members = document.list_members()?;
         Ok(tonic::Response::new(
@@ -37,10 +44,14 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::cluster_server::Cluste
         request: tonic::Request<etcd_proto::etcdserverpb::MemberRemoveRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::MemberRemoveResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member remove request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document.remove_member(request.id).await?;
+        info!(id=?request.id, "Removed member");
         let header = document.header()?;
         let list = document.list_members()?;
 
@@ -57,10 +68,16 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::cluster_server::Cluste
         request: tonic::Request<etcd_proto::etcdserverpb::MemberUpdateRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::MemberUpdateResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member update request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document
+            .update_member(request.id, request.peer_ur_ls.clone())
+            .await?;
+        info!(id=?request.id, peer_urls=?request.peer_ur_ls, "Updated member");
         let header = document.header()?;
         let list = document.list_members()?;
 
@@ -94,10 +111,14 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::cluster_server::Cluste
         request: tonic::Request<etcd_proto::etcdserverpb::MemberPromoteRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::MemberPromoteResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let request = request.into_inner();
-        error!(?request, "Got member_promote request but unimplemented");
-
-        let document = self.document.lock().await;
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        document.promote_member(request.id).await?;
+        info!(id=?request.id, "Promoted member");
         let header = document.header()?;
         let list = document.list_members()?;
 
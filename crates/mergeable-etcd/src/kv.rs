@@ -1,20 +1,23 @@
 use mergeable_etcd_core::value::Value;
 use tonic::Response;
 
+use crate::auth::request_credentials;
+use crate::auth::Tokens;
 use crate::{Doc, DocPersister};
-use etcd_proto::etcdserverpb::{kv_server::Kv, RangeResponse};
+use etcd_proto::etcdserverpb::kv_server::Kv;
 use etcd_proto::etcdserverpb::{DeleteRangeResponse, PutResponse, TxnResponse};
 use tracing::debug;
-use tracing::error;
 
 pub struct KvServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 impl<P: DocPersister, V: Value> Clone for KvServer<P, V> {
     fn clone(&self) -> Self {
         Self {
             document: self.document.clone(),
+            tokens: self.tokens.clone(),
         }
     }
 }
@@ -28,39 +31,29 @@ where
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::RangeRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::RangeResponse>, tonic::Status> {
-        let request: mergeable_etcd_core::RangeRequest = request.into_inner().into();
+        let credentials = request_credentials(&request);
+        let request: mergeable_etcd_core::RangeRequest = request.into_inner().try_into()?;
         debug!(start=?request.start, end=?request.end, "RANGE");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_range_permission(&user, &request)?;
+            }
             document.range(request)
         };
 
         let (header, response) = result?.await.unwrap();
 
-        let kvs = response
-            .values
-            .into_iter()
-            .map(|kv| kv.into())
-            .collect::<Vec<_>>();
-
-        let header = Some(header.into());
-
-        let count = kvs.len() as i64;
-        let reply = RangeResponse {
-            header,
-            count,
-            kvs,
-            more: false,
-        };
-        Ok(Response::new(reply))
+        Ok(Response::new(response.into_etcd(header)))
     }
 
     async fn put(
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::PutRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::PutResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let request: mergeable_etcd_core::PutRequest<V> =
             request.into_inner().try_into().map_err(|err| {
                 tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
@@ -70,6 +63,9 @@ where
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_put_permission(&user, &request)?;
+            }
             document.put(request).await
         };
 
@@ -87,12 +83,16 @@ where
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::DeleteRangeRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::DeleteRangeResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let request: mergeable_etcd_core::DeleteRangeRequest = request.into_inner().into();
         debug!(start=?request.start, end=?request.end, "DELETE_RANGE");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_delete_range_permission(&user, &request)?;
+            }
             document.delete_range(request).await
         };
 
@@ -114,14 +114,16 @@ where
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::TxnRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::TxnResponse>, tonic::Status> {
-        let request = request.into_inner().try_into().map_err(|err| {
-            tonic::Status::invalid_argument(format!("Failed to parse request: {:?}", err))
-        })?;
+        let credentials = request_credentials(&request);
+        let request: mergeable_etcd_core::TxnRequest<V> = request.into_inner().try_into()?;
         debug!("TXN");
 
         let result = {
             // ensure we drop the lock before waiting on the result
             let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_txn_permission(&user, &request)?;
+            }
             document.txn(request).await
         };
 
@@ -143,15 +145,18 @@ where
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::CompactionRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::CompactionResponse>, tonic::Status> {
-        let etcd_proto::etcdserverpb::CompactionRequest {
-            revision: _,
-            physical: _,
-        } = request.into_inner();
-
-        // FIXME: implement compaction
-        let document = self.document.lock().await;
-        error!("got compaction request but not implemented");
-        let header = document.header()?;
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::CompactionRequest { revision, physical } =
+            request.into_inner();
+        debug!(?revision, ?physical, "COMPACT");
+
+        let header = {
+            let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_admin(&user)?;
+            }
+            document.compact(revision as u64, physical)?
+        };
 
         Ok(tonic::Response::new(
             etcd_proto::etcdserverpb::CompactionResponse {
@@ -1,16 +1,19 @@
 use futures::Stream;
 use futures::StreamExt;
+use mergeable_etcd_core::auth::PermissionType;
 use mergeable_etcd_core::value::Value;
 use std::pin::Pin;
-use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::debug;
 
+use crate::auth::request_credentials;
+use crate::auth::Tokens;
 use crate::Doc;
 use crate::DocPersister;
 
 pub(crate) struct LeaseServer<P, V> {
     pub(crate) document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 #[tonic::async_trait]
@@ -21,6 +24,7 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::LeaseGrantRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::LeaseGrantResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let etcd_proto::etcdserverpb::LeaseGrantRequest { ttl, id } = request.into_inner();
 
         debug!(?ttl, ?id, "Got lease_grant request");
@@ -31,37 +35,18 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let ttl = if ttl > 0 { Some(ttl) } else { None };
         let id = if id > 0 { Some(id) } else { None };
 
         let mut document = self.document.lock().await;
+        document.check_not_learner()?;
+        document.check_space()?;
         if let Some((id, ttl)) = document.add_lease(id, ttl) {
-            let document_clone = self.document.clone();
-            tokio::spawn(async move {
-                loop {
-                    // wait for the ttl to pass
-                    tokio::time::sleep(Duration::from_secs(ttl as u64)).await;
-
-                    // check the latest refresh (may have been done already)
-                    let mut doc = document_clone.lock().await;
-                    if let Some(last_refresh) = doc.last_lease_refresh(id) {
-                        let time_since_refresh = chrono::Utc::now().timestamp() - last_refresh;
-                        if time_since_refresh > ttl {
-                            // lease has expired, revoke it and exit
-                            debug!(?id, "Removing lease due to timeout");
-                            doc.remove_lease(id).await;
-                            break;
-                        }
-                    } else {
-                        // failed to find lease so just exit, probably got removed manually
-                        break;
-                    }
-                }
-                debug!(lease_id=?id, "Closing lease revoke check loop");
-            });
-
+            // expiry is handled by the lease expiry loop on whichever member owns it
             let header = document.header()?;
             Ok(tonic::Response::new(
                 etcd_proto::etcdserverpb::LeaseGrantResponse {
@@ -82,13 +67,20 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::LeaseRevokeRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::LeaseRevokeResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let etcd_proto::etcdserverpb::LeaseRevokeRequest { id } = request.into_inner();
 
         debug!(?id, "Got lease_revoke request");
 
         let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            // revoking deletes the attached keys so needs write access to all of them
+            for key in document.keys_for_lease(id) {
+                document.check_permission(&user, &key, None, PermissionType::Write)?;
+            }
+        }
+        document.check_not_learner()?;
         document.remove_lease(id).await;
-        // the revoke poller will exit once it can't find the lease
 
         Ok(tonic::Response::new(
             etcd_proto::etcdserverpb::LeaseRevokeResponse {
@@ -111,6 +103,7 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
         &self,
         request: tonic::Request<tonic::Streaming<etcd_proto::etcdserverpb::LeaseKeepAliveRequest>>,
     ) -> Result<tonic::Response<Self::LeaseKeepAliveStream>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let mut request_stream = request.into_inner();
 
         // check our node is ready
@@ -119,6 +112,8 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let (response_sender, response_receiver) = mpsc::channel(10);
@@ -158,26 +153,36 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
         request: tonic::Request<etcd_proto::etcdserverpb::LeaseTimeToLiveRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::LeaseTimeToLiveResponse>, tonic::Status>
     {
+        let credentials = request_credentials(&request);
         let etcd_proto::etcdserverpb::LeaseTimeToLiveRequest { id, keys } = request.into_inner();
 
-        debug!("Got request for lease ttl");
+        debug!(?id, ?keys, "Got request for lease ttl");
 
-        // check our node is ready
-        {
-            let document = self.document.lock().await;
-            if !document.is_ready() {
-                return Err(tonic::Status::unavailable("node not ready"));
-            }
+        let document = self.document.lock().await;
+        if !document.is_ready() {
+            return Err(tonic::Status::unavailable("node not ready"));
         }
+        // any authenticated user can use leases
+        let user = self.tokens.authenticate(&document, &credentials).await?;
 
-        let document = self.document.lock().await;
         let header = document.header()?;
-        let last_refresh = document.last_lease_refresh(id).unwrap();
-        let granted_ttl = document.granted_lease_ttl(id).unwrap();
-        let time_since_refresh = chrono::Utc::now().timestamp() - last_refresh;
-        let ttl = granted_ttl - time_since_refresh;
+        // like etcd, a lease that doesn't exist has a ttl of -1
+        let (ttl, granted_ttl) = match (
+            document.remaining_lease_ttl(id),
+            document.granted_lease_ttl(id),
+        ) {
+            (Some(ttl), Some(granted_ttl)) => (ttl, granted_ttl),
+            _ => (-1, 0),
+        };
         let keys_for_lease = if keys {
-            document.keys_for_lease(id)
+            let keys_for_lease = document.keys_for_lease(id);
+            if let Some(user) = user {
+                // listing the keys reveals them so needs read access to all of them
+                for key in &keys_for_lease {
+                    document.check_permission(&user, key, None, PermissionType::Read)?;
+                }
+            }
+            keys_for_lease
         } else {
             vec![]
         };
@@ -188,7 +193,7 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
                 id,
                 ttl,
                 granted_ttl,
-                keys: keys_for_lease.into_iter().map(|s| s.into_bytes()).collect(),
+                keys: keys_for_lease,
             },
         ))
     }
@@ -197,6 +202,7 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
         &self,
         request: tonic::Request<etcd_proto::etcdserverpb::LeaseLeasesRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::LeaseLeasesResponse>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let etcd_proto::etcdserverpb::LeaseLeasesRequest {} = request.into_inner();
 
         debug!("Got request for all leases");
@@ -207,6 +213,8 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::lease_server::Lease
             if !document.is_ready() {
                 return Err(tonic::Status::unavailable("node not ready"));
             }
+            // any authenticated user can use leases
+            self.tokens.authenticate(&document, &credentials).await?;
         }
 
         let document = self.document.lock().await;
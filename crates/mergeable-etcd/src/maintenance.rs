@@ -1,11 +1,15 @@
+use crate::auth::{request_credentials, Tokens};
+use crate::snapshot::snapshot_responses;
 use crate::{Doc, DocPersister};
 use futures::Stream;
+use mergeable_etcd_core::alarm::{Alarm, AlarmType};
 use mergeable_etcd_core::value::Value;
 use std::pin::Pin;
-use tracing::info;
+use tracing::{debug, info};
 
 pub struct MaintenanceServer<P, V> {
     pub document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 const VERSION: &str = "3.3.27";
@@ -16,9 +20,66 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::maintenance_server::Ma
 {
     async fn alarm(
         &self,
-        _request: tonic::Request<etcd_proto::etcdserverpb::AlarmRequest>,
+        request: tonic::Request<etcd_proto::etcdserverpb::AlarmRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::AlarmResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::AlarmRequest {
+            action,
+            member_id,
+            alarm,
+        } = request.into_inner();
+        let action = etcd_proto::etcdserverpb::alarm_request::AlarmAction::from_i32(action)
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown alarm action"))?;
+        let alarm = etcd_proto::etcdserverpb::AlarmType::from_i32(alarm)
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown alarm type"))?;
+        debug!(?action, ?member_id, ?alarm, "Got alarm request");
+        // none matches any alarm when getting them but isn't an alarm itself
+        let alarm = AlarmType::try_from(alarm).ok();
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let alarms = match action {
+            etcd_proto::etcdserverpb::alarm_request::AlarmAction::Get => document
+                .alarms()
+                .into_iter()
+                .filter(|a| alarm.is_none() || alarm == Some(a.alarm))
+                .collect(),
+            etcd_proto::etcdserverpb::alarm_request::AlarmAction::Activate => {
+                let alarm = Alarm {
+                    member_id,
+                    alarm: alarm.ok_or_else(|| {
+                        tonic::Status::invalid_argument("alarm type must be given")
+                    })?,
+                };
+                if document.activate_alarm(alarm) {
+                    vec![alarm]
+                } else {
+                    Vec::new()
+                }
+            }
+            etcd_proto::etcdserverpb::alarm_request::AlarmAction::Deactivate => {
+                let alarm = Alarm {
+                    member_id,
+                    alarm: alarm.ok_or_else(|| {
+                        tonic::Status::invalid_argument("alarm type must be given")
+                    })?,
+                };
+                if document.deactivate_alarm(alarm) {
+                    vec![alarm]
+                } else {
+                    Vec::new()
+                }
+            }
+        };
+
+        Ok(tonic::Response::new(
+            etcd_proto::etcdserverpb::AlarmResponse {
+                header: Some(document.header()?.into()),
+                alarms: alarms.into_iter().map(|alarm| alarm.into()).collect(),
+            },
+        ))
     }
 
     async fn status(
@@ -44,7 +105,7 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::maintenance_server::Ma
             raft_applied_index: 1,
             errors: Vec::new(),
             db_size_in_use: db_size as i64,
-            is_learner: false,
+            is_learner: document.member().is_learner,
         };
 
         Ok(tonic::Response::new(response))
@@ -52,23 +113,80 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::maintenance_server::Ma
 
     async fn defragment(
         &self,
-        _request: tonic::Request<etcd_proto::etcdserverpb::DefragmentRequest>,
+        request: tonic::Request<etcd_proto::etcdserverpb::DefragmentRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::DefragmentResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::DefragmentRequest {} = request.into_inner();
+        debug!("Got defragment request");
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let (before, after) = document.defragment();
+        info!(
+            before_changes = before.changes,
+            before_document = before.document,
+            after_changes = after.changes,
+            after_document = after.document,
+            "Defragmented storage"
+        );
+
+        Ok(tonic::Response::new(
+            etcd_proto::etcdserverpb::DefragmentResponse {
+                header: Some(document.header()?.into()),
+            },
+        ))
     }
 
     async fn hash(
         &self,
-        _request: tonic::Request<etcd_proto::etcdserverpb::HashRequest>,
+        request: tonic::Request<etcd_proto::etcdserverpb::HashRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::HashResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::HashRequest {} = request.into_inner();
+        debug!("Got hash request");
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let hash = document.hash();
+
+        Ok(tonic::Response::new(
+            etcd_proto::etcdserverpb::HashResponse {
+                header: Some(document.header()?.into()),
+                hash,
+            },
+        ))
     }
 
     async fn hash_kv(
         &self,
-        _request: tonic::Request<etcd_proto::etcdserverpb::HashKvRequest>,
+        request: tonic::Request<etcd_proto::etcdserverpb::HashKvRequest>,
     ) -> Result<tonic::Response<etcd_proto::etcdserverpb::HashKvResponse>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::HashKvRequest { revision } = request.into_inner();
+        debug!(?revision, "Got hash_kv request");
+        let revision = if revision > 0 {
+            Some(revision as u64)
+        } else {
+            None
+        };
+
+        let mut document = self.document.lock().await;
+        if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+            document.check_admin(&user)?;
+        }
+        let hash = document.hash_kv(revision)?;
+
+        Ok(tonic::Response::new(
+            etcd_proto::etcdserverpb::HashKvResponse {
+                header: Some(document.header()?.into()),
+                hash,
+                compact_revision: document.compact_revision() as i64,
+            },
+        ))
     }
 
     type SnapshotStream = Pin<
@@ -82,9 +200,25 @@ impl<P: DocPersister, V: Value> etcd_proto::etcdserverpb::maintenance_server::Ma
 
     async fn snapshot(
         &self,
-        _request: tonic::Request<etcd_proto::etcdserverpb::SnapshotRequest>,
+        request: tonic::Request<etcd_proto::etcdserverpb::SnapshotRequest>,
     ) -> Result<tonic::Response<Self::SnapshotStream>, tonic::Status> {
-        todo!()
+        let credentials = request_credentials(&request);
+        let etcd_proto::etcdserverpb::SnapshotRequest {} = request.into_inner();
+        debug!("Got snapshot request");
+
+        let (header, snapshot) = {
+            let mut document = self.document.lock().await;
+            if let Some(user) = self.tokens.authenticate(&document, &credentials).await? {
+                document.check_admin(&user)?;
+            }
+            (document.header()?, document.snapshot())
+        };
+        info!(size = snapshot.len(), "Streaming snapshot");
+
+        let responses = snapshot_responses(header.into(), snapshot);
+        Ok(tonic::Response::new(Box::pin(futures::stream::iter(
+            responses.into_iter().map(Ok::<_, tonic::Status>),
+        ))))
     }
 
     async fn move_leader(
@@ -1,26 +1,85 @@
 use automerge::sync;
 use etcd_proto::etcdserverpb::Member;
-use peer_proto::{HelloRequest, HelloResponse, SyncChanges, SyncMessage};
+use futures::Stream;
+use peer_proto::{
+    peer_message, Heartbeat, HelloRequest, HelloResponse, PeerMessage, SyncAck, SyncChanges,
+    SyncMessage,
+};
+use prometheus_client::metrics::counter::Counter;
+use prost::Message as _;
 use std::{
     collections::HashMap,
     fmt::Debug,
-    sync::Arc,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::body::BoxBody;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::{http, Body as _};
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tower::util::BoxCloneService;
+use tower::ServiceBuilder;
 use tracing::{debug, info, warn};
 
-use mergeable_etcd_core::{value::Value, Syncer};
+use mergeable_etcd_core::{hlc::HlcTimestamp, value::Value, Syncer, MEMBER_HEARTBEAT_INTERVAL};
 
+use crate::metrics::PeerMetrics;
 use crate::{Doc, DocPersister};
 
 const SYNC_SLEEP_DURATION: Duration = Duration::from_millis(10);
 
+/// How many messages can be sent to a peer before it has to acknowledge applying them.
+const MAX_IN_FLIGHT_MESSAGES: usize = 16;
+
+/// How often to let peers know we are still alive.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(MEMBER_HEARTBEAT_INTERVAL);
+
+/// Longest to wait before reopening a broken sync stream.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(5);
+
+type PeerClient = peer_proto::peer_client::PeerClient<
+    BoxCloneService<
+        http::Request<BoxBody>,
+        http::Response<tonic::transport::Body>,
+        tonic::transport::Error,
+    >,
+>;
+
+/// Make a client for syncing with a peer, counting the bytes it sends after any compression.
+fn peer_client(channel: Channel, compression: bool, sent_bytes: Counter) -> PeerClient {
+    let service = ServiceBuilder::new()
+        .map_request(move |request: http::Request<BoxBody>| {
+            let sent_bytes = sent_bytes.clone();
+            request.map(|body| {
+                body.map_data(move |data| {
+                    sent_bytes.inc_by(data.len() as u64);
+                    data
+                })
+                .boxed_unsync()
+            })
+        })
+        .service(channel);
+    let client =
+        PeerClient::new(BoxCloneService::new(service)).accept_compressed(CompressionEncoding::Gzip);
+    if compression {
+        client.send_compressed(CompressionEncoding::Gzip)
+    } else {
+        client
+    }
+}
+
 pub struct DocumentChangedSyncer {
     pub notify: Arc<tokio::sync::Notify>,
     pub local_change_sender: broadcast::Sender<Vec<Vec<u8>>>,
     pub member_changed: broadcast::Sender<Member>,
+    pub member_removed: broadcast::Sender<u64>,
 }
 
 #[tonic::async_trait]
@@ -42,11 +101,26 @@ impl Syncer for DocumentChangedSyncer {
     async fn member_change(&mut self, member: &Member) {
         self.member_changed.send(member.clone()).unwrap();
     }
+
+    async fn member_remove(&mut self, id: u64) {
+        self.member_removed.send(id).unwrap();
+    }
 }
 
 pub struct PeerSyncer {
     address: String,
-    sender: mpsc::Sender<Message>,
+    sender: mpsc::Sender<PeerMessage>,
+    /// Messages sent on the sync stream that the peer hasn't acknowledged yet.
+    in_flight: Arc<AtomicUsize>,
+    /// The task keeping the sync stream to the peer open, which would otherwise keep retrying the
+    /// address after this syncer is gone.
+    stream_task: JoinHandle<()>,
+}
+
+impl Drop for PeerSyncer {
+    fn drop(&mut self) {
+        self.stream_task.abort();
+    }
 }
 
 impl Debug for PeerSyncer {
@@ -58,11 +132,14 @@ impl Debug for PeerSyncer {
 }
 
 impl PeerSyncer {
-    async fn new(
+    async fn new<P: DocPersister, V: Value>(
+        document: Doc<P, V>,
         address: String,
         ca_certificate: &Option<Vec<u8>>,
         member: Member,
         their_id: Option<u64>,
+        compression: bool,
+        metrics: &PeerMetrics,
     ) -> (u64, Self) {
         debug!(address, "Setting up peer syncer");
         let (msg_sender, mut msg_receiver) = mpsc::channel(1);
@@ -74,15 +151,15 @@ impl PeerSyncer {
         if let Some(tls_config) = tls_config {
             channel = channel.tls_config(tls_config).unwrap();
         }
-        let (id, mut client) = loop {
+        let (id, connected) = loop {
             debug!(address = address_clone, "Trying to connect to peer");
             match channel.connect().await {
-                Ok(channel) => {
-                    let mut client = peer_proto::peer_client::PeerClient::new(channel);
+                Ok(connected) => {
+                    let mut client = peer_proto::peer_client::PeerClient::new(connected.clone());
                     info!(address=?address_clone, "Connected client");
                     // if we already know who they are then don't worry about finding out
                     if let Some(their_id) = their_id {
-                        break (their_id, client);
+                        break (their_id, connected);
                     }
                     // otherwise introduce ourselves
                     let request = HelloRequest {
@@ -98,7 +175,7 @@ impl PeerSyncer {
                         Ok(res) => {
                             let res = res.into_inner();
                             debug!(?res, "Got peer member id");
-                            break (res.themselves.unwrap().id, client);
+                            break (res.themselves.unwrap().id, connected);
                         }
                         Err(err) => {
                             debug!(%err, "Error trying to get peer member id");
@@ -113,63 +190,59 @@ impl PeerSyncer {
         };
         debug!(address = address_clone, "Connected client");
 
-        tokio::spawn(async move {
-            debug!(address = address_clone, "Waiting on messages to send");
+        let sent_bytes = metrics.for_peer(id);
+        let mut client = peer_client(connected, compression, sent_bytes.compressed.clone());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let in_flight_clone = Arc::clone(&in_flight);
+        let stream_task = tokio::spawn(async move {
+            let in_flight = in_flight_clone;
+            let mut retry_wait = Duration::from_millis(1);
             loop {
-                match msg_receiver.recv().await {
-                    Some(message) => {
-                        debug!(address=?address_clone, "Sending message on client");
-                        let message: Message = message;
-
-                        // Try and send the message, retrying if it fails
-                        let mut retry_wait = Duration::from_millis(1);
-                        let retry_max = Duration::from_secs(5);
-
-                        loop {
-                            let res = match message.clone() {
-                                Message::SyncMessage(m) => client.sync_one(m).await.map(|_| ()),
-                                Message::SyncChanges(m) => client.send_changes(m).await.map(|_| ()),
-                            };
-                            debug!(address=?address_clone, "Sent sync message to client");
-                            match res {
-                                Ok(_response) => {
-                                    break;
-                                }
-                                Err(error) => {
-                                    // don't race into the next request
-                                    tokio::time::sleep(retry_wait).await;
-
-                                    // exponential backoff
-                                    retry_wait *= 2;
-                                    // but don't let it get too high!
-                                    retry_wait = std::cmp::min(retry_wait, retry_max);
-
-                                    warn!(%error, ?retry_wait, address=?address_clone,  "Got error sending sync message to peer");
-                                    // had an error, reconnect the client
-                                    client = loop {
-                                        match channel.connect().await {
-                                            Ok(channel) => {
-                                                let client =
-                                                    peer_proto::peer_client::PeerClient::new(
-                                                        channel,
-                                                    );
-                                                info!(address=?address_clone, "Reconnected client");
-                                                break client;
-                                            }
-                                            Err(err) => {
-                                                warn!(address=?address_clone, %err, "Failed to reconnect client");
-                                                tokio::time::sleep(Duration::from_millis(1000))
-                                                    .await;
-                                            }
-                                        }
-                                    };
-                                }
+                let (stream_sender, stream_receiver) = mpsc::channel(MAX_IN_FLIGHT_MESSAGES);
+                let error = match client.sync(ReceiverStream::new(stream_receiver)).await {
+                    Ok(acks) => {
+                        info!(address=?address_clone, "Opened sync stream to peer");
+                        retry_wait = Duration::from_millis(1);
+                        in_flight.store(0, Ordering::SeqCst);
+                        // anything in flight on an earlier stream may have been lost
+                        document.lock().await.resume_sync(id);
+                        let acks = acks.into_inner();
+                        match forward_messages(
+                            &mut msg_receiver,
+                            stream_sender,
+                            acks,
+                            &in_flight,
+                            &sent_bytes.uncompressed,
+                        )
+                        .await
+                        {
+                            Some(error) => error,
+                            None => {
+                                warn!(address=?address_clone, "No more messages to send, closing");
+                                break;
                             }
                         }
                     }
-                    None => {
-                        warn!(address=?address_clone, "No more messages to send, closing");
-                        break;
+                    Err(error) => error,
+                };
+                if error.code() == tonic::Code::FailedPrecondition {
+                    // the peer won't take a new stream either, such as when it has removed us
+                    warn!(%error, address=?address_clone, "Peer refused sync stream");
+                    break;
+                }
+
+                warn!(%error, ?retry_wait, address=?address_clone, "Sync stream to peer broke");
+                // don't race into reconnecting
+                tokio::time::sleep(retry_wait).await;
+                // exponential backoff, but don't let it get too high!
+                retry_wait = std::cmp::min(retry_wait * 2, MAX_RETRY_WAIT);
+                match channel.connect().await {
+                    Ok(channel) => {
+                        client = peer_client(channel, compression, sent_bytes.compressed.clone());
+                        info!(address=?address_clone, "Reconnected client");
+                    }
+                    Err(err) => {
+                        warn!(address=?address_clone, %err, "Failed to reconnect client");
                     }
                 }
             }
@@ -179,26 +252,39 @@ impl PeerSyncer {
             Self {
                 address,
                 sender: msg_sender,
+                in_flight,
+                stream_task,
             },
         )
     }
 
-    pub async fn send_message(&mut self, from: u64, to: u64, name: String, msg: Vec<u8>) {
+    pub async fn send_message(
+        &mut self,
+        from: u64,
+        to: u64,
+        name: String,
+        hlc: HlcTimestamp,
+        msg: Vec<u8>,
+    ) {
         debug!(?from, ?to, ?name, "Sending message to peer");
 
         let _: Result<_, _> = self
             .sender
-            .send(Message::SyncMessage(SyncMessage {
-                from,
-                to,
-                name,
-                data: msg,
-            }))
+            .send(PeerMessage {
+                message: Some(peer_message::Message::SyncMessage(SyncMessage {
+                    from,
+                    to,
+                    name,
+                    data: msg,
+                    hlc: hlc.to_u64(),
+                })),
+            })
             .await;
     }
 
+    /// Whether the peer can take another message, it has to acknowledge the ones in flight first.
     pub fn can_send(&self) -> bool {
-        self.sender.capacity() > 0
+        self.sender.capacity() > 0 && self.in_flight.load(Ordering::SeqCst) < MAX_IN_FLIGHT_MESSAGES
     }
 
     pub async fn send_local_changes(
@@ -206,20 +292,66 @@ impl PeerSyncer {
         from: u64,
         to: u64,
         name: String,
+        hlc: HlcTimestamp,
         changes: Vec<Vec<u8>>,
     ) {
         debug!(?from, ?to, ?name, "Sending changes to peer");
 
         let _: Result<_, _> = self
             .sender
-            .send(Message::SyncChanges(SyncChanges {
-                from,
-                to,
-                name,
-                changes,
-            }))
+            .send(PeerMessage {
+                message: Some(peer_message::Message::SyncChanges(SyncChanges {
+                    from,
+                    to,
+                    name,
+                    changes,
+                    hlc: hlc.to_u64(),
+                })),
+            })
             .await;
     }
+
+    /// Let the peer know that we are still alive, skipped if there are already messages waiting
+    /// to go to it as they tell it the same.
+    pub fn send_heartbeat(&self, from: u64, hlc: HlcTimestamp) {
+        let _: Result<_, _> = self.sender.try_send(PeerMessage {
+            message: Some(peer_message::Message::Heartbeat(Heartbeat {
+                from,
+                hlc: hlc.to_u64(),
+            })),
+        });
+    }
+}
+
+/// Send messages on to the peer's sync stream until the syncer is dropped, returning `None`, or
+/// the stream breaks.
+async fn forward_messages(
+    messages: &mut mpsc::Receiver<PeerMessage>,
+    stream: mpsc::Sender<PeerMessage>,
+    mut acks: tonic::Streaming<SyncAck>,
+    in_flight: &AtomicUsize,
+    sent_bytes: &Counter,
+) -> Option<tonic::Status> {
+    loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let message = message?;
+                sent_bytes.inc_by(message.encoded_len() as u64);
+                // counted before sending so that the ack can't arrive first
+                in_flight.fetch_add(1, Ordering::SeqCst);
+                if stream.send(message).await.is_err() {
+                    return Some(tonic::Status::unavailable("sync stream closed"));
+                }
+            }
+            ack = acks.message() => match ack {
+                Ok(Some(SyncAck {})) => {
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+                Ok(None) => return Some(tonic::Status::unavailable("sync stream closed by peer")),
+                Err(error) => return Some(error),
+            },
+        }
+    }
 }
 
 pub struct PeerServerInner<P, V> {
@@ -227,15 +359,25 @@ pub struct PeerServerInner<P, V> {
     // map from peer id to the syncer running for them
     connections: HashMap<u64, PeerSyncer>,
     ca_certificate: Option<Vec<u8>>,
+    // whether to compress what we send to peers
+    compression: bool,
+    metrics: PeerMetrics,
 }
 
 impl<P: DocPersister, V: Value> PeerServerInner<P, V> {
-    async fn new(document: Doc<P, V>, ca_certificate: Option<Vec<u8>>) -> Self {
+    async fn new(
+        document: Doc<P, V>,
+        ca_certificate: Option<Vec<u8>>,
+        compression: bool,
+        metrics: PeerMetrics,
+    ) -> Self {
         let connections = HashMap::new();
         let s = Self {
             document,
             connections,
             ca_certificate,
+            compression,
+            metrics,
         };
         s
     }
@@ -251,8 +393,9 @@ impl<P: DocPersister, V: Value> PeerServerInner<P, V> {
         debug!(?to_id, "attempting to send changes");
         let syncer = self.connections.get_mut(&to_id).unwrap();
         if syncer.can_send() {
+            let hlc = self.document.lock().await.now();
             syncer
-                .send_local_changes(from_id, to_id, from_name.to_owned(), changes)
+                .send_local_changes(from_id, to_id, from_name.to_owned(), hlc, changes)
                 .await;
         }
     }
@@ -264,16 +407,15 @@ impl<P: DocPersister, V: Value> PeerServerInner<P, V> {
         debug!("Started generating sync message");
         let syncer = self.connections.get_mut(&to_id).unwrap();
         if syncer.can_send() {
-            let message = self
-                .document
-                .lock()
-                .await
-                .generate_sync_message(to_id)
-                .map(|m| m.encode());
+            let (hlc, message) = {
+                let mut doc = self.document.lock().await;
+                let message = doc.generate_sync_message(to_id).map(|m| m.encode());
+                (doc.now(), message)
+            };
             debug!("Finished generating sync message");
             if let Some(msg) = message {
                 syncer
-                    .send_message(from_id, to_id, from_name.to_owned(), msg)
+                    .send_message(from_id, to_id, from_name.to_owned(), hlc, msg)
                     .await;
             }
         }
@@ -304,6 +446,7 @@ impl<P, V> Clone for PeerServer<P, V> {
 }
 
 impl<P: DocPersister, V: Value> PeerServer<P, V> {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         document: Doc<P, V>,
         name: &str,
@@ -311,10 +454,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         notify: Arc<tokio::sync::Notify>,
         mut local_changes: broadcast::Receiver<Vec<Vec<u8>>>,
         mut member_changed: broadcast::Receiver<Member>,
+        mut member_removed: broadcast::Receiver<u64>,
         ca_certificate: Option<Vec<u8>>,
+        compression: bool,
+        metrics: PeerMetrics,
     ) -> Self {
         let inner = Arc::new(Mutex::new(
-            PeerServerInner::new(document, ca_certificate.clone()).await,
+            PeerServerInner::new(document, ca_certificate, compression, metrics).await,
         ));
         let s = Self { inner };
 
@@ -343,6 +489,14 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
             }
         });
 
+        let s_clone = s.clone();
+        tokio::spawn(async move {
+            // stop syncing with members removed from the document
+            while let Ok(id) = member_removed.recv().await {
+                s_clone.member_removed(id).await;
+            }
+        });
+
         let s_clone = s.clone();
         tokio::spawn(async move {
             // handle local changes
@@ -360,9 +514,33 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
                 tokio::time::sleep(SYNC_SLEEP_DURATION).await;
             }
         });
+
+        let s_clone = s.clone();
+        tokio::spawn(async move {
+            // let peers know we are alive even when there is nothing to sync
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                s_clone.send_heartbeats().await;
+            }
+        });
         s
     }
 
+    /// Send a heartbeat to each of the peers we are connected to.
+    pub async fn send_heartbeats(&self) {
+        let inner = self.inner.lock().await;
+        let (member_id, hlc) = {
+            let mut doc = inner.document.lock().await;
+            (doc.member_id(), doc.now())
+        };
+        for (id, syncer) in &inner.connections {
+            if *id != member_id {
+                syncer.send_heartbeat(member_id, hlc);
+            }
+        }
+    }
+
     pub async fn send_local_changes(&self, changes: Vec<Vec<u8>>) {
         debug!("sending local changes");
         let member = self.inner.lock().await.member().await;
@@ -392,33 +570,94 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
     }
 
     pub async fn member_changed(&self, member: Member) {
-        let inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await;
+        let us = inner.member().await;
+        if member.id == us.id {
+            debug!(?member, "Skipping updated member as it is us!");
+            return;
+        }
         // see if we have a connection to that member
         if let Some(syncer) = inner.connections.get(&member.id) {
-            // they have the same address
-            let our_peer_address = member.peer_ur_ls.first().unwrap();
-            let us = inner.member().await;
-            let name = us.name;
             if member.peer_ur_ls.contains(&syncer.address) {
                 // no change
-            } else if member.peer_ur_ls.contains(&our_peer_address) {
-                debug!(?name, ?member, "Skipping updated member as it is us!");
-            } else {
-                warn!(?name, ?member, ?our_peer_address, ?syncer.address, "hit update member");
-                // FIXME: should update address somehow
+                return;
             }
-        } else {
-            let s = self.clone();
-            tokio::spawn(async move {
-                let name = member.name;
-                s.add_connection(
-                    &name,
-                    member.peer_ur_ls.first().unwrap().to_owned(),
-                    Some(member.id),
-                )
-                .await;
-            });
+            // their address has changed so drop the old connection and connect to the new one
+            info!(?member, old_address=?syncer.address, "Reconnecting to updated member");
+            inner.connections.remove(&member.id);
+        }
+        let s = self.clone();
+        tokio::spawn(async move {
+            let name = member.name;
+            s.add_connection(
+                &name,
+                member.peer_ur_ls.first().unwrap().to_owned(),
+                Some(member.id),
+            )
+            .await;
+        });
+    }
+
+    /// Drop the connection to a member that has been removed from the cluster.
+    pub async fn member_removed(&self, id: u64) {
+        if self.inner.lock().await.connections.remove(&id).is_some() {
+            info!(member_id=?id, "Dropped connection to removed member");
+        }
+    }
+
+    /// Apply a sync message from a peer, whether it came on a stream or on its own.
+    async fn handle_sync_message(&self, message: SyncMessage) -> Result<(), tonic::Status> {
+        let SyncMessage {
+            from,
+            to,
+            name,
+            data,
+            hlc,
+        } = message;
+        self.check_not_removed(from).await?;
+        let message = sync::Message::decode(&data).unwrap();
+        self.receive_message(from, to, name, HlcTimestamp::from_u64(hlc), message)
+            .await;
+        Ok(())
+    }
+
+    /// Apply changes pushed from a peer, whether they came on a stream or on their own.
+    async fn handle_sync_changes(&self, changes: SyncChanges) -> Result<(), tonic::Status> {
+        let SyncChanges {
+            from,
+            to,
+            name,
+            changes,
+            hlc,
+        } = changes;
+        self.check_not_removed(from).await?;
+        let changes = changes
+            .into_iter()
+            .filter_map(|c| automerge::Change::from_bytes(c).ok());
+        self.receive_changes(from, to, name, HlcTimestamp::from_u64(hlc), changes)
+            .await;
+        Ok(())
+    }
+
+    /// Note that a peer is still alive, keeping our clock in step with theirs.
+    async fn handle_heartbeat(&self, heartbeat: Heartbeat) -> Result<(), tonic::Status> {
+        let Heartbeat { from, hlc } = heartbeat;
+        self.check_not_removed(from).await?;
+        let inner = self.inner.lock().await;
+        let mut doc = inner.document.lock().await;
+        doc.update_clock(from, HlcTimestamp::from_u64(hlc));
+        doc.heard_from(from);
+        Ok(())
+    }
+
+    /// Refuse requests from members that have been removed from the cluster.
+    async fn check_not_removed(&self, id: u64) -> Result<(), tonic::Status> {
+        let inner = self.inner.lock().await;
+        if inner.document.lock().await.is_member_removed(id) {
+            warn!(member_id=?id, "Refusing request from removed member");
+            return Err(mergeable_etcd_core::Error::MemberRemoved.into());
         }
+        Ok(())
     }
 
     pub async fn document_changed(&self) {
@@ -460,7 +699,19 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         info!("Started adding peer connection");
         let us = self.inner.lock().await.member().await;
         let ca_cert = self.inner.lock().await.ca_certificate.clone();
-        let (id, syncer) = PeerSyncer::new(address.clone(), &ca_cert, us.clone(), their_id).await;
+        let document = Arc::clone(&self.inner.lock().await.document);
+        let compression = self.inner.lock().await.compression;
+        let metrics = self.inner.lock().await.metrics.clone();
+        let (id, syncer) = PeerSyncer::new(
+            document,
+            address.clone(),
+            &ca_cert,
+            us.clone(),
+            their_id,
+            compression,
+            &metrics,
+        )
+        .await;
         self.inner.lock().await.connections.insert(id, syncer);
         info!("Finished adding peer connection");
         id
@@ -473,6 +724,7 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         from: u64,
         to: u64,
         name: String,
+        hlc: HlcTimestamp,
         message: sync::Message<'_>,
     ) {
         let mut inner = self.inner.lock().await;
@@ -483,6 +735,7 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
             let mut doc = inner.document.lock().await;
             let start = Instant::now();
             debug!(changes = ?message.changes.len(), "Started receiving sync message");
+            doc.update_clock(from, hlc);
             doc.receive_sync_message(from, message)
                 .await
                 .unwrap()
@@ -506,10 +759,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
                 debug!("Initiating reverse connection");
                 let us = inner.document.lock().await.member();
                 let (id, syncer) = PeerSyncer::new(
+                    Arc::clone(&inner.document),
                     member.peer_ur_ls.first().unwrap().to_owned(),
                     &inner.ca_certificate,
                     us,
                     Some(from),
+                    inner.compression,
+                    &inner.metrics,
                 )
                 .await;
                 debug!("Setup reverse connection");
@@ -527,6 +783,7 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
         from: u64,
         to: u64,
         name: String,
+        hlc: HlcTimestamp,
         changes: impl Iterator<Item = automerge::Change>,
     ) {
         let mut inner = self.inner.lock().await;
@@ -537,6 +794,7 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
             let mut doc = inner.document.lock().await;
             let start = Instant::now();
             debug!("Started receiving sync changes");
+            doc.update_clock(from, hlc);
             doc.receive_sync_changes(from, changes).await.unwrap();
             debug!("Finished receiving sync message");
             let duration = start.elapsed();
@@ -557,10 +815,13 @@ impl<P: DocPersister, V: Value> PeerServer<P, V> {
                 debug!("Initiating reverse connection");
                 let us = inner.document.lock().await.member();
                 let (id, syncer) = PeerSyncer::new(
+                    Arc::clone(&inner.document),
                     member.peer_ur_ls.first().unwrap().to_owned(),
                     &inner.ca_certificate,
                     us,
                     Some(from),
+                    inner.compression,
+                    &inner.metrics,
                 )
                 .await;
                 debug!("Setup reverse connection");
@@ -577,14 +838,49 @@ impl<P: DocPersister, V: Value> peer_proto::peer_server::Peer for PeerServer<P,
 where
     P::Error: Send,
 {
+    type SyncStream = Pin<Box<dyn Stream<Item = Result<SyncAck, tonic::Status>> + Send + 'static>>;
+
     async fn sync(
         &self,
-        request: tonic::Request<tonic::Streaming<SyncMessage>>,
-    ) -> Result<tonic::Response<peer_proto::Empty>, tonic::Status> {
-        let _request = request.into_inner();
-        warn!("got sync request that isn't implemented");
+        request: tonic::Request<tonic::Streaming<PeerMessage>>,
+    ) -> Result<tonic::Response<Self::SyncStream>, tonic::Status> {
+        let mut messages = request.into_inner();
+        debug!("SYNC stream from peer");
+        let (ack_sender, ack_receiver) = mpsc::channel(MAX_IN_FLIGHT_MESSAGES);
+        let s = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = match messages.message().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(error) => {
+                        debug!(%error, "Sync stream from peer broke");
+                        break;
+                    }
+                };
+                let result = match message.message {
+                    Some(peer_message::Message::SyncMessage(message)) => {
+                        s.handle_sync_message(message).await
+                    }
+                    Some(peer_message::Message::SyncChanges(changes)) => {
+                        s.handle_sync_changes(changes).await
+                    }
+                    Some(peer_message::Message::Heartbeat(heartbeat)) => {
+                        s.handle_heartbeat(heartbeat).await
+                    }
+                    None => Ok(()),
+                };
+                let refused = result.is_err();
+                // stop once the peer has gone away or we've refused it
+                if ack_sender.send(result.map(|()| SyncAck {})).await.is_err() || refused {
+                    break;
+                }
+            }
+        });
 
-        Ok(tonic::Response::new(peer_proto::Empty {}))
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(
+            ack_receiver,
+        ))))
     }
 
     #[tracing::instrument(skip(self, request))]
@@ -594,14 +890,7 @@ where
     ) -> Result<tonic::Response<peer_proto::Empty>, tonic::Status> {
         let request = request.into_inner();
         debug!(?request, "SYNC_ONE from peer");
-        let SyncMessage {
-            from,
-            to,
-            name,
-            data,
-        } = request;
-        let message = sync::Message::decode(&data).unwrap();
-        self.receive_message(from, to, name, message).await;
+        self.handle_sync_message(request).await?;
 
         Ok(tonic::Response::new(peer_proto::Empty {}))
     }
@@ -617,16 +906,7 @@ where
             changes = request.changes.len(),
             "SYNC_CHANGES from peer"
         );
-        let SyncChanges {
-            from,
-            to,
-            name,
-            changes,
-        } = request;
-        let changes = changes
-            .into_iter()
-            .filter_map(|c| automerge::Change::from_bytes(c).ok());
-        self.receive_changes(from, to, name, changes).await;
+        self.handle_sync_changes(request).await?;
 
         Ok(tonic::Response::new(peer_proto::SyncChangesResponse {}))
     }
@@ -639,6 +919,7 @@ where
         let request = request.into_inner();
         debug!(?request, "HELLO from peer");
         let them = request.myself.unwrap();
+        self.check_not_removed(them.id).await?;
         let us = self.inner.lock().await.member().await;
         let s = self.clone();
         if !self.has_connection(&them.id).await {
@@ -703,9 +984,3 @@ pub fn split_initial_cluster(s: &str) -> HashMap<String, String> {
     }
     cluster
 }
-
-#[derive(Clone)]
-enum Message {
-    SyncMessage(SyncMessage),
-    SyncChanges(SyncChanges),
-}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use automerge_persistent::Persister;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::persister::{PersisterDispatcher, PersisterDispatcherError};
+
+/// How much of the snapshot to send in each response, the same as etcd.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Length of the sha256 checksum that trails a snapshot.
+const CHECKSUM_SIZE: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RestoreError {
+    #[error("failed to read snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("snapshot is too short to contain a checksum")]
+    MissingChecksum,
+    #[error("snapshot checksum does not match its contents")]
+    ChecksumMismatch,
+    #[error("data dir already contains a document")]
+    NotEmpty,
+    #[error("failed to persist snapshot: {0}")]
+    Persister(#[from] PersisterDispatcherError),
+}
+
+/// Split a saved document into the responses to stream back to the client, with a trailing
+/// sha256 checksum of the document so that it can be verified on restore.
+pub(crate) fn snapshot_responses(
+    header: etcd_proto::etcdserverpb::ResponseHeader,
+    mut snapshot: Vec<u8>,
+) -> Vec<etcd_proto::etcdserverpb::SnapshotResponse> {
+    let checksum = Sha256::digest(&snapshot);
+    snapshot.extend_from_slice(&checksum);
+
+    let mut remaining_bytes = snapshot.len();
+    snapshot
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            remaining_bytes -= chunk.len();
+            etcd_proto::etcdserverpb::SnapshotResponse {
+                header: Some(header.clone()),
+                remaining_bytes: remaining_bytes as u64,
+                blob: chunk.to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Seed an empty data dir with the document from a snapshot file, as saved by `etcdctl snapshot
+/// save`.
+pub(crate) fn restore(
+    path: &Path,
+    persister: &mut PersisterDispatcher,
+) -> Result<(), RestoreError> {
+    let mut snapshot = std::fs::read(path)?;
+    if snapshot.len() < CHECKSUM_SIZE {
+        return Err(RestoreError::MissingChecksum);
+    }
+    let checksum = snapshot.split_off(snapshot.len() - CHECKSUM_SIZE);
+    if Sha256::digest(&snapshot).as_slice() != checksum {
+        return Err(RestoreError::ChecksumMismatch);
+    }
+
+    let existing_document = persister.get_document()?;
+    let existing_changes = persister.get_changes()?;
+    if existing_document.is_some() || !existing_changes.is_empty() {
+        return Err(RestoreError::NotEmpty);
+    }
+
+    info!(?path, size = snapshot.len(), "Restoring snapshot");
+    persister.set_document(snapshot)?;
+    persister.flush()?;
+    Ok(())
+}
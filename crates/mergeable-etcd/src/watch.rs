@@ -1,27 +1,64 @@
+use etcd_proto::etcdserverpb::{
+    watch_create_request::FilterType, WatchCancelRequest, WatchCreateRequest,
+};
 use etcd_proto::etcdserverpb::{watch_request::RequestUnion, watch_server::Watch, WatchResponse};
-use etcd_proto::etcdserverpb::{WatchCancelRequest, WatchCreateRequest};
+use futures::stream::FuturesUnordered;
 use futures::Stream;
 use futures::StreamExt;
+use mergeable_etcd_core::auth::PermissionType;
 use mergeable_etcd_core::value::Value;
 use mergeable_etcd_core::Header;
 use mergeable_etcd_core::WatchEvent;
-use std::{collections::HashSet, pin::Pin, sync::Arc};
+use mergeable_etcd_core::WatchFilter;
+use prost::Message;
+use std::{collections::HashSet, pin::Pin, sync::Arc, time::Duration};
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 use tracing::{debug, warn};
 
+use crate::auth::request_credentials;
+use crate::auth::Tokens;
 use crate::{Doc, DocPersister};
 
+/// How often to send progress notifications to watches that asked for them, the same as etcd's
+/// default.
+const PROGRESS_NOTIFY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The watch id etcd uses for responses that aren't about a single watch, such as progress
+/// notifications for all watches on a stream or creates that failed.
+const INVALID_WATCH_ID: i64 = -1;
+
+/// Largest response to send before splitting up its events, the same as etcd's default
+/// `--max-request-bytes`.
+const MAX_RESPONSE_BYTES: usize = 1536 * 1024;
+
+/// How many events a stream can have queued for its client before its watches buffer them
+/// instead.
+const STREAM_BUFFER_SIZE: usize = 1024;
+
+/// How often to retry handing buffered events to streams that were full and to catch up watches
+/// that fell behind.
+const SLOW_WATCH_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a stream checks for its watches being cancelled for falling behind.
+const CANCELLED_WATCH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct WatchService<P, V> {
     pub(crate) watch_server: Arc<Mutex<mergeable_etcd_core::WatchServer<V>>>,
+    /// The same queue the document publishes events to, for ordering progress notifications.
+    pub(crate) watch_sender: mpsc::Sender<WatchMessage<V>>,
     pub(crate) document: Doc<P, V>,
+    pub(crate) tokens: Tokens,
 }
 
 impl<P: DocPersister, V> Clone for WatchService<P, V> {
     fn clone(&self) -> Self {
         Self {
             watch_server: self.watch_server.clone(),
+            watch_sender: self.watch_sender.clone(),
             document: self.document.clone(),
+            tokens: self.tokens.clone(),
         }
     }
 }
@@ -41,6 +78,7 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
         &self,
         request: tonic::Request<tonic::Streaming<etcd_proto::etcdserverpb::WatchRequest>>,
     ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let credentials = request_credentials(&request);
         let mut request_stream = request.into_inner();
 
         // check our node is ready
@@ -52,28 +90,60 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
         }
 
         let (tx_response, rx_response) = tokio::sync::mpsc::channel(1);
-        let (local_sender, mut local_receiver) = tokio::sync::mpsc::channel(1);
+        let (local_sender, mut local_receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER_SIZE);
+        let (progress_sender, mut progress_receiver) = mpsc::channel::<Progress>(1);
+
+        // watches on this stream that asked for large responses to be fragmented
+        let fragment_ids = Arc::new(Mutex::new(HashSet::new()));
 
         let tx_response_clone = tx_response.clone();
+        let fragment_ids_clone = Arc::clone(&fragment_ids);
         tokio::spawn(async move {
-            while let Some((watch_id, header, event)) = local_receiver.recv().await {
-                let event: WatchEvent<V> = event;
-                debug!(watch_id, typ=?event.typ, "Sending watch response");
-                let header: mergeable_etcd_core::Header = header;
-                let event: WatchEvent<V> = event;
-                let event: etcd_proto::mvccpb::Event = event.into();
-                let response = WatchResponse {
-                    header: Some(header.into()),
-                    watch_id,
-                    created: false,
-                    canceled: false,
-                    compact_revision: 0,
-                    cancel_reason: String::new(),
-                    fragment: false,
-                    events: vec![event],
+            loop {
+                let responses = tokio::select! {
+                    Some(event) = local_receiver.recv() => {
+                        // take everything already queued so that events from the same revision
+                        // can share a response
+                        let mut events = vec![event];
+                        while let Ok(event) = local_receiver.try_recv() {
+                            events.push(event);
+                        }
+                        event_responses(events)
+                    }
+                    Some((watch_ids, header, ack)) = progress_receiver.recv() => {
+                        // the progress was queued behind the events already handed to this
+                        // stream so they need to go out first
+                        let mut events = Vec::new();
+                        while let Ok(event) = local_receiver.try_recv() {
+                            events.push(event);
+                        }
+                        let _ = ack.send(());
+                        let mut responses = event_responses(events);
+                        debug!(?watch_ids, "Sending watch progress notification");
+                        responses.extend(watch_ids.into_iter().map(|watch_id| WatchResponse {
+                            header: Some(header.clone().into()),
+                            watch_id,
+                            created: false,
+                            canceled: false,
+                            compact_revision: 0,
+                            cancel_reason: String::new(),
+                            fragment: false,
+                            events: vec![],
+                        }));
+                        responses
+                    }
+                    else => break,
                 };
-                if let Err(error) = tx_response_clone.send(Ok(response)).await {
-                    warn!(%error, "Failed to send watch response");
+                for response in responses {
+                    let fragment = fragment_ids_clone.lock().await.contains(&response.watch_id);
+                    for response in split_response(response, fragment) {
+                        if let Err(error) = tx_response_clone.send(Ok(response)).await {
+                            warn!(%error, "Failed to send watch response");
+                            // the client has gone, dropping the receiver lets the watch server
+                            // know to drop its watches
+                            return;
+                        }
+                    }
                 }
             }
         });
@@ -81,14 +151,65 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
         let s = self.clone();
         tokio::spawn(async move {
             let mut ids_created_here = HashSet::new();
-            while let Some(request) = request_stream.next().await {
+            let mut progress_notify_ids = HashSet::new();
+            let mut progress_interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + PROGRESS_NOTIFY_INTERVAL,
+                PROGRESS_NOTIFY_INTERVAL,
+            );
+            let mut cancelled_interval = tokio::time::interval(CANCELLED_WATCH_CHECK_INTERVAL);
+            loop {
+                let request = tokio::select! {
+                    request = request_stream.next() => match request {
+                        Some(request) => request,
+                        None => break,
+                    },
+                    _ = progress_interval.tick() => {
+                        if !progress_notify_ids.is_empty() {
+                            s.request_progress(
+                                progress_notify_ids.iter().copied().collect(),
+                                progress_sender.clone(),
+                                local_sender.clone(),
+                                false,
+                            )
+                            .await;
+                        }
+                        continue;
+                    }
+                    _ = cancelled_interval.tick() => {
+                        let cancelled = s.watch_server.lock().await.take_cancelled(&local_sender);
+                        for (watch_id, error) in cancelled {
+                            warn!(watch_id, %error, "Watch was cancelled by the server");
+                            ids_created_here.remove(&watch_id);
+                            progress_notify_ids.remove(&watch_id);
+                            fragment_ids.lock().await.remove(&watch_id);
+                            let document = s.document.lock().await;
+                            let compact_revision =
+                                if matches!(error, mergeable_etcd_core::Error::Compacted) {
+                                    document.compact_revision() as i64
+                                } else {
+                                    0
+                                };
+                            let response = WatchResponse {
+                                header: Some(document.header().unwrap().into()),
+                                watch_id,
+                                created: false,
+                                canceled: true,
+                                compact_revision,
+                                cancel_reason: error.to_string(),
+                                fragment: false,
+                                events: vec![],
+                            };
+                            drop(document);
+                            if let Err(error) = tx_response.send(Ok(response)).await {
+                                warn!(%error, "Failed to send watch cancel response");
+                            }
+                        }
+                        continue;
+                    }
+                };
                 match request {
                     Err(error) => {
                         warn!(%error, "Got an error while handling watch request");
-                        let mut watch_server = s.watch_server.lock().await;
-                        for id in ids_created_here {
-                            watch_server.remove_watch(id);
-                        }
                         break;
                     }
                     Ok(request) => match request.request_union {
@@ -106,18 +227,19 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                                 watch_id,
                                 fragment,
                             }) => {
-                                if progress_notify {
-                                    warn!("Got progress_notify on watch create request but not currently implemented");
-                                }
-                                assert!(filters.is_empty());
-                                assert_eq!(watch_id, 0);
-                                assert!(!fragment);
+                                let filters = filters
+                                    .into_iter()
+                                    .filter_map(FilterType::from_i32)
+                                    .map(WatchFilter::from)
+                                    .collect();
+                                // etcd uses 0 to ask for an id to be picked
+                                let watch_id = if watch_id == 0 { None } else { Some(watch_id) };
 
-                                let start = String::from_utf8(key).unwrap();
+                                let start = key;
                                 let end = if range_end.is_empty() {
                                     None
                                 } else {
-                                    Some(String::from_utf8(range_end).unwrap())
+                                    Some(range_end)
                                 };
                                 let start_revision = if start_revision > 0 {
                                     Some(start_revision as u64)
@@ -126,7 +248,39 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                                 };
                                 debug!(?start, ?end, ?start_revision, "got watch create request");
                                 let mut document = s.document.lock().await;
-                                let watch_id = s
+                                let permitted =
+                                    match s.tokens.authenticate(&document, &credentials).await {
+                                        Ok(Some(user)) => document
+                                            .check_permission(
+                                                &user,
+                                                &start,
+                                                end.as_deref(),
+                                                PermissionType::Read,
+                                            )
+                                            .map_err(tonic::Status::from),
+                                        Ok(None) => Ok(()),
+                                        Err(status) => Err(status),
+                                    };
+                                if let Err(status) = permitted {
+                                    warn!(%status, "Refused to create watch");
+                                    // cancel just this watch, an error would end the stream
+                                    let response = WatchResponse {
+                                        header: Some(document.header().unwrap().into()),
+                                        watch_id: watch_id.unwrap_or(INVALID_WATCH_ID),
+                                        created: true,
+                                        canceled: true,
+                                        compact_revision: 0,
+                                        cancel_reason: status.message().to_owned(),
+                                        fragment: false,
+                                        events: vec![],
+                                    };
+                                    drop(document);
+                                    if let Err(error) = tx_response.send(Ok(response)).await {
+                                        warn!(%error, "Failed to send watch create error");
+                                    }
+                                    continue;
+                                }
+                                let watch_id = match s
                                     .watch_server
                                     .lock()
                                     .await
@@ -135,13 +289,52 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                                         start,
                                         end,
                                         prev_kv,
+                                        filters,
                                         start_revision,
+                                        watch_id,
                                         local_sender.clone(),
                                     )
                                     .await
-                    .expect("watch shouldn't be able to be created if the node isn't ready");
+                                {
+                                    Ok(watch_id) => watch_id,
+                                    Err(error) => {
+                                        warn!(%error, "Failed to create watch");
+                                        // like etcd, cancel the watch in the create response
+                                        // rather than failing every watch on the stream so
+                                        // clients can tell to relist
+                                        let compact_revision = if matches!(
+                                            error,
+                                            mergeable_etcd_core::Error::Compacted
+                                        ) {
+                                            document.compact_revision() as i64
+                                        } else {
+                                            0
+                                        };
+                                        let response = WatchResponse {
+                                            header: Some(document.header().unwrap().into()),
+                                            watch_id: watch_id.unwrap_or(INVALID_WATCH_ID),
+                                            created: true,
+                                            canceled: true,
+                                            compact_revision,
+                                            cancel_reason: error.to_string(),
+                                            fragment: false,
+                                            events: vec![],
+                                        };
+                                        drop(document);
+                                        if let Err(error) = tx_response.send(Ok(response)).await {
+                                            warn!(%error, "Failed to send watch create error");
+                                        }
+                                        continue;
+                                    }
+                                };
 
                                 ids_created_here.insert(watch_id);
+                                if fragment {
+                                    fragment_ids.lock().await.insert(watch_id);
+                                }
+                                if progress_notify {
+                                    progress_notify_ids.insert(watch_id);
+                                }
                                 let header = document.header().unwrap().into();
                                 let response = WatchResponse {
                                     header: Some(header),
@@ -153,6 +346,8 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                                     fragment: false,
                                     events: vec![],
                                 };
+                                // don't hold up the document while the client is slow to read
+                                drop(document);
                                 debug!(?watch_id, "Sent watch create response");
                                 if let Err(error) = tx_response.send(Ok(response)).await {
                                     warn!(%error, "Failed to send watch create response");
@@ -160,7 +355,12 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                             }
                             RequestUnion::CancelRequest(WatchCancelRequest { watch_id }) => {
                                 debug!(watch_id, "got watch cancel request");
-                                s.watch_server.lock().await.remove_watch(watch_id);
+                                s.watch_server
+                                    .lock()
+                                    .await
+                                    .remove_watch(watch_id, &local_sender);
+                                progress_notify_ids.remove(&watch_id);
+                                fragment_ids.lock().await.remove(&watch_id);
                                 if !ids_created_here.remove(&watch_id) {
                                     warn!(
                                         ?watch_id,
@@ -183,13 +383,24 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
                                     warn!(%error, "Error sending watch cancel response");
                                 }
                             }
-                            RequestUnion::ProgressRequest(progress) => {
-                                warn!(?progress, "got watch progress request")
+                            RequestUnion::ProgressRequest(_) => {
+                                debug!("got watch progress request");
+                                s.request_progress(
+                                    vec![INVALID_WATCH_ID],
+                                    progress_sender.clone(),
+                                    local_sender.clone(),
+                                    true,
+                                )
+                                .await;
                             }
                         },
                     },
                 }
             }
+            let mut watch_server = s.watch_server.lock().await;
+            for id in ids_created_here {
+                watch_server.remove_watch(id, &local_sender);
+            }
         });
 
         Ok(tonic::Response::new(Box::pin(
@@ -198,22 +409,250 @@ impl<P: DocPersister, V: Value> Watch for WatchService<P, V> {
     }
 }
 
+impl<P: DocPersister, V: Value> WatchService<P, V> {
+    /// Queue a progress notification at the current header for the given watches.
+    async fn request_progress(
+        &self,
+        watch_ids: Vec<i64>,
+        sender: mpsc::Sender<Progress>,
+        stream: mpsc::Sender<(i64, Header, WatchEvent<V>)>,
+        explicit: bool,
+    ) {
+        let document = self.document.lock().await;
+        let header = document.header().unwrap();
+        // queue while holding the document so that every event up to this header is ahead of it
+        let message = WatchMessage::Progress {
+            watch_ids,
+            header,
+            sender,
+            stream,
+            explicit,
+        };
+        if let Err(error) = self.watch_sender.send(message).await {
+            warn!(%error, "Failed to queue watch progress notification");
+        }
+    }
+}
+
+/// Turn the events into responses, with consecutive events for the same watch and header sharing
+/// one.
+fn event_responses<V: Value>(events: Vec<(i64, Header, WatchEvent<V>)>) -> Vec<WatchResponse> {
+    let mut responses: Vec<WatchResponse> = Vec::new();
+    for (watch_id, header, event) in events {
+        debug!(watch_id, typ=?event.typ, "Sending watch response");
+        let header: etcd_proto::etcdserverpb::ResponseHeader = header.into();
+        let event: etcd_proto::mvccpb::Event = event.into();
+        match responses.last_mut() {
+            Some(last) if last.watch_id == watch_id && last.header.as_ref() == Some(&header) => {
+                last.events.push(event);
+            }
+            _ => responses.push(WatchResponse {
+                header: Some(header),
+                watch_id,
+                created: false,
+                canceled: false,
+                compact_revision: 0,
+                cancel_reason: String::new(),
+                fragment: false,
+                events: vec![event],
+            }),
+        }
+    }
+    responses
+}
+
+/// Split a response that is too big to send into several, marking all but the last as fragments
+/// if the watch asked for them.
+fn split_response(mut response: WatchResponse, fragment: bool) -> Vec<WatchResponse> {
+    if response.events.len() <= 1 || response.encoded_len() <= MAX_RESPONSE_BYTES {
+        return vec![response];
+    }
+
+    let mut responses = Vec::new();
+    let mut events = Vec::new();
+    let mut size = 0;
+    for event in std::mem::take(&mut response.events) {
+        let event_size = event.encoded_len();
+        if !events.is_empty() && size + event_size > MAX_RESPONSE_BYTES {
+            responses.push(WatchResponse {
+                fragment,
+                events: std::mem::take(&mut events),
+                ..response.clone()
+            });
+            size = 0;
+        }
+        size += event_size;
+        events.push(event);
+    }
+    responses.push(WatchResponse { events, ..response });
+    responses
+}
+
+/// A progress notification for some watches on a stream, acknowledged once the stream has taken
+/// the events queued before it so that no later events get mixed in ahead of it.
+type Progress = (Vec<i64>, Header, oneshot::Sender<()>);
+
+/// Messages queued from the document for the watch server.
+pub enum WatchMessage<V> {
+    Event(Header, WatchEvent<V>),
+    /// Send a progress notification to a stream after all of the events queued before it.
+    Progress {
+        watch_ids: Vec<i64>,
+        header: Header,
+        sender: mpsc::Sender<Progress>,
+        /// The sender the stream's watches send their events with.
+        stream: mpsc::Sender<(i64, Header, WatchEvent<V>)>,
+        /// Whether the client asked for it, rather than it being periodic, so it can't be skipped.
+        explicit: bool,
+    },
+}
+
+/// A progress notification waiting to be handed to its stream.
+struct PendingProgress<V> {
+    watch_ids: Vec<i64>,
+    header: Header,
+    sender: mpsc::Sender<Progress>,
+    stream: mpsc::Sender<(i64, Header, WatchEvent<V>)>,
+}
+
+impl<V: Value> PendingProgress<V> {
+    /// Hand the notification to its stream, holding back newer events until the stream has taken
+    /// it, or give it back if the stream isn't ready for it.
+    fn try_send(
+        self,
+        watch_server: &mut mergeable_etcd_core::WatchServer<V>,
+    ) -> Result<oneshot::Receiver<()>, Self> {
+        // like etcd, watches still working through older events don't get told about progress
+        // they haven't seen yet
+        if !watch_server.is_synced(&self.stream) {
+            return Err(self);
+        }
+        let (ack_sender, ack_receiver) = oneshot::channel();
+        match self
+            .sender
+            .try_send((self.watch_ids, self.header, ack_sender))
+        {
+            Ok(()) => {
+                watch_server.pause(&self.stream);
+                Ok(ack_receiver)
+            }
+            Err(error) => {
+                let (watch_ids, header, _) = error.into_inner();
+                Err(Self {
+                    watch_ids,
+                    header,
+                    ..self
+                })
+            }
+        }
+    }
+}
+
+/// Wait for a stream to take its progress notification, giving back the stream to resume.
+async fn wait_for_ack<V>(
+    stream: mpsc::Sender<(i64, Header, WatchEvent<V>)>,
+    ack: oneshot::Receiver<()>,
+) -> mpsc::Sender<(i64, Header, WatchEvent<V>)> {
+    let _ = ack.await;
+    stream
+}
+
+/// Try to send the deferred progress notifications again, keeping those still waiting and
+/// returning the streams that took one along with their acknowledgements.
+#[allow(clippy::type_complexity)]
+fn retry_progress<V: Value>(
+    deferred: &mut Vec<PendingProgress<V>>,
+    watch_server: &mut mergeable_etcd_core::WatchServer<V>,
+) -> Vec<(
+    mpsc::Sender<(i64, Header, WatchEvent<V>)>,
+    oneshot::Receiver<()>,
+)> {
+    let mut sent = Vec::new();
+    for progress in std::mem::take(deferred) {
+        // the stream has gone away so nobody is waiting on it
+        if progress.sender.is_closed() {
+            continue;
+        }
+        let stream = progress.stream.clone();
+        match progress.try_send(watch_server) {
+            Ok(ack) => sent.push((stream, ack)),
+            Err(progress) => deferred.push(progress),
+        }
+    }
+    sent
+}
+
 pub struct MyWatcher<V> {
-    pub(crate) sender: mpsc::Sender<(Header, WatchEvent<V>)>,
+    pub(crate) sender: mpsc::Sender<WatchMessage<V>>,
 }
 
 #[tonic::async_trait]
 impl<V: Value> mergeable_etcd_core::Watcher<V> for MyWatcher<V> {
     async fn publish_event(&mut self, header: Header, event: WatchEvent<V>) {
-        self.sender.send((header, event)).await.unwrap()
+        self.sender
+            .send(WatchMessage::Event(header, event))
+            .await
+            .unwrap()
     }
 }
 
-pub async fn propagate_watches<V: Value>(
-    mut receiver: mpsc::Receiver<(Header, WatchEvent<V>)>,
+/// Hand the events from the document to the watches, without waiting on slow streams so that they
+/// can't hold up the document.
+pub async fn propagate_watches<P: DocPersister, V: Value>(
+    mut receiver: mpsc::Receiver<WatchMessage<V>>,
     watch_server: Arc<Mutex<mergeable_etcd_core::WatchServer<V>>>,
+    document: Doc<P, V>,
 ) {
-    while let Some((header, event)) = receiver.recv().await {
-        watch_server.lock().await.receive_event(header, event).await;
+    // streams holding back events until their progress notification has been taken
+    let mut paused = FuturesUnordered::new();
+    // progress notifications clients asked for, waiting on their streams to catch up
+    let mut deferred: Vec<PendingProgress<V>> = Vec::new();
+    let mut retry_interval = tokio::time::interval(SLOW_WATCH_RETRY_INTERVAL);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(WatchMessage::Event(header, event)) => {
+                    watch_server.lock().await.receive_event(header, event).await;
+                }
+                Some(WatchMessage::Progress {
+                    watch_ids,
+                    header,
+                    sender,
+                    stream,
+                    explicit,
+                }) => {
+                    let progress = PendingProgress {
+                        watch_ids,
+                        header,
+                        sender,
+                        stream: stream.clone(),
+                    };
+                    match progress.try_send(&mut *watch_server.lock().await) {
+                        Ok(ack) => paused.push(wait_for_ack(stream, ack)),
+                        // the client is waiting on it so send it once the stream catches up
+                        Err(progress) if explicit => deferred.push(progress),
+                        Err(progress) => {
+                            debug!(watch_ids=?progress.watch_ids, "Skipping progress notification");
+                        }
+                    }
+                }
+                None => break,
+            },
+            Some(stream) = paused.next() => {
+                watch_server.lock().await.resume(&stream);
+            }
+            _ = retry_interval.tick() => {
+                let mut watch_server = watch_server.lock().await;
+                watch_server.flush();
+                // the document may be waiting on this task to take its events so don't wait for
+                // it, the watches can catch up on the next try
+                if let Ok(document) = document.try_lock() {
+                    watch_server.catch_up(&document);
+                }
+                for (stream, ack) in retry_progress(&mut deferred, &mut watch_server) {
+                    paused.push(wait_for_ack(stream, ack));
+                }
+            }
+        }
     }
 }
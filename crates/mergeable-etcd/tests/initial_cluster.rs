@@ -11,12 +11,19 @@ use std::{
 };
 use tempdir::TempDir;
 use test_log::test;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Certificate;
 use tonic::transport::Channel;
 use tonic::transport::ClientTlsConfig;
 use tracing::info;
 
-use etcd_proto::etcdserverpb::{MemberAddRequest, PutRequest, RangeRequest, RangeResponse};
+use etcd_proto::etcdserverpb::watch_client::WatchClient;
+use etcd_proto::etcdserverpb::watch_request::RequestUnion;
+use etcd_proto::etcdserverpb::{
+    MemberAddRequest, PutRequest, RangeRequest, RangeResponse, WatchCreateRequest,
+    WatchProgressRequest, WatchRequest,
+};
 
 static BASE_PORT: AtomicU32 = AtomicU32::new(3379);
 const CERT_FILE: &str = "../../certs/server.crt";
@@ -336,6 +343,278 @@ async fn initial_cluster_double() {
     assert_eq!(response1, response2);
 }
 
+#[test(tokio::test)]
+async fn watch_progress_while_buffered() {
+    let data_dir = tempdir::TempDir::new("").unwrap();
+    let (client, peer, metrics) = get_addresses_single();
+    let node_opts = mergeable_etcd::Options {
+        name: "node1".to_owned(),
+        data_dir: Some(data_dir.path().to_owned()),
+        advertise_client_urls: vec![client.clone()],
+        initial_advertise_peer_urls: vec![peer.clone()],
+        initial_cluster: format!("node1={peer}"),
+        listen_client_urls: vec![client.clone()],
+        listen_metrics_urls: vec![metrics.clone()],
+        listen_peer_urls: vec![peer.clone()],
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        mergeable_etcd::run::<Bytes>(node_opts).await;
+    });
+
+    poll_ready(&metrics).await;
+
+    // on its own channel so that not reading it doesn't hold up the puts
+    let channel = get_channel(None, client.parse().unwrap()).await;
+    let mut watch_client = WatchClient::new(channel);
+    let (request_sender, request_receiver) = tokio::sync::mpsc::channel(1);
+    request_sender
+        .send(WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: b"key".to_vec(),
+                range_end: b"kez".to_vec(),
+                ..Default::default()
+            })),
+        })
+        .await
+        .unwrap();
+    let mut responses = watch_client
+        .watch(ReceiverStream::new(request_receiver))
+        .await
+        .unwrap()
+        .into_inner();
+    let created = responses.message().await.unwrap().unwrap();
+    assert!(created.created);
+
+    // more events than the stream holds without being read, so the watch has to buffer some
+    let puts = 2000;
+    let mut kv_client = get_kv_client(&client).await;
+    let mut last_put = None;
+    for i in 0..puts {
+        let response = kv_client
+            .put(PutRequest {
+                key: format!("key{i}").into_bytes(),
+                value: vec![0; 1024],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        last_put = response.header;
+    }
+    request_sender
+        .send(WatchRequest {
+            request_union: Some(RequestUnion::ProgressRequest(WatchProgressRequest {})),
+        })
+        .await
+        .unwrap();
+
+    let mut events = 0;
+    let progress = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let response = responses.message().await.unwrap().unwrap();
+            if response.events.is_empty() {
+                break response;
+            }
+            events += response.events.len();
+        }
+    })
+    .await
+    .expect("progress notification was dropped");
+    // every event comes before the progress notification
+    assert_eq!(events, puts);
+    assert_eq!(progress.watch_id, -1);
+    assert!(progress.header.unwrap().revision >= last_put.unwrap().revision);
+}
+
+#[test(tokio::test)]
+async fn initial_cluster_double_compressed() {
+    let (client1, peer1, metrics1) = get_addresses_single();
+    let (client2, peer2, metrics2) = get_addresses_single();
+    let mut data_dirs = Vec::new();
+    for (name, client, peer, metrics) in [
+        ("node1", &client1, &peer1, &metrics1),
+        ("node2", &client2, &peer2, &metrics2),
+    ] {
+        let data_dir = tempdir::TempDir::new("").unwrap();
+        let node_opts = mergeable_etcd::Options {
+            name: name.to_owned(),
+            data_dir: Some(data_dir.path().to_owned()),
+            advertise_client_urls: vec![client.clone()],
+            initial_advertise_peer_urls: vec![peer.clone()],
+            initial_cluster: format!("node1={peer1},node2={peer2}"),
+            listen_client_urls: vec![client.clone()],
+            listen_metrics_urls: vec![metrics.clone()],
+            listen_peer_urls: vec![peer.clone()],
+            peer_compression: true,
+            ..Default::default()
+        };
+        data_dirs.push(data_dir);
+        tokio::spawn(async move {
+            mergeable_etcd::run::<Bytes>(node_opts).await;
+        });
+
+        poll_ready(metrics).await;
+    }
+
+    let mut kv_client1 = get_kv_client(&client1).await;
+    let mut kv_client2 = get_kv_client(&client2).await;
+    // something that compresses well
+    let value = vec![1; 64 * 1024];
+    kv_client1
+        .put(PutRequest {
+            key: b"key1".to_vec(),
+            value: value.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // give it a chance to sync
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = kv_client2
+        .range(RangeRequest {
+            key: b"key1".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.kvs.len(), 1);
+    assert_eq!(response.kvs[0].value, value);
+
+    let metrics = reqwest::get(&format!("{}/metrics", metrics1))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let compressed = metric_total(&metrics, "peer_sent_compressed_bytes_total");
+    let uncompressed = metric_total(&metrics, "peer_sent_uncompressed_bytes_total");
+    assert!(compressed > 0);
+    assert!(
+        compressed < uncompressed,
+        "compressed {compressed} should be less than uncompressed {uncompressed}"
+    );
+}
+
+/// Sum a counter across all of its labels in a metrics scrape.
+fn metric_total(metrics: &str, name: &str) -> u64 {
+    metrics
+        .lines()
+        .filter(|line| {
+            line.starts_with(&format!("{name}{{")) || line.starts_with(&format!("{name} "))
+        })
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+        .sum()
+}
+
+/// Forward connections on to a peer server until aborted, which cuts off every connection through
+/// it as if the server behind it had restarted.
+fn forward_peer(listener: TcpListener, target: String) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        // dropped along with the task, closing the connections
+        let mut connections = tokio::task::JoinSet::new();
+        loop {
+            let (mut inbound, _) = listener.accept().await.unwrap();
+            let target = target.clone();
+            connections.spawn(async move {
+                if let Ok(mut outbound) = TcpStream::connect(target).await {
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                }
+            });
+        }
+    })
+}
+
+#[test(tokio::test)]
+async fn peer_restart_mid_stream() {
+    let (client1, peer1, metrics1) = get_addresses_single();
+    let (client2, peer2, metrics2) = get_addresses_single();
+    // node1 reaches node2 through this so that its sync stream can be cut off part way through
+    let (_, proxy2, _) = get_addresses_single();
+    let proxy_address = proxy2.trim_start_matches("http://").to_owned();
+    let peer2_address = peer2.trim_start_matches("http://").to_owned();
+    let mut proxy = forward_peer(
+        TcpListener::bind(&proxy_address).await.unwrap(),
+        peer2_address.clone(),
+    );
+
+    let initial_cluster = format!("node1={peer1},node2={proxy2}");
+    let data_dir1 = tempdir::TempDir::new("").unwrap();
+    let data_dir2 = tempdir::TempDir::new("").unwrap();
+    let nodes = [
+        ("node1", &data_dir1, &client1, &peer1, &peer1, &metrics1),
+        ("node2", &data_dir2, &client2, &proxy2, &peer2, &metrics2),
+    ];
+    for (name, data_dir, client, advertise_peer, listen_peer, metrics) in nodes {
+        let node_opts = mergeable_etcd::Options {
+            name: name.to_owned(),
+            data_dir: Some(data_dir.path().to_owned()),
+            advertise_client_urls: vec![client.clone()],
+            initial_advertise_peer_urls: vec![advertise_peer.clone()],
+            initial_cluster: initial_cluster.clone(),
+            listen_client_urls: vec![client.clone()],
+            listen_metrics_urls: vec![metrics.clone()],
+            listen_peer_urls: vec![listen_peer.clone()],
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            mergeable_etcd::run::<Bytes>(node_opts).await;
+        });
+
+        poll_ready(metrics).await;
+    }
+
+    let mut kv_client1 = get_kv_client(&client1).await;
+    let mut kv_client2 = get_kv_client(&client2).await;
+    let keys = 100;
+    for i in 0..keys {
+        if i == keys / 2 {
+            // node2's peer server goes away while node1 is streaming changes to it, losing
+            // whatever was in flight, then comes back
+            proxy.abort();
+            let _ = proxy.await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            proxy = forward_peer(
+                TcpListener::bind(&proxy_address).await.unwrap(),
+                peer2_address.clone(),
+            );
+        }
+        kv_client1
+            .put(PutRequest {
+                key: format!("key{i}").into_bytes(),
+                value: vec![i as u8],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    // node1 reopens its stream and resumes syncing, so node2 still gets everything
+    let mut count = 0;
+    for _ in 0..100 {
+        count = kv_client2
+            .range(RangeRequest {
+                key: b"key".to_vec(),
+                range_end: b"kez".to_vec(),
+                count_only: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .count;
+        if count == keys {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(count, keys);
+    proxy.abort();
+}
+
 #[test(tokio::test)]
 #[ignore]
 async fn double_cluster_explicit_add() {